@@ -0,0 +1,12 @@
+//! Decodes a delta chunk in a browser. Build with
+//! `cargo build --example wasm_decode --target wasm32-unknown-unknown --no-default-features`.
+
+#[cfg(target_arch = "wasm32")]
+use wasm_bindgen::prelude::*;
+
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen]
+pub fn decode_delta_chunk(parent_data: &[u8], delta_chunk: &[u8]) -> Result<Vec<u8>, JsError> {
+    sbc_algorithm::decode_delta(parent_data, delta_chunk)
+        .map_err(|error| JsError::new(&error.to_string()))
+}
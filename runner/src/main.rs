@@ -5,7 +5,9 @@ extern crate sbc_algorithm;
 use chunkfs::chunkers::{FSChunker, RabinChunker, SizeParams, SuperChunker};
 use chunkfs::hashers::Sha256Hasher;
 use chunkfs::FileSystem;
-use sbc_algorithm::{SBCMap, SBCScrubber};
+use sbc_algorithm::{
+    compress_chunks, run_experiment, Codec, GraphFormat, ReportFormat, SBCMap, SBCScrubber,
+};
 use std::collections::HashMap;
 use std::io;
 
@@ -18,7 +20,75 @@ fn generate_data(mb_size: usize) -> Vec<u8> {
     (0..bytes).map(|_| rand::random::<u8>()).collect()
 }
 
+/// `runner graph <file> [dot|json]`: scrubs fixed-size chunks of the file and
+/// prints the resulting clusters.
+fn write_graph(path: &str, format: GraphFormat) -> io::Result<()> {
+    let data = std::fs::read(path)?;
+    let chunks = data.chunks(8192).map(<[u8]>::to_vec);
+    let (map, _) = compress_chunks(chunks, &mut SBCScrubber::new())?;
+    map.write_cluster_graph(&mut io::stdout().lock(), format)?;
+    Ok(())
+}
+
+/// `runner experiment <file> [json|csv]`: scrubs fixed-size chunks of the file
+/// and prints a report of the run for benchmark scripts.
+fn write_experiment(path: &str, format: ReportFormat) -> io::Result<()> {
+    let data = std::fs::read(path)?;
+    let chunks = data.chunks(8192).map(<[u8]>::to_vec);
+    let report = run_experiment(chunks, &mut SBCScrubber::new())?;
+    report.write_to(&mut io::stdout().lock(), format)?;
+    Ok(())
+}
+
+/// `runner --list-codecs`: prints the delta codecs and their capabilities.
+fn list_codecs() {
+    for codec in Codec::ALL {
+        let info = codec.info();
+        let max_chunk_len = info
+            .max_chunk_len
+            .map_or("unlimited".to_string(), |len| format!("{len} B"));
+        println!(
+            "{}: max chunk {max_chunk_len}, self-reference: {}, entropy coded: {}, speed: {:?}{}",
+            info.name,
+            info.self_reference,
+            info.entropy_coded,
+            info.speed,
+            if info.available {
+                ""
+            } else {
+                " (not compiled in)"
+            }
+        );
+    }
+}
+
 fn main() -> io::Result<()> {
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("--list-codecs") {
+        list_codecs();
+        return Ok(());
+    }
+    if args.get(1).map(String::as_str) == Some("graph") {
+        let format = match args.get(3).map(String::as_str) {
+            Some("json") => GraphFormat::Json,
+            _ => GraphFormat::Dot,
+        };
+        return write_graph(
+            args.get(2).map_or("runner/files/my_data", String::as_str),
+            format,
+        );
+    }
+    if args.get(1).map(String::as_str) == Some("experiment") {
+        let format = match args.get(3).map(String::as_str) {
+            Some("csv") => ReportFormat::Csv,
+            _ => ReportFormat::Json,
+        };
+        return write_experiment(
+            args.get(2).map_or("runner/files/my_data", String::as_str),
+            format,
+        );
+    }
+
     let mut fs = FileSystem::new_with_scrubber(
         HashMap::default(),
         SBCMap::new(),
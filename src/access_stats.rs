@@ -0,0 +1,162 @@
+use crate::chunkfs_sbc::decode_chunk;
+use crate::{parent_ref, ChunkType, Result, SBCHash, SBCMap};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+#[derive(Default)]
+pub(crate) struct AccessStats {
+    read_counts: Mutex<HashMap<SBCHash, u64>>,
+    materialized: HashMap<SBCHash, Vec<u8>>,
+    /// Materialized chunks by the parent they were decoded from.
+    materialized_children: HashMap<SBCHash, Vec<SBCHash>>,
+}
+
+impl AccessStats {
+    /// Counts a read of `sbc_hash` and returns its data if the chunk is materialized.
+    pub fn record_read(&self, sbc_hash: &SBCHash) -> Option<Vec<u8>> {
+        *self
+            .read_counts
+            .lock()
+            .unwrap()
+            .entry(sbc_hash.clone())
+            .or_insert(0) += 1;
+        self.materialized.get(sbc_hash).cloned()
+    }
+
+    /// Drops the decoded copies of `sbc_hash` and of the chunks decoded from
+    /// it, called whenever the value stored under `sbc_hash` changes.
+    pub fn forget(&mut self, sbc_hash: &SBCHash) {
+        self.materialized.remove(sbc_hash);
+        for child in self
+            .materialized_children
+            .remove(sbc_hash)
+            .unwrap_or_default()
+        {
+            self.materialized.remove(&child);
+        }
+    }
+}
+
+impl SBCMap {
+    pub fn read_count(&self, sbc_hash: &SBCHash) -> u64 {
+        let read_counts = self.access_stats.read_counts.lock().unwrap();
+        read_counts.get(sbc_hash).copied().unwrap_or(0)
+    }
+
+    pub fn read_counts(&self) -> HashMap<SBCHash, u64> {
+        self.access_stats.read_counts.lock().unwrap().clone()
+    }
+
+    /// Keeps decoded copies of delta chunks read at least `threshold` times, so
+    /// further reads of them skip delta decoding. Returns the number of such chunks.
+    /// The copies are kept in memory only, outside of the quota and of saved maps,
+    /// and are dropped once the chunk or its parent is replaced or removed.
+    pub fn optimize_for_reads(&mut self, threshold: u64) -> Result<usize> {
+        let frequent_chunks: Vec<(SBCHash, SBCHash)> = self
+            .read_counts()
+            .into_iter()
+            .filter(|(sbc_hash, read_count)| {
+                *read_count >= threshold
+                    && matches!(sbc_hash.chunk_type, ChunkType::Delta(_))
+                    && !self.access_stats.materialized.contains_key(sbc_hash)
+            })
+            .filter_map(|(sbc_hash, _)| {
+                let (parent_hash, _) = parent_ref::split(self.stored_value(&sbc_hash)?)?;
+                Some((sbc_hash, parent_hash))
+            })
+            .collect();
+        for (sbc_hash, parent_hash) in &frequent_chunks {
+            let data = decode_chunk(
+                sbc_hash,
+                |sbc_hash| self.stored_value(sbc_hash),
+                &self.preprocessing,
+            )?;
+            self.access_stats
+                .materialized
+                .insert(sbc_hash.clone(), data);
+            self.access_stats
+                .materialized_children
+                .entry(parent_hash.clone())
+                .or_default()
+                .push(sbc_hash.clone());
+        }
+        Ok(frequent_chunks.len())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{encode_delta, ChunkType, SBCHash, SBCMap};
+    use chunkfs::Database;
+
+    #[test]
+    fn test_frequently_read_chunks_are_materialized() {
+        let data: Vec<u8> = (0..8192).map(|_| rand::random::<u8>()).collect();
+        let mut similar_data = data.clone();
+        similar_data[1000] = similar_data[1000].wrapping_add(1);
+        let delta_hash = SBCHash {
+            key: 1,
+            chunk_type: ChunkType::Delta(0),
+        };
+        let mut map = SBCMap::new();
+        map.insert(SBCHash::default(), data.clone()).unwrap();
+        map.insert(
+            delta_hash.clone(),
+            encode_delta(&similar_data, &data, 0).unwrap(),
+        )
+        .unwrap();
+
+        for _ in 0..3 {
+            assert!(map.get(&delta_hash).is_ok());
+        }
+        assert_eq!(map.read_count(&delta_hash), 3);
+        assert_eq!(map.optimize_for_reads(4).unwrap(), 0);
+        assert_eq!(map.optimize_for_reads(3).unwrap(), 1);
+
+        assert_eq!(map.get(&delta_hash).unwrap(), similar_data);
+        assert_eq!(map.read_count(&delta_hash), 4);
+
+        let rolled_back_hash = SBCHash {
+            key: 2,
+            chunk_type: ChunkType::Delta(0),
+        };
+        let snapshot = map.snapshot();
+        map.insert(
+            rolled_back_hash.clone(),
+            encode_delta(&similar_data, &data, 0).unwrap(),
+        )
+        .unwrap();
+        map.get(&rolled_back_hash).unwrap();
+        assert_eq!(map.optimize_for_reads(1).unwrap(), 1);
+        map.rollback(snapshot).unwrap();
+        assert!(!map.contains(&rolled_back_hash));
+        assert!(map.get(&rolled_back_hash).is_err());
+    }
+
+    #[test]
+    fn test_replacing_parent_drops_materialized_chunks() {
+        let data: Vec<u8> = (0..8192).map(|_| rand::random::<u8>()).collect();
+        let mut similar_data = data.clone();
+        similar_data[1000] = similar_data[1000].wrapping_add(1);
+        let delta_hash = SBCHash {
+            key: 1,
+            chunk_type: ChunkType::Delta(0),
+        };
+        let mut map = SBCMap::new();
+        map.insert(SBCHash::default(), data.clone()).unwrap();
+        map.insert(
+            delta_hash.clone(),
+            encode_delta(&similar_data, &data, 0).unwrap(),
+        )
+        .unwrap();
+        map.get(&delta_hash).unwrap();
+        assert_eq!(map.optimize_for_reads(1).unwrap(), 1);
+
+        let mut new_data = data.clone();
+        new_data[2000] = new_data[2000].wrapping_add(1);
+        map.insert(SBCHash::default(), new_data.clone()).unwrap();
+        let mut expected = new_data;
+        expected[1000] = expected[1000].wrapping_add(1);
+        assert_eq!(map.get(&delta_hash).unwrap(), expected);
+    }
+}
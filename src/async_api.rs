@@ -0,0 +1,104 @@
+use crate::clusterer::{self, ChunkContainer, EncodeStatistics};
+use crate::{Result, SBCHash, SBCMap, SBCScrubber};
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+/// Future which is pending once, letting the executor run other tasks. Works
+/// with any executor, as it wakes its task right away.
+struct YieldNow(bool);
+
+impl Future for YieldNow {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.0 {
+            return Poll::Ready(());
+        }
+        self.0 = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
+
+fn yield_now() -> YieldNow {
+    YieldNow(false)
+}
+
+impl SBCScrubber {
+    /// Same as `scrub_chunks`, yielding to the executor after every encoded cluster.
+    pub(crate) async fn scrub_chunks_async<'a, C: ChunkContainer + 'a>(
+        &mut self,
+        chunks: impl Iterator<Item = &'a mut C>,
+        target_map: &mut SBCMap,
+        time_start: Instant,
+    ) -> Result<EncodeStatistics> {
+        let mut chunks = self.preprocess_chunks(chunks, target_map)?;
+        let (mut clusters, hinted_clusters) = self.cluster_chunks(&mut chunks, time_start);
+        clusterer::sort_clusters(&mut clusters);
+        let mut encoder = self.cluster_encoder(time_start);
+        for (parent_hash, mut cluster) in hinted_clusters {
+            encoder.encode_against(&mut cluster, Some(&parent_hash), target_map)?;
+            yield_now().await;
+        }
+        for (key, cluster) in clusters.iter_mut() {
+            encoder.encode(*key, cluster, target_map)?;
+            yield_now().await;
+        }
+        let statistics = encoder.finish();
+        Ok(self.finish_scrub(statistics))
+    }
+}
+
+impl SBCMap {
+    /// Decodes the chunks `keys`, yielding to the executor after every chunk.
+    pub async fn get_many_async(&self, keys: &[SBCHash]) -> Result<Vec<Vec<u8>>> {
+        let mut chunks = Vec::with_capacity(keys.len());
+        for sbc_hash in keys {
+            chunks.push(self.decode(sbc_hash)?);
+            yield_now().await;
+        }
+        Ok(chunks)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::compress_chunks_async;
+    use std::task::Waker;
+
+    /// Polls `future` to completion, returning its output and how often it yielded.
+    fn run<F: Future>(future: F) -> (F::Output, usize) {
+        let mut future = std::pin::pin!(future);
+        let mut context = Context::from_waker(Waker::noop());
+        let mut yields = 0;
+        loop {
+            match future.as_mut().poll(&mut context) {
+                Poll::Ready(output) => return (output, yields),
+                Poll::Pending => yields += 1,
+            }
+        }
+    }
+
+    #[test]
+    fn test_async_compress_and_get() {
+        let data: Vec<u8> = (0..8192).map(|_| rand::random::<u8>()).collect();
+        let mut similar_data = data.clone();
+        similar_data[100] ^= 1;
+        let other_data: Vec<u8> = (0..8192).map(|_| rand::random::<u8>()).collect();
+        let chunks = vec![data, similar_data, other_data];
+
+        let (result, yields) = run(compress_chunks_async(
+            chunks.clone(),
+            &mut SBCScrubber::new(),
+        ));
+        let (map, manifest) = result.unwrap();
+        assert!(yields >= 1);
+
+        let (restored, yields) = run(map.get_many_async(manifest.keys()));
+        assert_eq!(restored.unwrap(), chunks);
+        assert_eq!(yields, chunks.len());
+    }
+}
@@ -1,84 +1,918 @@
-use crate::graph::Graph;
-use crate::levenshtein_functions::{
-    get_delta_action,
-    Action::{Add, Del, Rep},
+use crate::clusterer::{
+    ChunkContainer, Cluster, ClusterEncoder, ClusterStatistics, CollisionCounter, EncodeSettings,
+    EncodeStatistics, HashCollisions, HintedCluster, KeyedCluster, ScrubBudget, SizeBucket,
 };
-use crate::{clusterer, hash_functions, ChunkType, SBCHash, SBCMap};
-use chunkfs::{
-    ChunkHash, Data, DataContainer, Database, IterableDatabase, Scrub, ScrubMeasurements,
+use crate::graph::Graph;
+use crate::levenshtein_functions::{decode_delta, is_levenshtein_delta};
+use crate::persistence::Checkpoint;
+use crate::preprocessing::{PreprocessedChunk, Preprocessing};
+use crate::{
+    blake2b_content_hash, clusterer, entropy, hash_functions, ChunkType, ContentHasher,
+    GearFeatures, LengthAwareHasher, PrecomputedClusterer, Result, Route, RoutingTable, SBCHash,
+    SBCMap, SbcError, SimilarityFilter,
 };
+use crate::{parent_digest, parent_ref, zstd_ref};
+use chunkfs::{ChunkHash, DataContainer, Database, IterableDatabase, Scrub, ScrubMeasurements};
+use std::borrow::Cow;
+use std::cmp::min;
 use std::collections::HashMap;
 use std::io;
-use std::time::Instant;
+use std::mem;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const HASHING_BATCH_LEN: usize = 16;
+/// Batches waiting between two stages of a pipelined scrub.
+const PIPELINE_QUEUE_LEN: usize = 2;
 
 impl Database<SBCHash, Vec<u8>> for SBCMap {
     fn insert(&mut self, sbc_hash: SBCHash, chunk: Vec<u8>) -> io::Result<()> {
-        self.sbc_hashmap.insert(sbc_hash, chunk);
-        Ok(())
+        self.account(&sbc_hash, &chunk)?;
+        self.record_insert(&sbc_hash);
+        self.store_value(sbc_hash, chunk)
     }
 
     fn get(&self, sbc_hash: &SBCHash) -> io::Result<Vec<u8>> {
-        let sbc_value = match self.sbc_hashmap.get(sbc_hash) {
-            None => {
-                panic!("{}, {:?}", sbc_hash.key, sbc_hash.chunk_type)
+        Ok(self.decode(sbc_hash)?)
+    }
+
+    // fn remove(&mut self, sbc_hash: &SBCHash) {
+    //     self.sbc_hashmap.remove(sbc_hash);
+    // }
+
+    fn contains(&self, key: &SBCHash) -> bool {
+        #[cfg(feature = "zstd")]
+        if self.solid_clusters.contains_key(key) {
+            return true;
+        }
+        self.stored_value(key).is_some()
+    }
+}
+
+impl SBCMap {
+    pub(crate) fn decode(&self, sbc_hash: &SBCHash) -> Result<Vec<u8>> {
+        #[cfg(feature = "access-stats")]
+        if let Some(data) = self.access_stats.record_read(sbc_hash) {
+            return Ok(data);
+        }
+        #[cfg(feature = "zstd")]
+        if let Some(cluster) = self.solid_clusters.get(sbc_hash) {
+            return cluster.get(sbc_hash);
+        }
+        decode_chunk(
+            sbc_hash,
+            |sbc_hash| self.stored_value(sbc_hash),
+            &self.preprocessing,
+        )
+    }
+}
+
+pub(crate) fn decode_chunk<'a>(
+    sbc_hash: &SBCHash,
+    stored_value: impl Fn(&SBCHash) -> Option<&'a [u8]>,
+    preprocessing: &HashMap<SBCHash, Preprocessing>,
+) -> Result<Vec<u8>> {
+    let missing_chunk = |key: u32, what: &str| SbcError::Decode {
+        key,
+        reason: format!("{what} is not stored"),
+    };
+    let corrupted_chunk = |key: u32| SbcError::Decode {
+        key,
+        reason: "zstd delta is corrupted or the zstd feature is disabled".to_string(),
+    };
+    let chunk = match sbc_hash.chunk_type {
+        ChunkType::Simple(_) | ChunkType::Content(_) => match stored_value(sbc_hash) {
+            None => return Err(missing_chunk(sbc_hash.key, "chunk")),
+            Some(data) => data.to_vec(),
+        },
+        ChunkType::Delta(_) => {
+            let sbc_value = match stored_value(sbc_hash) {
+                None => return Err(missing_chunk(sbc_hash.key, "delta chunk")),
+                Some(data) => data,
+            };
+            let Some((parent_hash, sbc_value)) = parent_ref::split(sbc_value) else {
+                return Err(SbcError::Decode {
+                    key: sbc_hash.key,
+                    reason: "delta chunk has no parent reference".to_string(),
+                });
+            };
+            let Some(parent_data) = stored_value(&parent_hash) else {
+                return Err(missing_chunk(sbc_hash.key, "parent chunk"));
+            };
+            let sbc_value =
+                parent_digest::strip(&sbc_value, parent_data).ok_or_else(|| SbcError::Decode {
+                    key: sbc_hash.key,
+                    reason: "parent chunk does not match the digest stored in the delta"
+                        .to_string(),
+                })?;
+            if zstd_ref::is_zstd_delta(&sbc_value) {
+                zstd_ref::decode(parent_data, &sbc_value)
+                    .ok_or_else(|| corrupted_chunk(sbc_hash.key))?
+            } else if !is_levenshtein_delta(&sbc_value) {
+                return Err(SbcError::Decode {
+                    key: sbc_hash.key,
+                    reason: "delta format is not supported by this version".to_string(),
+                });
+            } else {
+                decode_delta(parent_data, &sbc_value).map_err(|error| SbcError::Decode {
+                    key: sbc_hash.key,
+                    reason: error.to_string(),
+                })?
             }
-            Some(data) => data,
-        };
+        }
+    };
+    Ok(match preprocessing.get(sbc_hash) {
+        None => chunk,
+        Some(preprocessing) => preprocessing.invert(chunk.as_slice()),
+    })
+}
 
-        let chunk = match sbc_hash.chunk_type {
-            ChunkType::Simple {} => sbc_value.clone(),
-            ChunkType::Delta(_) => {
-                let mut buf = [0u8; 4];
-                buf.copy_from_slice(&sbc_value[..4]);
-
-                let parent_hash = u32::from_be_bytes(buf);
-                let mut data = self
-                    .get(&SBCHash {
-                        key: parent_hash,
-                        chunk_type: ChunkType::Simple,
-                    })
-                    .unwrap();
+/// Parts of the scrubber the hashing and clustering stages of a scrub read,
+/// on threads of their own when the scrub is pipelined.
+#[derive(Clone, Copy)]
+struct Clustering<'s> {
+    hasher: Option<LengthAwareHasher>,
+    gear_features: Option<&'s GearFeatures>,
+    max_entropy: Option<f64>,
+    min_chunk_size: usize,
+    routing: Option<&'s RoutingTable>,
+    hashing_threads: usize,
+    precomputed_clusters: Option<&'s PrecomputedClusterer>,
+    parent_hints: &'s HashMap<usize, SBCHash>,
+    min_resemblance: Option<f64>,
+}
 
-                let mut byte_index = 4;
-                while byte_index < sbc_value.len() {
-                    buf.copy_from_slice(&sbc_value[byte_index..byte_index + 4]);
-                    let delta_action = u32::from_be_bytes(buf);
+/// Clusters of a batch of chunks and the clusters of its chunks with parent hints.
+type BatchClusters<'c, C> = (Vec<KeyedCluster<'c, C>>, Vec<HintedCluster<'c, C>>);
 
-                    let (action, index, byte_value) = get_delta_action(delta_action);
-                    match action {
-                        Del => {
-                            data.remove(index);
+/// Results of the hashing and clustering stages of a scrub.
+struct ClusteringResults {
+    skipped_chunk_count: usize,
+    hash_collisions: HashCollisions,
+    hashing_time: Duration,
+}
+
+impl Clustering<'_> {
+    /// Similarity hash of a chunk, taken from the chunker when it recorded one.
+    fn similarity_hash(&self, data: &[u8]) -> u32 {
+        self.gear_features
+            .and_then(|features| features.get(data))
+            .unwrap_or_else(|| hash_functions::similarity_hash(self.hasher, data))
+    }
+
+    /// Whether the chunk is hashed into the graph, by scrubs and
+    /// [`SBCScrubber::process_chunk`] alike.
+    fn is_clusterable(&self, data: &[u8]) -> bool {
+        data.len() >= self.min_chunk_size
+            && self
+                .max_entropy
+                .is_none_or(|max_entropy| entropy::byte_entropy(data) <= max_entropy)
+            && self
+                .routing
+                .is_none_or(|routing| routing.route(data) != Route::Skip)
+    }
+
+    /// Hashes the chunks of a batch starting at `first_position` of the scrub
+    /// and returns the similarity hashes of the chunks to cluster, with the
+    /// number of chunks left out by [`Clustering::is_clusterable`], which every
+    /// kind of clustering goes through.
+    fn hash_batch<C: ChunkContainer>(
+        &self,
+        first_position: usize,
+        batch: &[C],
+    ) -> (Vec<Option<u32>>, usize) {
+        let chunks_data: Vec<Option<&[u8]>> = batch.iter().map(C::chunk_data).collect();
+        // Chunks with parent hints are hashed, chunks left out of precomputed
+        // clusters are not.
+        let is_clustered = |position: usize| {
+            self.parent_hints.contains_key(&position)
+                || self
+                    .precomputed_clusters
+                    .is_none_or(|clusterer| clusterer.cluster_of(position).is_some())
+        };
+        let hash = |chunk_id: usize, data: Option<&[u8]>| match data {
+            None => (None, false),
+            Some(data) if !self.is_clusterable(data) => (None, true),
+            Some(data) => (
+                is_clustered(first_position + chunk_id).then(|| self.similarity_hash(data)),
+                false,
+            ),
+        };
+
+        let hashes: Vec<(Option<u32>, bool)> = if self.hashing_threads <= 1
+            || cfg!(feature = "no-parallel")
+        {
+            chunks_data
+                .iter()
+                .enumerate()
+                .map(|(chunk_id, data)| hash(chunk_id, *data))
+                .collect()
+        } else {
+            let mut hashes = vec![(None, false); chunks_data.len()];
+            let next_chunk = AtomicUsize::new(0);
+            thread::scope(|scope| {
+                let (sender, receiver) = mpsc::channel();
+                for _ in 0..self.hashing_threads {
+                    let (sender, chunks_data, next_chunk) =
+                        (sender.clone(), &chunks_data, &next_chunk);
+                    scope.spawn(move || loop {
+                        // Threads take batches of chunks, so they rarely contend for
+                        // the counter and the channel.
+                        let first_chunk =
+                            next_chunk.fetch_add(HASHING_BATCH_LEN, Ordering::Relaxed);
+                        if first_chunk >= chunks_data.len() {
+                            break;
                         }
-                        Add => data.insert(index, byte_value),
-                        Rep => data[index] = byte_value,
-                    }
-                    byte_index += 4;
+                        let last_chunk = min(first_chunk + HASHING_BATCH_LEN, chunks_data.len());
+                        let chunk_hashes: Vec<(Option<u32>, bool)> = (first_chunk..last_chunk)
+                            .map(|chunk_id| hash(chunk_id, chunks_data[chunk_id]))
+                            .collect();
+                        if sender.send((first_chunk, chunk_hashes)).is_err() {
+                            break;
+                        }
+                    });
                 }
-                data
-            }
+                drop(sender);
+                for (first_chunk, chunk_hashes) in receiver {
+                    hashes[first_chunk..first_chunk + chunk_hashes.len()]
+                        .copy_from_slice(&chunk_hashes);
+                }
+            });
+            hashes
         };
-        Ok(chunk)
+        let skipped_chunk_count = hashes.iter().filter(|(_, skipped)| *skipped).count();
+        let hashes = hashes.into_iter().map(|(hash, _)| hash).collect();
+        (hashes, skipped_chunk_count)
     }
 
-    // fn remove(&mut self, sbc_hash: &SBCHash) {
-    //     self.sbc_hashmap.remove(sbc_hash);
-    // }
+    /// Groups the hashed chunks of a batch starting at `first_position` of the
+    /// scrub into clusters, adding their hashes to the graph in the order of
+    /// the chunks. Chunks with parent hints are grouped by their parents instead.
+    fn cluster_batch<'c, C: ChunkContainer>(
+        &self,
+        graph: &mut Graph,
+        first_position: usize,
+        batch: &'c mut [C],
+        hashes: Vec<Option<u32>>,
+        collisions: &mut CollisionCounter,
+    ) -> BatchClusters<'c, C> {
+        let mut clusters: HashMap<u32, Cluster<C>> = HashMap::new();
+        let mut hinted_clusters: HashMap<SBCHash, Cluster<C>> = HashMap::new();
+        for (chunk_id, (data_container, hash)) in batch.iter_mut().zip(hashes).enumerate() {
+            let Some(hash) = hash else {
+                continue;
+            };
+            let position = first_position + chunk_id;
+            if let Some(stored_parent) = self.parent_hints.get(&position) {
+                let cluster = hinted_clusters.entry(stored_parent.clone()).or_default();
+                cluster.push((hash, data_container));
+                continue;
+            }
+            let key = match self.precomputed_clusters {
+                Some(clusterer) => clusterer.cluster_of(position),
+                None => Some(graph.add_vertex(hash)),
+            };
+            let Some(key) = key else {
+                continue;
+            };
+            if let Some(data) = data_container.chunk_data() {
+                collisions.add(hash, data);
+            }
+            clusters
+                .entry(key)
+                .or_default()
+                .push((hash, data_container));
+        }
+        let mut clusters: Vec<KeyedCluster<C>> = clusters.into_iter().collect();
+        if let Some(min_resemblance) = self.min_resemblance {
+            clusters = clusterer::refine_clusters(clusters, min_resemblance);
+        }
+        (clusters, hinted_clusters.into_iter().collect())
+    }
 
-    fn contains(&self, key: &SBCHash) -> bool {
-        self.sbc_hashmap.contains_key(key)
+    /// Hashes and clusters all chunks of a scrub at once.
+    fn cluster_all<'c, C: ChunkContainer>(
+        &self,
+        graph: &mut Graph,
+        chunks: &'c mut [C],
+        time_start: Instant,
+    ) -> (BatchClusters<'c, C>, ClusteringResults) {
+        let (hashes, skipped_chunk_count) = self.hash_batch(0, chunks);
+        let mut collisions = CollisionCounter::default();
+        let clusters = self.cluster_batch(graph, 0, chunks, hashes, &mut collisions);
+        let results = ClusteringResults {
+            skipped_chunk_count,
+            hash_collisions: collisions.count(),
+            hashing_time: time_start.elapsed(),
+        };
+        (clusters, results)
     }
 }
 
 pub struct SBCScrubber {
     graph: Graph,
+    min_resemblance: Option<f64>,
+    hashing_threads: usize,
+    pipeline_batch_len: Option<usize>,
+    recluster_targets: bool,
+    max_entropy: Option<f64>,
+    min_chunk_size: usize,
+    hasher: Option<LengthAwareHasher>,
+    gear_features: Option<GearFeatures>,
+    skipped_chunk_count: usize,
+    hashing_time: Duration,
+    size_buckets: Vec<SizeBucket>,
+    cluster_statistics: Vec<ClusterStatistics>,
+    hash_collisions: HashCollisions,
+    max_collision_rate: Option<f64>,
+    /// Collisions of the scrub after which simple chunks got content-addressed keys.
+    key_widening: Option<HashCollisions>,
+    settings: EncodeSettings,
+    /// Parents of clusters of [`SBCScrubber::process_chunk`] with their numbers of children.
+    online_parents: HashMap<u32, (SBCHash, usize)>,
+    /// Stored chunks added by [`SBCScrubber::seed_from_map`], by cluster.
+    seeded_parents: HashMap<u32, SBCHash>,
+    precomputed_clusters: Option<PrecomputedClusterer>,
+    /// Parents chosen by the caller, by position of the chunk in a scrub.
+    parent_hints: HashMap<usize, SBCHash>,
 }
 
 impl SBCScrubber {
     pub fn new() -> SBCScrubber {
         SBCScrubber {
             graph: Graph::new(),
+            min_resemblance: None,
+            hashing_threads: 1,
+            pipeline_batch_len: None,
+            recluster_targets: false,
+            max_entropy: None,
+            min_chunk_size: 0,
+            hasher: None,
+            gear_features: None,
+            skipped_chunk_count: 0,
+            hashing_time: Duration::ZERO,
+            size_buckets: Vec::new(),
+            cluster_statistics: Vec::new(),
+            hash_collisions: HashCollisions::default(),
+            max_collision_rate: None,
+            key_widening: None,
+            settings: EncodeSettings::default(),
+            online_parents: HashMap::new(),
+            seeded_parents: HashMap::new(),
+            precomputed_clusters: None,
+            parent_hints: HashMap::new(),
         }
     }
+
+    /// Preset for throughput: hashing on all cores, incompressible chunks skipped
+    /// and dissimilar chunks rejected by cheap byte sampling.
+    pub fn fast() -> SBCScrubber {
+        let threads = thread::available_parallelism().map_or(1, usize::from);
+        SBCScrubber::new()
+            .with_hashing_threads(threads)
+            .with_entropy_skip(7.5)
+            .with_similarity_filter(SimilarityFilter {
+                sample_count: 64,
+                min_sampled_similarity: 0.5,
+                ..SimilarityFilter::default()
+            })
+    }
+
+    /// Preset balancing speed and ratio: graph clusters whose chunks are
+    /// delta encoded only when enough of their windows are found in the parent.
+    pub fn balanced() -> SBCScrubber {
+        SBCScrubber::new()
+            .with_entropy_skip(7.9)
+            .with_similarity_filter(SimilarityFilter {
+                window_sample_count: 32,
+                min_window_resemblance: 0.25,
+                ..SimilarityFilter::default()
+            })
+    }
+
+    /// Preset for the best ratio: clusters refined by MinHash resemblance and,
+    /// with the `zstd` feature, zstd deltas where Levenshtein ones are too large.
+    pub fn max_compression() -> SBCScrubber {
+        let scrubber = SBCScrubber::new().with_resemblance_refinement(0.3);
+        #[cfg(feature = "zstd")]
+        let scrubber = scrubber.with_zstd_fallback(19);
+        scrubber
+    }
+
+    /// Compresses chunks whose Levenshtein delta is too large with zstd, using
+    /// the parent chunk as a raw content dictionary.
+    #[cfg(feature = "zstd")]
+    pub fn with_zstd_fallback(mut self, level: i32) -> SBCScrubber {
+        self.settings.zstd_level = Some(level);
+        self
+    }
+
+    /// Stores chunks whose delta would exceed `fraction` of their size as simple
+    /// ones. Encoders give up as soon as they know the delta is too large.
+    pub fn with_max_delta_fraction(mut self, fraction: f64) -> SBCScrubber {
+        self.settings.max_delta_fraction = Some(fraction);
+        self
+    }
+
+    /// Attaches at most `max_children` delta chunks to a parent. The next chunk
+    /// of the cluster is stored as a simple chunk and becomes the parent of the
+    /// following ones, which limits the damage of losing a single parent.
+    pub fn with_max_children_per_parent(mut self, max_children: usize) -> SBCScrubber {
+        self.settings.max_children = Some(max_children);
+        self
+    }
+
+    /// Hashes chunks, and checks whether they are clusterable, on `threads`
+    /// threads. Ignored with the `no-parallel` feature.
+    pub fn with_hashing_threads(mut self, threads: usize) -> SBCScrubber {
+        self.hashing_threads = threads;
+        self
+    }
+
+    /// Computes the deltas of the chunks of a cluster on `threads` threads and
+    /// stores them in the order of the chunks, so the result does not depend on
+    /// the number of threads. Deltas against a parent which replaced another
+    /// because of [`SBCScrubber::with_max_children_per_parent`] are computed
+    /// on the current thread. Ignored with the `no-parallel` feature.
+    pub fn with_encoding_threads(mut self, threads: usize) -> SBCScrubber {
+        self.settings.encoding_threads = threads;
+        self
+    }
+
+    /// Scrubs chunks in batches of `batch_len` passed through three stages
+    /// connected by bounded queues: hashing, on the threads of
+    /// [`SBCScrubber::with_hashing_threads`], clustering, on a thread of its
+    /// own, and encoding, on the current thread and the threads of
+    /// [`SBCScrubber::with_encoding_threads`]. A batch is encoded while the
+    /// next ones are hashed and clustered.
+    ///
+    /// Clusters span batches: chunks joining a cluster encoded in an earlier
+    /// batch are encoded against its parent. The budget orders clusters and
+    /// resemblance refinement splits them within a batch only. Ignored with
+    /// the `no-parallel` feature.
+    pub fn with_pipeline_batch_len(mut self, batch_len: usize) -> SBCScrubber {
+        self.pipeline_batch_len = Some(batch_len.max(1));
+        self
+    }
+
+    /// Limits the time and the amount of data processed by a scrub. Clusters with
+    /// the largest potential savings are encoded first.
+    pub fn with_budget(mut self, budget: ScrubBudget) -> SBCScrubber {
+        self.settings.budget = budget;
+        self
+    }
+
+    /// Splits every cluster found by similarity hashes into groups of chunks whose
+    /// estimated resemblance (MinHash) is at least `min_resemblance`.
+    pub fn with_resemblance_refinement(mut self, min_resemblance: f64) -> SBCScrubber {
+        self.min_resemblance = Some(min_resemblance);
+        self
+    }
+
+    /// Sets the transform applied to chunks before hashing and delta encoding.
+    /// Stored chunks remember it, so they are decoded to the original data.
+    pub fn with_preprocessing(mut self, preprocessing: Preprocessing) -> SBCScrubber {
+        self.settings.preprocessing = preprocessing;
+        self
+    }
+
+    /// Sets the filter deciding which chunks of a cluster are delta encoded.
+    pub fn with_similarity_filter(mut self, filter: SimilarityFilter) -> SBCScrubber {
+        self.settings.filter = filter;
+        self
+    }
+
+    /// Stores a digest of the parent chunk in every delta chunk. Decoding fails
+    /// instead of returning wrong data when the parent key was reused by another
+    /// chunk, e.g. after a similarity hash collision.
+    pub fn with_parent_verification(mut self) -> SBCScrubber {
+        self.settings.parent_digest = true;
+        self
+    }
+
+    /// Keys simple chunks by `content_hasher`, e.g. [`crate::blake2b_content_hash`],
+    /// instead of the similarity hash, so identical chunks are stored once and
+    /// deltas refer to their parents unambiguously.
+    pub fn with_content_addressing(mut self, content_hasher: ContentHasher) -> SBCScrubber {
+        self.settings.content_hasher = Some(content_hasher);
+        self
+    }
+
+    /// Switches to content-addressed keys, as [`SBCScrubber::with_content_addressing`]
+    /// with [`crate::blake2b_content_hash`] does, once the similarity hash
+    /// collision rate of a scrub exceeds `max_rate`. Colliding contents then no
+    /// longer share numbered keys, so parents are referred to unambiguously.
+    /// The switch applies to the encoding of the scrub which triggered it, or
+    /// to the next scrub when the scrub is pipelined, and is reported by
+    /// [`SBCScrubber::key_widening`].
+    pub fn with_collision_widening(mut self, max_rate: f64) -> SBCScrubber {
+        self.max_collision_rate = Some(max_rate);
+        self
+    }
+
+    /// Saves the target map to `path` every time clusters of at least
+    /// `interval_bytes` have been encoded since the last save. To resume after
+    /// a crash, load the map with [`SBCMap::load`] and scrub again: chunks
+    /// already replaced by their targets are not encoded twice.
+    pub fn with_checkpoint<P: Into<PathBuf>>(
+        mut self,
+        path: P,
+        interval_bytes: usize,
+    ) -> SBCScrubber {
+        self.settings.checkpoint = Some(Checkpoint {
+            path: path.into(),
+            interval_bytes,
+        });
+        self
+    }
+
+    /// Also clusters chunks encoded by previous scrubs, decoding them from the
+    /// target map, so that they can get better parents. Replaced delta chunks
+    /// stay in the map.
+    pub fn with_target_reclustering(mut self) -> SBCScrubber {
+        self.recluster_targets = true;
+        self
+    }
+
+    /// Leaves chunks whose byte entropy exceeds `max_entropy` bits per byte
+    /// (already compressed or encrypted data) out of hashing and delta encoding.
+    pub fn with_entropy_skip(mut self, max_entropy: f64) -> SBCScrubber {
+        self.max_entropy = Some(max_entropy);
+        self
+    }
+
+    /// Leaves chunks smaller than `min_chunk_size` bytes out of every kind of
+    /// clustering, parent hints and precomputed clusters included. Like the
+    /// other chunks left unprocessed, [`crate::compress_chunks`] stores them as
+    /// simple chunks and chunkfs scrubs leave them in the source database.
+    /// Deltas of such chunks save too little to be worth a parent lookup on
+    /// every read.
+    pub fn with_min_chunk_size(mut self, min_chunk_size: usize) -> SBCScrubber {
+        self.min_chunk_size = min_chunk_size;
+        self
+    }
+
+    /// Hashes chunks with `hasher` instead of [`crate::sbc_hashing`], so chunks of
+    /// very different lengths or layouts are not clustered together.
+    pub fn with_length_aware_hashing(mut self, hasher: LengthAwareHasher) -> SBCScrubber {
+        self.hasher = Some(hasher);
+        self
+    }
+
+    /// Clusters chunks as `clusterer` says instead of by similarity hashes. The
+    /// first chunk of every cluster becomes its parent. Chunks left out of the
+    /// clusters are neither hashed into the graph nor delta encoded, the
+    /// entropy check is not done.
+    pub fn with_precomputed_clusters(mut self, clusterer: PrecomputedClusterer) -> SBCScrubber {
+        self.precomputed_clusters = Some(clusterer);
+        self
+    }
+
+    /// Encodes the chunks at the given positions of a scrub against the given
+    /// stored parents instead of the parents of their clusters, e.g. the chunks
+    /// they descend from in an older snapshot. Other chunks are clustered as
+    /// usual. Chunks hinted to a parent which is not stored with the same
+    /// preprocessing are clustered with each other.
+    pub fn with_parent_hints(mut self, hints: HashMap<usize, SBCHash>) -> SBCScrubber {
+        self.parent_hints = hints;
+        self
+    }
+
+    /// Picks the encoding of every chunk by its class: text, binary or
+    /// compressed. Chunks routed to [`Route::Skip`] are left out of clustering
+    /// like chunks skipped by the entropy check.
+    pub fn with_routing(mut self, routing: RoutingTable) -> SBCScrubber {
+        self.settings.routing = Some(routing);
+        self
+    }
+
+    /// Takes the similarity hashes of chunks cut by a [`crate::GearChunker`]
+    /// recording into `features` from the chunker, instead of hashing them
+    /// again. Other chunks are hashed as usual.
+    pub fn with_gear_features(mut self, features: GearFeatures) -> SBCScrubber {
+        self.gear_features = Some(features);
+        self
+    }
+
+    pub(crate) fn similarity_hash(&self, data: &[u8]) -> u32 {
+        self.clustering().similarity_hash(data)
+    }
+
+    pub(crate) fn content_hasher(&self) -> Option<ContentHasher> {
+        self.settings.content_hasher
+    }
+
+    pub(crate) fn encode_settings(&self) -> &EncodeSettings {
+        &self.settings
+    }
+
+    fn clustering(&self) -> Clustering<'_> {
+        Clustering {
+            hasher: self.hasher,
+            gear_features: self.gear_features.as_ref(),
+            max_entropy: self.max_entropy,
+            min_chunk_size: self.min_chunk_size,
+            routing: self.settings.routing.as_ref(),
+            hashing_threads: self.hashing_threads,
+            precomputed_clusters: self.precomputed_clusters.as_ref(),
+            parent_hints: &self.parent_hints,
+            min_resemblance: self.min_resemblance,
+        }
+    }
+
+    /// Encoder of the clusters of a scrub. Seeded parents are kept by graph
+    /// root, which precomputed clusters are not keyed by.
+    pub(crate) fn cluster_encoder(&self, time_start: Instant) -> ClusterEncoder<'_> {
+        let seeded_parents = match self.precomputed_clusters {
+            None => self.seeded_parents.clone(),
+            Some(_) => HashMap::new(),
+        };
+        ClusterEncoder::new(
+            &self.settings,
+            time_start,
+            seeded_parents,
+            self.min_resemblance.is_none(),
+        )
+    }
+
+    /// Number of chunks skipped by the entropy check or as too small during the
+    /// last scrub.
+    pub fn skipped_chunk_count(&self) -> usize {
+        self.skipped_chunk_count
+    }
+
+    /// Time the last scrub took to hash and cluster the chunks.
+    pub fn hashing_time(&self) -> Duration {
+        self.hashing_time
+    }
+
+    /// Results of the last scrub grouped by the original size of chunks:
+    /// below 2 KiB, 2-8 KiB, 8-32 KiB and above.
+    pub fn size_buckets(&self) -> &[SizeBucket] {
+        self.size_buckets.as_slice()
+    }
+
+    /// Results of the last scrub for every encoded cluster, in the order they
+    /// were encoded. Clusters left untouched by the budget are not listed.
+    pub fn cluster_statistics(&self) -> &[ClusterStatistics] {
+        self.cluster_statistics.as_slice()
+    }
+
+    /// Similarity hash collisions among the chunks of the last scrub.
+    pub fn hash_collisions(&self) -> HashCollisions {
+        self.hash_collisions
+    }
+
+    /// Collisions of the scrub which made the scrubber switch to content-addressed
+    /// keys, see [`SBCScrubber::with_collision_widening`].
+    pub fn key_widening(&self) -> Option<HashCollisions> {
+        self.key_widening
+    }
+
+    /// Adds the simple chunks of `map` to the clusters of the scrubber, so
+    /// similar chunks scrubbed into `map` later are encoded as deltas of them
+    /// instead of forming parallel clusters. The keys of the chunks are used as
+    /// their similarity hashes, the first chunk of a cluster becomes its parent.
+    pub fn seed_from_map(&mut self, map: &SBCMap) {
+        let mut keys: Vec<(u32, u16)> = map
+            .keys()
+            .into_iter()
+            .filter_map(|sbc_hash| match sbc_hash.chunk_type {
+                ChunkType::Simple(number) => Some((sbc_hash.key, number)),
+                _ => None,
+            })
+            .collect();
+        keys.sort();
+        for (key, number) in keys {
+            let sbc_hash = SBCHash {
+                key,
+                chunk_type: ChunkType::Simple(number),
+            };
+            let cluster = self.graph.add_vertex(key);
+            self.online_parents
+                .entry(cluster)
+                .or_insert_with(|| (sbc_hash.clone(), 0));
+            self.seeded_parents.entry(cluster).or_insert(sbc_hash);
+        }
+    }
+
+    /// Encodes a chunk right away instead of during a scrub. The chunk joins the
+    /// cluster of similar chunks seen by the scrubber and is stored as a delta of
+    /// the first chunk of the cluster processed this way, or becomes that chunk.
+    pub fn process_chunk(&mut self, data: &[u8], target_map: &mut SBCMap) -> Result<SBCHash> {
+        let preprocessing = self.settings.preprocessing;
+        let data = match preprocessing {
+            Preprocessing::None => Cow::Borrowed(data),
+            _ => Cow::Owned(preprocessing.apply(data)),
+        };
+        let hash = self.similarity_hash(&data);
+        let clusterable = self.clustering().is_clusterable(&data);
+        let cluster = clusterable.then(|| self.graph.add_vertex(hash));
+        let parent = cluster
+            .and_then(|cluster| self.online_parents.get(&cluster))
+            .filter(|(_, children)| self.settings.max_children.is_none_or(|max| *children < max))
+            .and_then(|(parent_hash, _)| {
+                let parent_data = target_map.stored_value(parent_hash)?;
+                self.settings
+                    .filter
+                    .should_delta_encode(&data, parent_data)
+                    .then(|| {
+                        clusterer::delta_chunk(&data, parent_data, parent_hash, &self.settings)
+                    })
+            });
+
+        let sbc_hash = match parent {
+            Some(delta_chunk) => {
+                let (outcome, sbc_hash) = clusterer::store_delta_chunk(
+                    target_map,
+                    &data,
+                    hash,
+                    delta_chunk,
+                    &self.settings,
+                );
+                target_map.ensure_stored(&sbc_hash)?;
+                if !outcome.fallback_simple {
+                    self.online_parents.get_mut(&cluster.unwrap()).unwrap().1 += 1;
+                }
+                sbc_hash
+            }
+            None => {
+                let (_, sbc_hash) = clusterer::encode_new_simple_chunk(
+                    target_map,
+                    &data,
+                    hash,
+                    self.settings.content_hasher,
+                    preprocessing,
+                );
+                target_map.ensure_stored(&sbc_hash)?;
+                if let Some(cluster) = cluster {
+                    if !self.online_parents.contains_key(&cluster)
+                        || self
+                            .settings
+                            .max_children
+                            .is_some_and(|max| self.online_parents[&cluster].1 >= max)
+                    {
+                        self.online_parents.insert(cluster, (sbc_hash.clone(), 0));
+                    }
+                }
+                sbc_hash
+            }
+        };
+        target_map.set_preprocessing(sbc_hash.clone(), preprocessing);
+        Ok(sbc_hash)
+    }
+
+    pub(crate) fn scrub_chunks<'a, C: ChunkContainer + Send + 'a>(
+        &mut self,
+        chunks: impl Iterator<Item = &'a mut C>,
+        target_map: &mut SBCMap,
+        time_start: Instant,
+    ) -> Result<EncodeStatistics> {
+        let mut chunks = self.preprocess_chunks(chunks, target_map)?;
+        let pipeline_batch_len = self
+            .pipeline_batch_len
+            .filter(|batch_len| *batch_len < chunks.len() && !cfg!(feature = "no-parallel"));
+        let statistics = match pipeline_batch_len {
+            Some(batch_len) => {
+                self.scrub_pipelined(&mut chunks, batch_len, target_map, time_start)?
+            }
+            None => {
+                let (mut clusters, mut hinted_clusters) =
+                    self.cluster_chunks(&mut chunks, time_start);
+                let mut encoder = self.cluster_encoder(time_start);
+                encoder.encode_all(&mut clusters, &mut hinted_clusters, target_map)?;
+                encoder.finish()
+            }
+        };
+        Ok(self.finish_scrub(statistics))
+    }
+
+    /// Scrubs the chunks in batches, see [`SBCScrubber::with_pipeline_batch_len`].
+    fn scrub_pipelined<C: ChunkContainer + Send>(
+        &mut self,
+        chunks: &mut [C],
+        batch_len: usize,
+        target_map: &mut SBCMap,
+        time_start: Instant,
+    ) -> Result<EncodeStatistics> {
+        // The graph is taken out of the scrubber for the clustering stage,
+        // while the other stages read the rest of it.
+        let mut graph = mem::replace(&mut self.graph, Graph::new());
+        let clustering = self.clustering();
+        let mut encoder = self.cluster_encoder(time_start);
+        let results: Result<ClusteringResults> = thread::scope(|scope| {
+            let (hashed_sender, hashed_receiver) = mpsc::sync_channel(PIPELINE_QUEUE_LEN);
+            let (clustered_sender, clustered_receiver) = mpsc::sync_channel(PIPELINE_QUEUE_LEN);
+            let hashing_stage = scope.spawn(move || {
+                let mut skipped_chunk_count = 0;
+                for (batch_id, batch) in chunks.chunks_mut(batch_len).enumerate() {
+                    let first_position = batch_id * batch_len;
+                    let (hashes, skipped) = clustering.hash_batch(first_position, batch);
+                    skipped_chunk_count += skipped;
+                    if hashed_sender.send((first_position, batch, hashes)).is_err() {
+                        break;
+                    }
+                }
+                skipped_chunk_count
+            });
+            let graph = &mut graph;
+            let clustering_stage = scope.spawn(move || {
+                let mut collisions = CollisionCounter::default();
+                for (first_position, batch, hashes) in hashed_receiver {
+                    let clusters = clustering.cluster_batch(
+                        graph,
+                        first_position,
+                        batch,
+                        hashes,
+                        &mut collisions,
+                    );
+                    if clustered_sender.send(clusters).is_err() {
+                        break;
+                    }
+                }
+                (collisions.count(), time_start.elapsed())
+            });
+            // An error drops the receiver, which stops the other stages.
+            for (mut clusters, mut hinted_clusters) in clustered_receiver {
+                encoder.encode_all(&mut clusters, &mut hinted_clusters, target_map)?;
+            }
+            let skipped_chunk_count = hashing_stage.join().unwrap();
+            let (hash_collisions, hashing_time) = clustering_stage.join().unwrap();
+            Ok(ClusteringResults {
+                skipped_chunk_count,
+                hash_collisions,
+                hashing_time,
+            })
+        });
+        let statistics = encoder.finish();
+        self.graph = graph;
+        self.record_clustering(results?);
+        Ok(statistics)
+    }
+
+    pub(crate) fn preprocess_chunks<'a, C: ChunkContainer + 'a>(
+        &self,
+        chunks: impl Iterator<Item = &'a mut C>,
+        target_map: &SBCMap,
+    ) -> Result<Vec<PreprocessedChunk<'a, C>>> {
+        let preprocessing = self.settings.preprocessing;
+        chunks
+            .map(|data_container| match data_container.target() {
+                Some(keys) if self.recluster_targets => {
+                    let mut data = Vec::new();
+                    for sbc_hash in keys {
+                        data.extend(target_map.decode(sbc_hash)?);
+                    }
+                    Ok(PreprocessedChunk::with_data(
+                        data_container,
+                        data.as_slice(),
+                        preprocessing,
+                    ))
+                }
+                _ => Ok(PreprocessedChunk::new(data_container, preprocessing)),
+            })
+            .collect()
+    }
+
+    /// Hashes the chunks and groups them into clusters to encode. Chunks with
+    /// parent hints are grouped by their parents instead.
+    pub(crate) fn cluster_chunks<'c, C: ChunkContainer>(
+        &mut self,
+        chunks: &'c mut [C],
+        time_start: Instant,
+    ) -> BatchClusters<'c, C> {
+        let mut graph = mem::replace(&mut self.graph, Graph::new());
+        let (clusters, results) = self
+            .clustering()
+            .cluster_all(&mut graph, chunks, time_start);
+        self.graph = graph;
+        self.record_clustering(results);
+        clusters
+    }
+
+    fn record_clustering(&mut self, results: ClusteringResults) {
+        self.skipped_chunk_count = results.skipped_chunk_count;
+        self.hash_collisions = results.hash_collisions;
+        self.hashing_time = results.hashing_time;
+        if self.settings.content_hasher.is_none()
+            && self
+                .max_collision_rate
+                .is_some_and(|max_rate| self.hash_collisions.rate() > max_rate)
+        {
+            self.settings.content_hasher = Some(blake2b_content_hash);
+            self.key_widening = Some(self.hash_collisions);
+        }
+    }
+
+    pub(crate) fn finish_scrub(&mut self, mut statistics: EncodeStatistics) -> EncodeStatistics {
+        statistics.skipped_chunk_count = self.skipped_chunk_count;
+        self.size_buckets = statistics.size_buckets.to_vec();
+        self.cluster_statistics = statistics.clusters.clone();
+        statistics
+    }
 }
 
 impl Default for SBCScrubber {
@@ -102,24 +936,15 @@ where
         let time_start = Instant::now();
         let mut processed_data = 0;
         let mut data_left = 0;
-        let mut clusters: HashMap<u32, Vec<(u32, &mut DataContainer<SBCHash>)>> = HashMap::new();
-        for (_, data_container) in database.iterator_mut() {
-            match data_container.extract() {
-                Data::Chunk(data) => {
-                    let sbc_hash = hash_functions::sbc_hashing(data.as_slice());
-                    let parent_hash = self.graph.add_vertex(sbc_hash);
-                    let cluster = clusters.entry(parent_hash).or_default();
-                    cluster.push((sbc_hash, data_container));
-                }
-                Data::TargetChunk(_) => {}
-            }
-        }
-        let time_hashing = time_start.elapsed();
-        println!("time for hashing: {time_hashing:?}");
-        let (clusters_data_left, clusters_processed_data) =
-            clusterer::encode_clusters(&mut clusters, target_map);
-        data_left += clusters_data_left;
-        processed_data += clusters_processed_data;
+        let statistics = self.scrub_chunks(
+            database
+                .iterator_mut()
+                .map(|(_, data_container)| data_container),
+            target_map,
+            time_start,
+        )?;
+        data_left += statistics.data_left;
+        processed_data += statistics.processed_data;
         let running_time = time_start.elapsed();
         Ok(ScrubMeasurements {
             processed_data,
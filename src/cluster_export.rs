@@ -0,0 +1,145 @@
+use crate::{ChunkType, Result, SBCMap};
+use std::io::Write;
+
+/// Format of [`SBCMap::write_cluster_graph`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GraphFormat {
+    /// Graphviz digraph.
+    Dot,
+    /// `{"nodes": [{"id", "kind", "size"}], "edges": [{"parent", "child", "delta_size"}]}`.
+    Json,
+}
+
+struct Node {
+    id: String,
+    kind: &'static str,
+    size: usize,
+}
+
+struct Edge {
+    parent: String,
+    child: String,
+    delta_size: usize,
+}
+
+impl SBCMap {
+    /// Writes the stored chunks as a graph to visualize clusters: nodes are chunks
+    /// with their decoded sizes, edges lead from parents to their delta chunks
+    /// and carry the stored sizes of the deltas.
+    pub fn write_cluster_graph<W: Write>(&self, writer: &mut W, format: GraphFormat) -> Result<()> {
+        let mut nodes = Vec::new();
+        let mut edges = Vec::new();
+        for sbc_hash in self.keys() {
+            let kind = match sbc_hash.chunk_type {
+                ChunkType::Simple(_) | ChunkType::Content(_) => "simple",
+                ChunkType::Delta(_) => "delta",
+            };
+            if let Some(parent_hash) = self.parent_of(&sbc_hash) {
+                edges.push(Edge {
+                    parent: parent_hash.to_string(),
+                    child: sbc_hash.to_string(),
+                    delta_size: self.stored_len(&sbc_hash).unwrap_or_default(),
+                });
+            }
+            nodes.push(Node {
+                id: sbc_hash.to_string(),
+                kind,
+                size: self.decode(&sbc_hash)?.len(),
+            });
+        }
+        nodes.sort_by(|a, b| a.id.cmp(&b.id));
+        edges.sort_by(|a, b| (&a.parent, &a.child).cmp(&(&b.parent, &b.child)));
+
+        match format {
+            GraphFormat::Dot => {
+                writeln!(writer, "digraph clusters {{")?;
+                for node in &nodes {
+                    let shape = if node.kind == "simple" {
+                        "box"
+                    } else {
+                        "ellipse"
+                    };
+                    writeln!(
+                        writer,
+                        "  \"{}\" [shape={shape}, label=\"{}\\n{} B\"];",
+                        node.id, node.id, node.size
+                    )?;
+                }
+                for edge in &edges {
+                    writeln!(
+                        writer,
+                        "  \"{}\" -> \"{}\" [label=\"{} B\"];",
+                        edge.parent, edge.child, edge.delta_size
+                    )?;
+                }
+                writeln!(writer, "}}")?;
+            }
+            GraphFormat::Json => {
+                let nodes: Vec<String> = nodes
+                    .iter()
+                    .map(|node| {
+                        format!(
+                            "{{\"id\":\"{}\",\"kind\":\"{}\",\"size\":{}}}",
+                            node.id, node.kind, node.size
+                        )
+                    })
+                    .collect();
+                let edges: Vec<String> = edges
+                    .iter()
+                    .map(|edge| {
+                        format!(
+                            "{{\"parent\":\"{}\",\"child\":\"{}\",\"delta_size\":{}}}",
+                            edge.parent, edge.child, edge.delta_size
+                        )
+                    })
+                    .collect();
+                writeln!(
+                    writer,
+                    "{{\"nodes\":[{}],\"edges\":[{}]}}",
+                    nodes.join(","),
+                    edges.join(",")
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::SBCHash;
+    use chunkfs::Database;
+
+    #[test]
+    fn test_write_cluster_graph() {
+        let data: Vec<u8> = (0..4096).map(|_| rand::random::<u8>()).collect();
+        let mut similar_data = data.clone();
+        similar_data[10] ^= 1;
+        let mut map = SBCMap::new();
+        map.insert(SBCHash::default(), data.clone()).unwrap();
+        let delta_hash = SBCHash {
+            key: 7,
+            chunk_type: ChunkType::Delta(0),
+        };
+        let delta = crate::encode_delta(&similar_data, &data, 0).unwrap();
+        map.insert(delta_hash, delta).unwrap();
+
+        let mut dot = Vec::new();
+        map.write_cluster_graph(&mut dot, GraphFormat::Dot).unwrap();
+        let dot = String::from_utf8(dot).unwrap();
+        assert!(dot.starts_with("digraph clusters {"));
+        assert!(dot.contains("\"d7.0\" [shape=ellipse, label=\"d7.0\\n4096 B\"];"));
+        assert!(dot.contains("\"s0.0\" -> \"d7.0\" [label=\"8 B\"];"));
+
+        let mut json = Vec::new();
+        map.write_cluster_graph(&mut json, GraphFormat::Json)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(json).unwrap(),
+            "{\"nodes\":[{\"id\":\"d7.0\",\"kind\":\"delta\",\"size\":4096},\
+             {\"id\":\"s0.0\",\"kind\":\"simple\",\"size\":4096}],\
+             \"edges\":[{\"parent\":\"s0.0\",\"child\":\"d7.0\",\"delta_size\":8}]}\n"
+        );
+    }
+}
@@ -1,158 +1,698 @@
+use crate::content_hash::content_key;
+use crate::graph::MAX_WEIGHT_EDGE;
 use crate::levenshtein_functions::levenshtein_distance;
-use crate::{levenshtein_functions, ChunkType, SBCHash, SBCMap};
+use crate::min_hash::{group_by_resemblance, MinHashSketch};
+use crate::persistence::Checkpoint;
+use crate::routing::DEFAULT_ZSTD_LEVEL;
+use crate::{
+    levenshtein_functions, parent_digest, parent_ref, zstd_ref, ChunkType, Codec, ContentHasher,
+    Preprocessing, Result, Route, RoutingTable, SBCHash, SBCMap, SimilarityFilter,
+};
 use chunkfs::{Data, DataContainer, Database};
+use std::collections::hash_map::DefaultHasher;
 use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
 
-fn count_delta_chunks_with_hash(target_map: &mut SBCMap, hash: u32) -> u16 {
-    let mut count = 0;
+pub(crate) type Cluster<'a, C> = Vec<(u32, &'a mut C)>;
+
+/// Cluster whose parent was chosen by the caller.
+pub(crate) type HintedCluster<'a, C> = (SBCHash, Cluster<'a, C>);
+
+/// Cluster with the key its chunks were grouped by: the root of their graph
+/// cluster or the number of their precomputed cluster.
+pub(crate) type KeyedCluster<'a, C> = (u32, Cluster<'a, C>);
+
+pub(crate) trait ChunkContainer {
+    fn chunk_data(&self) -> Option<&[u8]>;
+
+    fn set_target(&mut self, sbc_hash: SBCHash);
+
+    /// Keys of the chunks the container already refers to.
+    fn target(&self) -> Option<&[SBCHash]> {
+        None
+    }
+}
+
+impl ChunkContainer for DataContainer<SBCHash> {
+    fn chunk_data(&self) -> Option<&[u8]> {
+        match self.extract() {
+            Data::Chunk(data) => Some(data.as_slice()),
+            Data::TargetChunk(_) => None,
+        }
+    }
+
+    fn set_target(&mut self, sbc_hash: SBCHash) {
+        self.make_target(vec![sbc_hash]);
+    }
+
+    fn target(&self) -> Option<&[SBCHash]> {
+        match self.extract() {
+            Data::Chunk(_) => None,
+            Data::TargetChunk(keys) => Some(keys.as_slice()),
+        }
+    }
+}
+
+/// First free number of a chunk of the given type under the similarity hash `hash`.
+pub(crate) fn next_chunk_number(
+    target_map: &SBCMap,
+    hash: u32,
+    chunk_type: fn(u16) -> ChunkType,
+) -> u16 {
+    let mut number = 0;
     while target_map.contains(&SBCHash {
         key: hash,
-        chunk_type: ChunkType::Delta(count),
+        chunk_type: chunk_type(number),
     }) {
-        count += 1
+        number += 1
     }
-    count
+    number
 }
 
-fn find_empty_cell(target_map: &SBCMap, hash: u32) -> u32 {
-    let mut left = hash;
-    let mut right = hash + 1;
-    loop {
-        if target_map.contains(&SBCHash {
-            key: left,
-            chunk_type: ChunkType::Simple,
-        }) {
-            left = left.saturating_sub(1);
-        } else {
-            return left;
+pub(crate) fn encode_simple_chunk(
+    target_map: &mut SBCMap,
+    data: &[u8],
+    hash: u32,
+) -> (usize, SBCHash) {
+    let sbc_hash = SBCHash {
+        key: hash,
+        chunk_type: ChunkType::Simple(next_chunk_number(target_map, hash, ChunkType::Simple)),
+    };
+
+    let _ = target_map.insert_shared(sbc_hash.clone(), Arc::from(data));
+    (data.len(), sbc_hash)
+}
+
+/// Stores the chunk under its content hash when `content_hasher` is set, and as
+/// a numbered simple chunk otherwise. A chunk with the same content hash and
+/// preprocessing is stored only once.
+pub(crate) fn encode_new_simple_chunk(
+    target_map: &mut SBCMap,
+    data: &[u8],
+    hash: u32,
+    content_hasher: Option<ContentHasher>,
+    preprocessing: Preprocessing,
+) -> (usize, SBCHash) {
+    let Some(content_hasher) = content_hasher else {
+        return encode_simple_chunk(target_map, data, hash);
+    };
+    let sbc_hash = content_key(content_hasher(data));
+    if !target_map.contains(&sbc_hash) {
+        let _ = target_map.insert_shared(sbc_hash.clone(), Arc::from(data));
+        return (data.len(), sbc_hash);
+    }
+    match target_map.preprocessing.get(&sbc_hash).copied() {
+        stored if stored.unwrap_or_default() == preprocessing => (0, sbc_hash),
+        _ => encode_simple_chunk(target_map, data, hash),
+    }
+}
+
+/// Stores the chunk as a simple one, reusing the simple chunk the container
+/// already refers to when it holds the same data.
+fn store_simple_chunk<C: ChunkContainer>(
+    target_map: &mut SBCMap,
+    container: &C,
+    data: &[u8],
+    hash: u32,
+    settings: &EncodeSettings,
+) -> (usize, SBCHash) {
+    let preprocessing = settings.preprocessing;
+    if let Some([sbc_hash]) = container.target() {
+        if matches!(
+            sbc_hash.chunk_type,
+            ChunkType::Simple(_) | ChunkType::Content(_)
+        ) && target_map.stored_value(sbc_hash) == Some(data)
+            && target_map
+                .preprocessing
+                .get(sbc_hash)
+                .copied()
+                .unwrap_or_default()
+                == preprocessing
+        {
+            return (data.len(), sbc_hash.clone());
+        }
+    }
+    encode_new_simple_chunk(
+        target_map,
+        data,
+        hash,
+        settings.content_hasher,
+        preprocessing,
+    )
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct EncodeOutcome {
+    pub original_bytes: usize,
+    pub stored_bytes: usize,
+    pub fallback_simple: bool,
+}
+
+/// Upper bounds of chunk sizes of all [`SizeBucket`]s but the last one.
+const SIZE_BUCKET_LIMITS: [usize; 3] = [2 << 10, 8 << 10, 32 << 10];
+
+/// Results of a scrub for chunks with original size in `min_size..max_size`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SizeBucket {
+    pub min_size: usize,
+    /// `None` for the last bucket, which has no upper bound.
+    pub max_size: Option<usize>,
+    pub chunk_count: usize,
+    pub original_bytes: usize,
+    pub stored_bytes: usize,
+    pub encode_time: Duration,
+}
+
+impl SizeBucket {
+    /// Stored size relative to the original size of the chunks.
+    pub fn ratio(&self) -> f64 {
+        match self.original_bytes {
+            0 => 1.0,
+            original_bytes => self.stored_bytes as f64 / original_bytes as f64,
+        }
+    }
+}
+
+/// Results of a scrub for a single cluster.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ClusterStatistics {
+    /// Chunk the first delta chunks of the cluster were encoded against.
+    pub parent: SBCHash,
+    /// Chunks encoded by the scrub, the parent included unless it was stored before.
+    pub chunk_count: usize,
+    pub delta_chunk_count: usize,
+    pub original_bytes: usize,
+    pub stored_bytes: usize,
+}
+
+/// Chunks of a scrub whose different contents got the same similarity hash.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct HashCollisions {
+    pub hashed_chunks: usize,
+    pub distinct_contents: usize,
+    /// Similarity hashes shared by different contents.
+    pub colliding_hashes: usize,
+    /// Distinct contents sharing their similarity hash with other contents.
+    pub colliding_contents: usize,
+}
+
+impl HashCollisions {
+    /// Share of distinct contents which collide with others.
+    pub fn rate(&self) -> f64 {
+        match self.distinct_contents {
+            0 => 0.0,
+            distinct_contents => self.colliding_contents as f64 / distinct_contents as f64,
+        }
+    }
+}
+
+/// Digests of the contents hashed under every similarity hash, counted into
+/// [`HashCollisions`] as the batches of a scrub are clustered.
+#[derive(Default)]
+pub(crate) struct CollisionCounter {
+    contents: HashMap<u32, HashSet<u64>>,
+    hashed_chunks: usize,
+}
+
+impl CollisionCounter {
+    pub fn add(&mut self, hash: u32, data: &[u8]) {
+        let mut hasher = DefaultHasher::new();
+        data.hash(&mut hasher);
+        self.contents
+            .entry(hash)
+            .or_default()
+            .insert(hasher.finish());
+        self.hashed_chunks += 1;
+    }
+
+    pub fn count(&self) -> HashCollisions {
+        let colliding = self.contents.values().filter(|contents| contents.len() > 1);
+        HashCollisions {
+            hashed_chunks: self.hashed_chunks,
+            distinct_contents: self.contents.values().map(HashSet::len).sum(),
+            colliding_hashes: colliding.clone().count(),
+            colliding_contents: colliding.map(HashSet::len).sum(),
+        }
+    }
+}
+
+fn empty_size_buckets() -> [SizeBucket; SIZE_BUCKET_LIMITS.len() + 1] {
+    std::array::from_fn(|bucket| SizeBucket {
+        min_size: bucket
+            .checked_sub(1)
+            .map_or(0, |bucket| SIZE_BUCKET_LIMITS[bucket]),
+        max_size: SIZE_BUCKET_LIMITS.get(bucket).copied(),
+        ..SizeBucket::default()
+    })
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct EncodeStatistics {
+    pub data_left: usize,
+    pub processed_data: usize,
+    pub delta_original_bytes: usize,
+    pub fallback_simple_count: usize,
+    pub untouched_chunk_count: usize,
+    pub skipped_chunk_count: usize,
+    pub size_buckets: [SizeBucket; SIZE_BUCKET_LIMITS.len() + 1],
+    /// Encoded clusters in the order they were encoded.
+    pub clusters: Vec<ClusterStatistics>,
+}
+
+impl Default for EncodeStatistics {
+    fn default() -> Self {
+        EncodeStatistics {
+            data_left: 0,
+            processed_data: 0,
+            delta_original_bytes: 0,
+            fallback_simple_count: 0,
+            untouched_chunk_count: 0,
+            skipped_chunk_count: 0,
+            size_buckets: empty_size_buckets(),
+            clusters: Vec::new(),
         }
-        if target_map.contains(&SBCHash {
-            key: right,
-            chunk_type: ChunkType::Simple,
-        }) {
-            right = right.saturating_add(1);
+    }
+}
+
+impl EncodeStatistics {
+    fn add_to_size_bucket(&mut self, original_bytes: usize, stored_bytes: usize, time: Duration) {
+        let bucket = SIZE_BUCKET_LIMITS
+            .iter()
+            .take_while(|&&limit| original_bytes >= limit)
+            .count();
+        let bucket = &mut self.size_buckets[bucket];
+        bucket.chunk_count += 1;
+        bucket.original_bytes += original_bytes;
+        bucket.stored_bytes += stored_bytes;
+        bucket.encode_time += time;
+    }
+
+    fn add_simple(&mut self, stored_bytes: usize) {
+        self.data_left += stored_bytes;
+    }
+
+    fn add_delta_outcome(&mut self, outcome: &EncodeOutcome) {
+        if outcome.fallback_simple {
+            self.data_left += outcome.stored_bytes;
+            self.fallback_simple_count += 1;
         } else {
-            return right;
+            self.processed_data += outcome.stored_bytes;
+            self.delta_original_bytes += outcome.original_bytes;
+        }
+    }
+
+    pub fn merge(&mut self, other: &EncodeStatistics) {
+        self.data_left += other.data_left;
+        self.processed_data += other.processed_data;
+        self.delta_original_bytes += other.delta_original_bytes;
+        self.fallback_simple_count += other.fallback_simple_count;
+        self.untouched_chunk_count += other.untouched_chunk_count;
+        self.skipped_chunk_count += other.skipped_chunk_count;
+        for (bucket, other_bucket) in self.size_buckets.iter_mut().zip(other.size_buckets.iter()) {
+            bucket.chunk_count += other_bucket.chunk_count;
+            bucket.original_bytes += other_bucket.original_bytes;
+            bucket.stored_bytes += other_bucket.stored_bytes;
+            bucket.encode_time += other_bucket.encode_time;
         }
+        self.clusters.extend_from_slice(&other.clusters);
     }
 }
 
-fn encode_simple_chunk(target_map: &mut SBCMap, data: &[u8], hash: u32) -> (usize, SBCHash) {
-    let sbc_hash = SBCHash {
-        key: find_empty_cell(target_map, hash),
-        chunk_type: ChunkType::Simple,
-    };
+/// Settings of a scrub applied to every cluster.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct EncodeSettings {
+    pub filter: SimilarityFilter,
+    pub budget: ScrubBudget,
+    pub preprocessing: Preprocessing,
+    pub zstd_level: Option<i32>,
+    pub checkpoint: Option<Checkpoint>,
+    pub parent_digest: bool,
+    pub content_hasher: Option<ContentHasher>,
+    pub max_delta_fraction: Option<f64>,
+    pub max_children: Option<usize>,
+    pub routing: Option<RoutingTable>,
+    /// Threads computing the deltas of a cluster, `0` and `1` compute them on
+    /// the current thread.
+    pub encoding_threads: usize,
+}
 
-    let _ = target_map.insert(sbc_hash.clone(), data.to_vec());
-    (data.len(), sbc_hash)
+/// Parent of a cluster with the number of delta chunks attached to it, so
+/// chunks joining the cluster later are encoded against it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct ClusterParent {
+    pub sbc_hash: SBCHash,
+    pub children: usize,
 }
 
+/// Limits of a single scrub, clusters left after the budget is exhausted stay untouched.
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct ScrubBudget {
+    /// Wall-clock time since the start of the scrub.
+    pub time: Option<Duration>,
+    /// Size of the chunks encoded during the scrub.
+    pub bytes: Option<usize>,
+}
+
+impl ScrubBudget {
+    fn is_exhausted(&self, time_start: Instant, processed_bytes: usize) -> bool {
+        self.time.is_some_and(|time| time_start.elapsed() >= time)
+            || self.bytes.is_some_and(|bytes| processed_bytes >= bytes)
+    }
+}
+
+#[cfg(test)]
 fn encode_delta_chunk(
     target_map: &mut SBCMap,
     data: &[u8],
     hash: u32,
     parent_data: &[u8],
-    parent_hash: u32,
-) -> (usize, usize, SBCHash) {
-    let number_delta_chunk = count_delta_chunks_with_hash(target_map, hash);
-    let sbc_hash = SBCHash {
-        key: hash,
-        chunk_type: ChunkType::Delta(number_delta_chunk),
-    };
-    let mut delta_chunk = Vec::new();
-    for byte in parent_hash.to_be_bytes() {
-        delta_chunk.push(byte);
+    parent_hash: &SBCHash,
+) -> (EncodeOutcome, SBCHash) {
+    encode_delta_chunk_with_fallback(
+        target_map,
+        data,
+        hash,
+        parent_data,
+        parent_hash,
+        &EncodeSettings::default(),
+    )
+}
+
+#[cfg(test)]
+pub(crate) fn encode_delta_chunk_with_fallback(
+    target_map: &mut SBCMap,
+    data: &[u8],
+    hash: u32,
+    parent_data: &[u8],
+    parent_hash: &SBCHash,
+    settings: &EncodeSettings,
+) -> (EncodeOutcome, SBCHash) {
+    let delta_chunk = delta_chunk(data, parent_data, parent_hash, settings);
+    store_delta_chunk(target_map, data, hash, delta_chunk, settings)
+}
+
+/// Encodes the chunk with Levenshtein actions or, when they are too long and
+/// `zstd_level` is set, with zstd using the parent as a dictionary. Deltas may
+/// take at most `max_delta_fraction` of the chunk size, by default all of it.
+/// With routing, chunks routed to zstd skip Levenshtein actions and skipped
+/// chunks are stored as simple ones, for which `None` is returned.
+///
+/// The delta is stored apart by [`store_delta_chunk`], so the parent can be
+/// borrowed from the map instead of copied.
+pub(crate) fn delta_chunk(
+    data: &[u8],
+    parent_data: &[u8],
+    parent_hash: &SBCHash,
+    settings: &EncodeSettings,
+) -> Option<Vec<u8>> {
+    let mut delta_chunk = parent_ref::header(parent_hash);
+    if settings.parent_digest {
+        delta_chunk.extend(parent_digest::header(parent_data));
     }
 
-    match levenshtein_functions::encode(data, parent_data) {
+    let max_len = settings.max_delta_fraction.map_or(data.len(), |fraction| {
+        (data.len() as f64 * fraction) as usize
+    });
+    let route = settings
+        .routing
+        .as_ref()
+        .map_or(Route::Delta(Codec::Levenshtein), |routing| {
+            routing.route(data)
+        });
+    let (delta_code, zstd_level) = match route {
+        Route::Delta(Codec::Levenshtein) => (
+            levenshtein_functions::encode_within(data, parent_data, max_len).map(|delta_code| {
+                delta_code
+                    .into_iter()
+                    .flat_map(u32::to_be_bytes)
+                    .collect::<Vec<u8>>()
+            }),
+            settings.zstd_level,
+        ),
+        Route::Delta(Codec::Zstd) => (
+            None,
+            Some(settings.zstd_level.unwrap_or(DEFAULT_ZSTD_LEVEL)),
+        ),
+        Route::Skip => (None, None),
+    };
+    let delta_code =
+        delta_code.or_else(|| zstd_ref::encode(data, parent_data, zstd_level?, max_len))?;
+    delta_chunk.extend_from_slice(delta_code.as_slice());
+    Some(delta_chunk)
+}
+
+/// Stores the result of [`delta_chunk`], or `data` as a simple chunk when
+/// there is no delta.
+pub(crate) fn store_delta_chunk(
+    target_map: &mut SBCMap,
+    data: &[u8],
+    hash: u32,
+    delta_chunk: Option<Vec<u8>>,
+    settings: &EncodeSettings,
+) -> (EncodeOutcome, SBCHash) {
+    match delta_chunk {
         None => {
-            let (data_left, sbc_hash) = encode_simple_chunk(target_map, data, hash);
-            (data_left, 0, sbc_hash)
+            let (stored_bytes, sbc_hash) = encode_new_simple_chunk(
+                target_map,
+                data,
+                hash,
+                settings.content_hasher,
+                settings.preprocessing,
+            );
+            let outcome = EncodeOutcome {
+                original_bytes: data.len(),
+                stored_bytes,
+                fallback_simple: true,
+            };
+            (outcome, sbc_hash)
         }
-        Some(delta_code) => {
-            for delta_action in delta_code {
-                for byte in delta_action.to_be_bytes() {
-                    delta_chunk.push(byte);
-                }
-            }
-            let processed_data = delta_chunk.len();
+        Some(delta_chunk) => {
+            let sbc_hash = SBCHash {
+                key: hash,
+                chunk_type: ChunkType::Delta(next_chunk_number(target_map, hash, ChunkType::Delta)),
+            };
+            let outcome = EncodeOutcome {
+                original_bytes: data.len(),
+                stored_bytes: delta_chunk.len(),
+                fallback_simple: false,
+            };
             let _ = target_map.insert(sbc_hash.clone(), delta_chunk);
-            (0, processed_data, sbc_hash)
+            (outcome, sbc_hash)
         }
     }
 }
 
-fn encode_cluster(
+/// Deltas of the chunks of a cluster against its parent, computed on the
+/// encoding threads before the chunks are stored one by one. `None` for chunks
+/// computed on the current thread: the parent and chunks without data.
+fn deltas_ahead<C: ChunkContainer>(
+    cluster: &[(u32, &mut C)],
+    parent_id: Option<usize>,
+    parent_data: &[u8],
+    parent_hash: &SBCHash,
+    settings: &EncodeSettings,
+) -> Vec<Option<Option<Vec<u8>>>> {
+    if settings.encoding_threads <= 1 || cluster.len() < 2 || cfg!(feature = "no-parallel") {
+        return Vec::new();
+    }
+    let chunks_data: Vec<Option<&[u8]>> = cluster
+        .iter()
+        .enumerate()
+        .map(|(chunk_id, (_, data_container))| {
+            data_container
+                .chunk_data()
+                .filter(|_| Some(chunk_id) != parent_id)
+        })
+        .collect();
+    let part_len = chunks_data.len().div_ceil(settings.encoding_threads);
+    thread::scope(|scope| {
+        let parts: Vec<_> = chunks_data
+            .chunks(part_len)
+            .map(|part| {
+                scope.spawn(move || {
+                    part.iter()
+                        .map(|data| {
+                            let data = (*data)?;
+                            // Chunks the filter rejects are stored as simple ones anyway.
+                            Some(
+                                settings
+                                    .filter
+                                    .should_delta_encode(data, parent_data)
+                                    .then(|| delta_chunk(data, parent_data, parent_hash, settings))
+                                    .flatten(),
+                            )
+                        })
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        parts
+            .into_iter()
+            .flat_map(|part| part.join().unwrap())
+            .collect()
+    })
+}
+
+/// Encodes the cluster against `stored_parent` when it is stored with the
+/// preprocessing of the scrub, and against its first chunk otherwise. Returns
+/// the parent the next chunks of the cluster are to be encoded against.
+fn encode_cluster<C: ChunkContainer>(
     target_map: &mut SBCMap,
-    cluster: &mut [(u32, &mut DataContainer<SBCHash>)],
-) -> (usize, usize) {
-    let mut data_left = 0;
-    let mut processed_data = 0;
-    let count_chunks_in_cluster = cluster.len();
-    let (parent_id, not_delta_encoded) = (0, Option::<HashSet<usize>>::None); //find_parent_chunk_in_cluster(cluster);
-    let (parent_hash, parent_data_container) = &mut cluster[parent_id];
-    let parent_data = match parent_data_container.extract() {
-        Data::Chunk(data) => data.clone(),
-        Data::TargetChunk(_) => {
-            panic!()
+    cluster: &mut [(u32, &mut C)],
+    stored_parent: Option<&ClusterParent>,
+    settings: &EncodeSettings,
+) -> (EncodeStatistics, Option<ClusterParent>) {
+    let mut statistics = EncodeStatistics::default();
+    let not_delta_encoded = Option::<HashSet<usize>>::None; //find_parent_chunk_in_cluster(cluster);
+
+    let stored_parent = stored_parent
+        .filter(
+            |ClusterParent {
+                 sbc_hash: parent_hash,
+                 ..
+             }| {
+                target_map
+                    .preprocessing
+                    .get(parent_hash)
+                    .copied()
+                    .unwrap_or_default()
+                    == settings.preprocessing
+            },
+        )
+        .filter(|parent| target_map.stored_value(&parent.sbc_hash).is_some());
+    // The parent is looked up in the map for every chunk instead of being held,
+    // so memory-mapped parents are borrowed rather than copied.
+    let (parent_id, mut parent_sbc_hash, parent_len, parent_stored_bytes) = match stored_parent {
+        Some(parent) => (None, parent.sbc_hash.clone(), 0, 0),
+        None => {
+            // Containers without data already refer to stored chunks, the first
+            // one with data becomes the parent.
+            let Some(parent_id) = cluster
+                .iter()
+                .position(|(_, container)| container.chunk_data().is_some())
+            else {
+                return (statistics, None);
+            };
+            let (parent_hash, parent_data_container) = &mut cluster[parent_id];
+            let Some(data) = parent_data_container.chunk_data() else {
+                return (statistics, None);
+            };
+            let encode_start = Instant::now();
+            let (left, parent_sbc_hash) = store_simple_chunk(
+                target_map,
+                &**parent_data_container,
+                data,
+                *parent_hash,
+                settings,
+            );
+            if target_map.stored_value(&parent_sbc_hash).is_none() {
+                // The map rejected the parent, e.g. because its quota is exhausted.
+                for (_, data_container) in cluster.iter() {
+                    if let Some(data) = data_container.chunk_data() {
+                        statistics.data_left += data.len();
+                        statistics.untouched_chunk_count += 1;
+                    }
+                }
+                return (statistics, None);
+            }
+            let parent_len = data.len();
+            statistics.add_simple(left);
+            statistics.add_to_size_bucket(parent_len, left, encode_start.elapsed());
+            target_map.set_preprocessing(parent_sbc_hash.clone(), settings.preprocessing);
+            parent_data_container.set_target(parent_sbc_hash.clone());
+            (Some(parent_id), parent_sbc_hash, parent_len, left)
         }
     };
-
-    if count_chunks_in_cluster > 5 {
-        print!("parent hash for cluster: {} ", parent_hash.clone());
-        println!("count chunks in cluster {}", count_chunks_in_cluster);
-    }
-    let (left, parent_sbc_hash) =
-        encode_simple_chunk(target_map, parent_data.as_slice(), *parent_hash);
-    let parent_hash = parent_sbc_hash.key;
-    data_left += left;
-    parent_data_container.make_target(vec![parent_sbc_hash]);
+    let mut cluster_statistics = ClusterStatistics {
+        parent: parent_sbc_hash.clone(),
+        chunk_count: parent_id.map_or(0, |_| 1),
+        delta_chunk_count: 0,
+        original_bytes: parent_len,
+        stored_bytes: parent_stored_bytes,
+    };
+    let mut children = stored_parent.map_or(0, |parent| parent.children);
+    let mut deltas_ahead = deltas_ahead(
+        cluster,
+        parent_id,
+        target_map
+            .stored_value(&parent_sbc_hash)
+            .unwrap_or_default(),
+        &parent_sbc_hash,
+        settings,
+    );
 
     for (chunk_id, (hash, data_container)) in cluster.iter_mut().enumerate() {
-        if chunk_id == parent_id {
+        if Some(chunk_id) == parent_id {
             continue;
         }
-        let mut target_hash = SBCHash::default();
-        match data_container.extract() {
-            Data::Chunk(data) => {
-                if match not_delta_encoded.clone() {
-                    None => false,
-                    Some(set) => set.contains(&chunk_id),
-                } || data.len().abs_diff(parent_data.len()) > 4000
-                {
-                    let (left, sbc_hash) = encode_simple_chunk(target_map, data, *hash);
-                    data_left += left;
-                    target_hash = sbc_hash;
-                } else {
-                    println!(
-                        "len1: {}; len2: {}, hash: {}; parent_hash: {}",
-                        data.len(),
-                        parent_data.len(),
-                        hash,
-                        parent_hash
-                    );
-                    let (left, processed, sbc_hash) = encode_delta_chunk(
-                        target_map,
-                        data,
-                        *hash,
-                        parent_data.as_slice(),
-                        parent_hash,
-                    );
-                    data_left += left;
-                    processed_data += processed;
-                    target_hash = sbc_hash;
-                }
-            }
-            Data::TargetChunk(_) => {}
+        let Some(data) = data_container.chunk_data() else {
+            continue;
+        };
+        let encode_start = Instant::now();
+        let parent_data = target_map
+            .stored_value(&parent_sbc_hash)
+            .unwrap_or_default();
+        let similar = !match not_delta_encoded.clone() {
+            None => false,
+            Some(set) => set.contains(&chunk_id),
+        } && settings.filter.should_delta_encode(data, parent_data);
+        let promote_to_parent = similar && settings.max_children.is_some_and(|max| children >= max);
+        let (sbc_hash, delta_outcome, stored_bytes) = if !similar || promote_to_parent {
+            let (left, sbc_hash) =
+                store_simple_chunk(target_map, &**data_container, data, *hash, settings);
+            (sbc_hash, None, left)
+        } else {
+            let delta_chunk = match deltas_ahead.get_mut(chunk_id).and_then(Option::take) {
+                Some(delta_chunk) => delta_chunk,
+                None => delta_chunk(data, parent_data, &parent_sbc_hash, settings),
+            };
+            let (outcome, sbc_hash) =
+                store_delta_chunk(target_map, data, *hash, delta_chunk, settings);
+            let stored_bytes = outcome.stored_bytes;
+            (sbc_hash, Some(outcome), stored_bytes)
+        };
+        if target_map.stored_value(&sbc_hash).is_none() {
+            statistics.data_left += data.len();
+            statistics.untouched_chunk_count += 1;
+            continue;
+        }
+        match &delta_outcome {
+            None => statistics.add_simple(stored_bytes),
+            Some(outcome) => statistics.add_delta_outcome(outcome),
         }
-        data_container.make_target(vec![target_hash]);
+        cluster_statistics.chunk_count += 1;
+        cluster_statistics.original_bytes += data.len();
+        cluster_statistics.stored_bytes += stored_bytes;
+        if delta_outcome.is_some_and(|outcome| !outcome.fallback_simple) {
+            children += 1;
+            cluster_statistics.delta_chunk_count += 1;
+        } else if promote_to_parent {
+            parent_sbc_hash = sbc_hash.clone();
+            children = 0;
+            // Deltas computed ahead refer to the previous parent.
+            deltas_ahead.clear();
+        }
+        statistics.add_to_size_bucket(data.len(), stored_bytes, encode_start.elapsed());
+        target_map.set_preprocessing(sbc_hash.clone(), settings.preprocessing);
+        data_container.set_target(sbc_hash);
     }
-    (data_left, processed_data)
+    statistics.clusters.push(cluster_statistics);
+    let parent = ClusterParent {
+        sbc_hash: parent_sbc_hash,
+        children,
+    };
+    (statistics, Some(parent))
 }
 
 #[allow(dead_code)]
-fn find_parent_chunk_in_cluster(
-    cluster: &[(u32, &mut DataContainer<SBCHash>)],
+fn find_parent_chunk_in_cluster<C: ChunkContainer>(
+    cluster: &[(u32, &mut C)],
+    filter: &SimilarityFilter,
 ) -> (usize, Option<HashSet<usize>>) {
     if cluster.len() == 1 {
         return (0, None);
@@ -162,66 +702,278 @@ fn find_parent_chunk_in_cluster(
     let mut parent_id = 0;
 
     for (chunk_id_1, (_, data_container_1)) in cluster.iter().enumerate() {
-        match data_container_1.extract() {
-            Data::Chunk(data_1) => {
-                let mut sum_dist_for_chunk = data_1.len() as u32;
-                for (chunk_id_2, (_, data_container_2)) in cluster.iter().enumerate() {
-                    match data_container_2.extract() {
-                        Data::Chunk(data_2) => {
-                            if chunk_id_1 == chunk_id_2 {
-                                continue;
-                            }
-                            if data_1.len().abs_diff(data_2.len()) > 4000 {
-                                let not_delta_encode_hashes =
-                                    not_delta_encoded.entry(chunk_id_1).or_default();
-                                not_delta_encode_hashes.insert(chunk_id_2);
-                                sum_dist_for_chunk += data_2.len() as u32;
-                            } else {
-                                let levenshtein_dist = levenshtein_distance(
-                                    (*data_1).as_slice(),
-                                    (*data_2).as_slice(),
-                                );
-                                if levenshtein_dist * 4 >= data_1.len() as u32 {
-                                    let not_delta_encode_hashes =
-                                        not_delta_encoded.entry(chunk_id_1).or_default();
-                                    not_delta_encode_hashes.insert(chunk_id_2);
-                                    sum_dist_for_chunk += data_2.len() as u32;
-                                } else {
-                                    sum_dist_for_chunk += levenshtein_dist;
-                                }
-                            }
-                        }
-                        Data::TargetChunk(_) => {}
-                    }
-                }
-                if sum_dist_for_chunk < min_sum_dist {
-                    min_sum_dist = sum_dist_for_chunk;
-                    parent_id = chunk_id_1;
+        let Some(data_1) = data_container_1.chunk_data() else {
+            continue;
+        };
+        let mut sum_dist_for_chunk = data_1.len() as u32;
+        for (chunk_id_2, (_, data_container_2)) in cluster.iter().enumerate() {
+            let Some(data_2) = data_container_2.chunk_data() else {
+                continue;
+            };
+            if chunk_id_1 == chunk_id_2 {
+                continue;
+            }
+            if !filter.sizes_are_close(data_1.len(), data_2.len()) {
+                let not_delta_encode_hashes = not_delta_encoded.entry(chunk_id_1).or_default();
+                not_delta_encode_hashes.insert(chunk_id_2);
+                sum_dist_for_chunk += data_2.len() as u32;
+            } else {
+                let levenshtein_dist = levenshtein_distance(data_1, data_2);
+                if levenshtein_dist * 4 >= data_1.len() as u32 {
+                    let not_delta_encode_hashes = not_delta_encoded.entry(chunk_id_1).or_default();
+                    not_delta_encode_hashes.insert(chunk_id_2);
+                    sum_dist_for_chunk += data_2.len() as u32;
+                } else {
+                    sum_dist_for_chunk += levenshtein_dist;
                 }
             }
-            Data::TargetChunk(_) => {}
+        }
+        if sum_dist_for_chunk < min_sum_dist {
+            min_sum_dist = sum_dist_for_chunk;
+            parent_id = chunk_id_1;
         }
     }
     (parent_id, not_delta_encoded.get(&parent_id).cloned())
 }
 
-pub(crate) fn encode_clusters(
-    clusters: &mut HashMap<u32, Vec<(u32, &mut DataContainer<SBCHash>)>>,
-    target_map: &mut SBCMap,
-) -> (usize, usize) {
-    let mut data_left = 0;
-    let mut processed_data = 0;
-    for (_, cluster) in clusters.iter_mut() {
-        let data_analyse = encode_cluster(target_map, cluster.as_mut_slice());
-        data_left += data_analyse.0;
-        processed_data += data_analyse.1;
+pub(crate) fn refine_clusters<C: ChunkContainer>(
+    clusters: Vec<KeyedCluster<C>>,
+    min_resemblance: f64,
+) -> Vec<KeyedCluster<C>> {
+    let mut refined_clusters = Vec::with_capacity(clusters.len());
+    for (key, cluster) in clusters {
+        if cluster.len() == 1 {
+            refined_clusters.push((key, cluster));
+            continue;
+        }
+        let sketches: Vec<MinHashSketch> = cluster
+            .iter()
+            .map(|(_, data_container)| {
+                MinHashSketch::new(data_container.chunk_data().unwrap_or(&[]))
+            })
+            .collect();
+        let groups = group_by_resemblance(sketches.as_slice(), min_resemblance);
+
+        let mut chunks: Vec<Option<(u32, &mut C)>> = cluster.into_iter().map(Some).collect();
+        for group in groups {
+            refined_clusters.push((
+                key,
+                group
+                    .into_iter()
+                    .filter_map(|chunk_id| chunks[chunk_id].take())
+                    .collect(),
+            ));
+        }
+    }
+    refined_clusters
+}
+
+fn hashes_and_sizes<C: ChunkContainer>(cluster: &[(u32, &mut C)]) -> Vec<(u32, usize)> {
+    cluster
+        .iter()
+        .map(|(hash, data_container)| (*hash, data_container.chunk_data().map_or(0, <[u8]>::len)))
+        .collect()
+}
+
+/// Estimates savings of delta encoding a cluster against its first chunk: sizes of
+/// the other chunks weighted by the proximity of their hashes to the parent hash.
+fn estimated_savings(hashes_and_sizes: &[(u32, usize)]) -> usize {
+    let parent_hash = match hashes_and_sizes.first() {
+        None => return 0,
+        Some((hash, _)) => *hash,
+    };
+    let max_dist = MAX_WEIGHT_EDGE as usize + 1;
+    hashes_and_sizes
+        .iter()
+        .skip(1)
+        .map(|(hash, size)| {
+            let dist = std::cmp::min(hash.abs_diff(parent_hash), MAX_WEIGHT_EDGE) as usize;
+            size * (max_dist - dist) / max_dist
+        })
+        .sum()
+}
+
+/// Orders clusters by estimated savings, so a budget is spent on the best ones.
+pub(crate) fn sort_clusters<C: ChunkContainer>(clusters: &mut [KeyedCluster<C>]) {
+    clusters.sort_by_cached_key(|(_, cluster)| {
+        std::cmp::Reverse(estimated_savings(hashes_and_sizes(cluster).as_slice()))
+    });
+}
+
+/// Encodes sorted clusters one at a time, keeping track of the budget and
+/// checkpoints across them.
+pub(crate) struct ClusterEncoder<'a> {
+    settings: &'a EncodeSettings,
+    time_start: Instant,
+    statistics: EncodeStatistics,
+    processed_bytes: usize,
+    checkpoint_bytes: usize,
+    /// Parents of the clusters encoded so far and of seeded ones, by key.
+    parents: HashMap<u32, ClusterParent>,
+    /// Whether clusters with the same key continue each other, which is not
+    /// the case for clusters split by resemblance.
+    continue_clusters: bool,
+}
+
+impl<'a> ClusterEncoder<'a> {
+    /// Encoder whose clusters with a key of `seeded_parents` are encoded
+    /// against the given stored chunk.
+    pub fn new(
+        settings: &'a EncodeSettings,
+        time_start: Instant,
+        seeded_parents: HashMap<u32, SBCHash>,
+        continue_clusters: bool,
+    ) -> ClusterEncoder<'a> {
+        let parents = seeded_parents
+            .into_iter()
+            .map(|(key, sbc_hash)| {
+                (
+                    key,
+                    ClusterParent {
+                        sbc_hash,
+                        children: 0,
+                    },
+                )
+            })
+            .collect();
+        ClusterEncoder {
+            settings,
+            time_start,
+            statistics: EncodeStatistics::default(),
+            processed_bytes: 0,
+            checkpoint_bytes: 0,
+            parents,
+            continue_clusters,
+        }
+    }
+
+    /// Encodes the clusters with parents chosen by the caller first, then the
+    /// others by estimated savings.
+    pub fn encode_all<C: ChunkContainer>(
+        &mut self,
+        clusters: &mut [KeyedCluster<C>],
+        hinted_clusters: &mut [HintedCluster<C>],
+        target_map: &mut SBCMap,
+    ) -> Result<()> {
+        sort_clusters(clusters);
+        for (parent_hash, cluster) in hinted_clusters.iter_mut() {
+            self.encode_against(cluster, Some(parent_hash), target_map)?;
+        }
+        for (key, cluster) in clusters.iter_mut() {
+            self.encode(*key, cluster, target_map)?;
+        }
+        Ok(())
+    }
+
+    /// Encodes the cluster against the parent of the cluster with the same key
+    /// encoded before, e.g. in an earlier batch, or seeded, if any.
+    pub fn encode<C: ChunkContainer>(
+        &mut self,
+        key: u32,
+        cluster: &mut Cluster<C>,
+        target_map: &mut SBCMap,
+    ) -> Result<()> {
+        let stored_parent = self.parents.get(&key).cloned();
+        let parent = self.encode_with(cluster, stored_parent.as_ref(), target_map)?;
+        if let Some(parent) = parent.filter(|_| self.continue_clusters) {
+            self.parents.insert(key, parent);
+        }
+        Ok(())
+    }
+
+    pub fn encode_against<C: ChunkContainer>(
+        &mut self,
+        cluster: &mut Cluster<C>,
+        stored_parent: Option<&SBCHash>,
+        target_map: &mut SBCMap,
+    ) -> Result<()> {
+        let stored_parent = stored_parent.map(|sbc_hash| ClusterParent {
+            sbc_hash: sbc_hash.clone(),
+            children: 0,
+        });
+        self.encode_with(cluster, stored_parent.as_ref(), target_map)?;
+        Ok(())
+    }
+
+    fn encode_with<C: ChunkContainer>(
+        &mut self,
+        cluster: &mut Cluster<C>,
+        stored_parent: Option<&ClusterParent>,
+        target_map: &mut SBCMap,
+    ) -> Result<Option<ClusterParent>> {
+        let cluster_size: usize = hashes_and_sizes(cluster).iter().map(|(_, size)| size).sum();
+        if self
+            .settings
+            .budget
+            .is_exhausted(self.time_start, self.processed_bytes)
+        {
+            self.statistics.data_left += cluster_size;
+            self.statistics.untouched_chunk_count += cluster.len();
+            return Ok(None);
+        }
+        let (cluster_statistics, parent) = encode_cluster(
+            target_map,
+            cluster.as_mut_slice(),
+            stored_parent,
+            self.settings,
+        );
+        self.statistics.merge(&cluster_statistics);
+        self.processed_bytes += cluster_size;
+        if let Some(checkpoint) = &self.settings.checkpoint {
+            self.checkpoint_bytes += cluster_size;
+            if self.checkpoint_bytes >= checkpoint.interval_bytes {
+                target_map.save(checkpoint.path.as_path())?;
+                self.checkpoint_bytes = 0;
+            }
+        }
+        Ok(parent)
+    }
+
+    pub fn finish(self) -> EncodeStatistics {
+        self.statistics
     }
-    (data_left, processed_data)
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+
+    #[test]
+    fn test_scrub_budget() {
+        let time_start = Instant::now();
+        assert!(!ScrubBudget::default().is_exhausted(time_start, usize::MAX));
+
+        let bytes_budget = ScrubBudget {
+            bytes: Some(1024),
+            ..ScrubBudget::default()
+        };
+        assert!(!bytes_budget.is_exhausted(time_start, 1000));
+        assert!(bytes_budget.is_exhausted(time_start, 1024));
+
+        let time_budget = ScrubBudget {
+            time: Some(Duration::ZERO),
+            ..ScrubBudget::default()
+        };
+        assert!(time_budget.is_exhausted(time_start, 0));
+    }
+
+    #[test]
+    fn test_estimated_savings_excludes_parent() {
+        assert_eq!(estimated_savings(&[]), 0);
+        assert_eq!(estimated_savings(&[(7, 8192)]), 0);
+        assert_eq!(estimated_savings(&[(7, 8192), (7, 4000), (7, 100)]), 4100);
+    }
+
+    #[test]
+    fn test_estimated_savings_decrease_with_hash_distance() {
+        let close = estimated_savings(&[(100, 8192), (101, 8192)]);
+        let far = estimated_savings(&[(100, 8192), (120, 8192)]);
+        let too_far = estimated_savings(&[(100, 8192), (1000, 8192)]);
+
+        assert!(close > far);
+        assert!(far > too_far);
+        assert!(too_far > 0);
+    }
     #[test]
     fn test_restore_similarity_chunk_1_byte_diff() {
         let mut data: Vec<u8> = (0..8192).map(|_| rand::random::<u8>()).collect();
@@ -234,12 +986,12 @@ mod test {
         let mut sbc_map = SBCMap::new();
 
         let (_, sbc_hash) = encode_simple_chunk(&mut sbc_map, data.as_slice(), 0);
-        let (_, _, sbc_hash_2) = encode_delta_chunk(
+        let (_, sbc_hash_2) = encode_delta_chunk(
             &mut sbc_map,
             data2.as_slice(),
             3,
             data.as_slice(),
-            sbc_hash.key,
+            &sbc_hash,
         );
 
         assert_eq!(sbc_map.get(&sbc_hash_2).unwrap(), data2)
@@ -261,12 +1013,12 @@ mod test {
         let mut sbc_map = SBCMap::new();
 
         let (_, sbc_hash) = encode_simple_chunk(&mut sbc_map, data.as_slice(), 0);
-        let (_, _, sbc_hash_2) = encode_delta_chunk(
+        let (_, sbc_hash_2) = encode_delta_chunk(
             &mut sbc_map,
             data2.as_slice(),
             3,
             data.as_slice(),
-            sbc_hash.key,
+            &sbc_hash,
         );
 
         assert_eq!(sbc_map.get(&sbc_hash_2).unwrap(), data2)
@@ -289,12 +1041,12 @@ mod test {
         let mut sbc_map = SBCMap::new();
 
         let (_, sbc_hash) = encode_simple_chunk(&mut sbc_map, data.as_slice(), 0);
-        let (_, _, sbc_hash_2) = encode_delta_chunk(
+        let (_, sbc_hash_2) = encode_delta_chunk(
             &mut sbc_map,
             data2.as_slice(),
             3,
             data.as_slice(),
-            sbc_hash.key,
+            &sbc_hash,
         );
 
         assert_eq!(sbc_map.get(&sbc_hash_2).unwrap(), data2)
@@ -307,12 +1059,12 @@ mod test {
         let mut sbc_map = SBCMap::new();
 
         let (_, sbc_hash) = encode_simple_chunk(&mut sbc_map, data.as_slice(), 0);
-        let (_, _, sbc_hash_2) = encode_delta_chunk(
+        let (_, sbc_hash_2) = encode_delta_chunk(
             &mut sbc_map,
             data2.as_slice(),
             3,
             data.as_slice(),
-            sbc_hash.key,
+            &sbc_hash,
         );
 
         assert_eq!(sbc_map.get(&sbc_hash_2).unwrap(), data2)
@@ -325,12 +1077,12 @@ mod test {
         let mut sbc_map = SBCMap::new();
 
         let (_, sbc_hash) = encode_simple_chunk(&mut sbc_map, data.as_slice(), 0);
-        let (_, _, sbc_hash_2) = encode_delta_chunk(
+        let (_, sbc_hash_2) = encode_delta_chunk(
             &mut sbc_map,
             data2.as_slice(),
             3,
             data.as_slice(),
-            sbc_hash.key,
+            &sbc_hash,
         );
 
         assert_eq!(sbc_map.get(&sbc_hash_2).unwrap(), data2)
@@ -340,18 +1092,18 @@ mod test {
     fn test_restore_similarity_chunk_with_offset() {
         let data: Vec<u8> = (0..8192).map(|_| rand::random::<u8>()).collect();
         let mut data2 = data[15..8000].to_vec();
-        data2[0] = data2[0] / 3;
-        data2[7000] = data2[7000] / 3;
+        data2[0] /= 3;
+        data2[7000] /= 3;
 
         let mut sbc_map = SBCMap::new();
 
         let (_, sbc_hash) = encode_simple_chunk(&mut sbc_map, data.as_slice(), 0);
-        let (_, _, sbc_hash_2) = encode_delta_chunk(
+        let (_, sbc_hash_2) = encode_delta_chunk(
             &mut sbc_map,
             data2.as_slice(),
             3,
             data.as_slice(),
-            sbc_hash.key,
+            &sbc_hash,
         );
 
         assert_eq!(sbc_map.get(&sbc_hash_2).unwrap(), data2)
@@ -366,12 +1118,12 @@ mod test {
         let mut sbc_map = SBCMap::new();
 
         let (_, sbc_hash) = encode_simple_chunk(&mut sbc_map, data.as_slice(), 0);
-        let (_, _, sbc_hash_2) = encode_delta_chunk(
+        let (_, sbc_hash_2) = encode_delta_chunk(
             &mut sbc_map,
             data2.as_slice(),
             3,
             data.as_slice(),
-            sbc_hash.key,
+            &sbc_hash,
         );
         assert_ne!(data, []);
         assert_eq!(sbc_hash_2.chunk_type, ChunkType::Delta(0));
@@ -386,15 +1138,202 @@ mod test {
         let mut sbc_map = SBCMap::new();
 
         let (_, sbc_hash) = encode_simple_chunk(&mut sbc_map, data.as_slice(), 0);
-        let (_, _, sbc_hash_2) = encode_delta_chunk(
+        let (_, sbc_hash_2) = encode_delta_chunk(
             &mut sbc_map,
             data2.as_slice(),
             3,
             data.as_slice(),
-            sbc_hash.key,
+            &sbc_hash,
         );
         assert_ne!(data, []);
         assert_eq!(sbc_hash_2.chunk_type, ChunkType::Delta(0));
         assert_eq!(sbc_map.get(&sbc_hash_2).unwrap(), data2)
     }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn test_restore_similarity_chunk_from_mmap_storage() {
+        let mut data: Vec<u8> = (0..8192).map(|_| rand::random::<u8>()).collect();
+        let data2 = data.clone();
+        data[15] = data[15].wrapping_add(1);
+        let path = std::env::temp_dir().join(format!("sbc_clusterer_mmap_{}", std::process::id()));
+        let mut sbc_map = SBCMap::with_mmap_storage(&path).unwrap();
+
+        let (_, sbc_hash) = encode_simple_chunk(&mut sbc_map, data.as_slice(), 0);
+        let (_, sbc_hash_2) = encode_delta_chunk(
+            &mut sbc_map,
+            data2.as_slice(),
+            3,
+            data.as_slice(),
+            &sbc_hash,
+        );
+
+        assert_eq!(sbc_map.get(&sbc_hash).unwrap(), data);
+        assert_eq!(sbc_map.get(&sbc_hash_2).unwrap(), data2);
+        drop(sbc_map);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_encode_outcome_for_delta_and_fallback() {
+        let data: Vec<u8> = (0..8192).map(|_| rand::random::<u8>()).collect();
+        let mut data2 = data.clone();
+        data2[15] = data2[15].wrapping_add(1);
+        let noise: Vec<u8> = (0..8192).map(|_| rand::random::<u8>()).collect();
+        let mut sbc_map = SBCMap::new();
+
+        let (_, sbc_hash) = encode_simple_chunk(&mut sbc_map, data.as_slice(), 0);
+        let (outcome, _) = encode_delta_chunk(
+            &mut sbc_map,
+            data2.as_slice(),
+            3,
+            data.as_slice(),
+            &sbc_hash,
+        );
+        assert_eq!(
+            outcome,
+            EncodeOutcome {
+                original_bytes: 8192,
+                stored_bytes: 8,
+                fallback_simple: false,
+            }
+        );
+
+        let (outcome, sbc_hash_3) = encode_delta_chunk(
+            &mut sbc_map,
+            noise.as_slice(),
+            5,
+            data.as_slice(),
+            &sbc_hash,
+        );
+        assert!(outcome.fallback_simple);
+        assert_eq!(outcome.stored_bytes, 8192);
+        assert_eq!(sbc_hash_3.chunk_type, ChunkType::Simple(0));
+    }
+
+    #[test]
+    fn test_max_delta_fraction() {
+        let data: Vec<u8> = (0..8192).map(|_| rand::random::<u8>()).collect();
+        let mut data2 = data.clone();
+        for i in 0..100 {
+            data2[i * 80] = data2[i * 80].wrapping_add(1);
+        }
+        let mut sbc_map = SBCMap::new();
+        let (_, sbc_hash) = encode_simple_chunk(&mut sbc_map, data.as_slice(), 0);
+
+        let encode_with_fraction = |sbc_map: &mut SBCMap, fraction| {
+            let settings = EncodeSettings {
+                max_delta_fraction: Some(fraction),
+                ..EncodeSettings::default()
+            };
+            encode_delta_chunk_with_fallback(
+                sbc_map,
+                data2.as_slice(),
+                3,
+                data.as_slice(),
+                &sbc_hash,
+                &settings,
+            )
+            .0
+        };
+        assert!(encode_with_fraction(&mut sbc_map, 0.01).fallback_simple);
+        let outcome = encode_with_fraction(&mut sbc_map, 0.1);
+        assert!(!outcome.fallback_simple);
+        assert_eq!(outcome.stored_bytes, 4 + 100 * 4);
+    }
+
+    #[test]
+    fn test_max_children_per_parent() {
+        let data: Vec<u8> = (0..8192).map(|_| rand::random::<u8>()).collect();
+        let mut containers: Vec<DataContainer<SBCHash>> = (0..7)
+            .map(|i| {
+                let mut chunk = data.clone();
+                chunk[i * 1000] ^= 1;
+                DataContainer::from(chunk)
+            })
+            .collect();
+        let mut cluster: Vec<(u32, &mut DataContainer<SBCHash>)> = containers
+            .iter_mut()
+            .map(|container| (0, container))
+            .collect();
+        let settings = EncodeSettings {
+            max_children: Some(2),
+            ..EncodeSettings::default()
+        };
+        let mut sbc_map = SBCMap::new();
+        encode_cluster(&mut sbc_map, cluster.as_mut_slice(), None, &settings);
+
+        let targets: Vec<SBCHash> = containers
+            .iter()
+            .map(|container| container.target().unwrap()[0].clone())
+            .collect();
+        let is_simple: Vec<bool> = targets
+            .iter()
+            .map(|sbc_hash| matches!(sbc_hash.chunk_type, ChunkType::Simple(_)))
+            .collect();
+        assert_eq!(is_simple, [true, false, false, true, false, false, true]);
+        assert_eq!(sbc_map.parent_of(&targets[4]), Some(targets[3].clone()));
+        for (i, sbc_hash) in targets.iter().enumerate() {
+            let mut chunk = data.clone();
+            chunk[i * 1000] ^= 1;
+            assert_eq!(sbc_map.decode(sbc_hash).unwrap(), chunk);
+        }
+    }
+
+    #[test]
+    fn test_cluster_keeps_existing_targets() {
+        let data: Vec<u8> = (0..8192).map(|_| rand::random::<u8>()).collect();
+        let mut similar_data = data.clone();
+        similar_data[100] ^= 1;
+        let stored_hash = SBCHash {
+            key: 7,
+            chunk_type: ChunkType::Simple(0),
+        };
+        let mut containers: Vec<DataContainer<SBCHash>> =
+            [data.clone(), data.clone(), similar_data]
+                .into_iter()
+                .map(DataContainer::from)
+                .collect();
+        containers[0].make_target(vec![stored_hash.clone()]);
+        let mut cluster: Vec<(u32, &mut DataContainer<SBCHash>)> = containers
+            .iter_mut()
+            .map(|container| (0, container))
+            .collect();
+        let mut sbc_map = SBCMap::new();
+        encode_cluster(
+            &mut sbc_map,
+            cluster.as_mut_slice(),
+            None,
+            &EncodeSettings::default(),
+        );
+
+        assert_eq!(
+            containers[0].target(),
+            Some([stored_hash.clone()].as_slice())
+        );
+        assert!(!sbc_map.contains(&stored_hash));
+        let parent_hash = containers[1].target().unwrap()[0].clone();
+        let delta_hash = containers[2].target().unwrap()[0].clone();
+        assert_eq!(sbc_map.parent_of(&delta_hash), Some(parent_hash));
+    }
+
+    #[test]
+    fn test_hash_collisions() {
+        let chunks: [(u32, &[u8]); 5] = [(1, b"a"), (1, b"a"), (1, b"b"), (2, b"c"), (3, b"d")];
+        let mut counter = CollisionCounter::default();
+        for (hash, data) in chunks {
+            counter.add(hash, data);
+        }
+        let collisions = counter.count();
+        assert_eq!(
+            collisions,
+            HashCollisions {
+                hashed_chunks: 5,
+                distinct_contents: 4,
+                colliding_hashes: 1,
+                colliding_contents: 2,
+            }
+        );
+        assert_eq!(collisions.rate(), 0.5);
+    }
 }
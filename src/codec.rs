@@ -0,0 +1,80 @@
+/// Delta codecs of deltas stored by a scrub.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Codec {
+    /// Byte-level Levenshtein edit actions, see [`crate::encode_delta`].
+    Levenshtein,
+    /// zstd frames with the parent as a raw content dictionary, see
+    /// [`crate::SBCScrubber::with_zstd_fallback`].
+    Zstd,
+}
+
+/// Relative encoding speed of a codec.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SpeedClass {
+    Slow,
+    Medium,
+    Fast,
+}
+
+/// Capabilities of a [`Codec`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CodecInfo {
+    pub name: &'static str,
+    /// Largest chunk and parent the codec can encode, if limited.
+    pub max_chunk_len: Option<usize>,
+    /// Whether deltas can refer to earlier parts of the chunk itself.
+    pub self_reference: bool,
+    /// Whether deltas are entropy coded, so compressing them further gains little.
+    pub entropy_coded: bool,
+    pub speed: SpeedClass,
+    /// Whether the codec is compiled in, zstd needs the `zstd` feature.
+    pub available: bool,
+}
+
+impl Codec {
+    pub const ALL: [Codec; 2] = [Codec::Levenshtein, Codec::Zstd];
+
+    pub fn info(self) -> CodecInfo {
+        match self {
+            Codec::Levenshtein => CodecInfo {
+                name: "levenshtein",
+                max_chunk_len: Some(crate::levenshtein_functions::MAX_CHUNK_LEN),
+                self_reference: false,
+                entropy_coded: false,
+                speed: SpeedClass::Slow,
+                available: true,
+            },
+            Codec::Zstd => CodecInfo {
+                name: "zstd",
+                max_chunk_len: None,
+                self_reference: true,
+                entropy_coded: true,
+                speed: SpeedClass::Fast,
+                available: cfg!(feature = "zstd"),
+            },
+        }
+    }
+
+    /// Codecs compiled into this build.
+    pub fn available() -> impl Iterator<Item = Codec> {
+        Codec::ALL
+            .into_iter()
+            .filter(|codec| codec.info().available)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_available_codecs() {
+        let names: Vec<&str> = Codec::available().map(|codec| codec.info().name).collect();
+        if cfg!(feature = "zstd") {
+            assert_eq!(names, ["levenshtein", "zstd"]);
+        } else {
+            assert_eq!(names, ["levenshtein"]);
+        }
+    }
+}
@@ -0,0 +1,160 @@
+use crate::{
+    blake2b_content_hash, LengthAwareHasher, Preprocessing, Result, RoutingTable, SBCMap,
+    SBCScrubber, ScrubBudget, SimilarityFilter,
+};
+use std::path::PathBuf;
+
+/// Settings of a scrubber and its target map. With the `serde` feature it can be
+/// read from configuration files, missing fields take default values.
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct SbcConfig {
+    pub similarity_filter: SimilarityFilter,
+    /// See [`SBCScrubber::with_resemblance_refinement`].
+    pub min_resemblance: Option<f64>,
+    pub budget: ScrubBudget,
+    pub preprocessing: Preprocessing,
+    /// See [`SBCScrubber::with_hashing_threads`], `0` and `1` hash on the current thread.
+    pub hashing_threads: usize,
+    /// See [`SBCScrubber::with_encoding_threads`], `0` and `1` encode on the current thread.
+    pub encoding_threads: usize,
+    /// See [`SBCScrubber::with_pipeline_batch_len`].
+    pub pipeline_batch_len: Option<usize>,
+    /// See [`SBCScrubber::with_entropy_skip`].
+    pub max_entropy: Option<f64>,
+    /// See [`SBCScrubber::with_min_chunk_size`], `0` clusters chunks of any size.
+    pub min_chunk_size: usize,
+    /// See [`SBCScrubber::with_routing`].
+    pub routing: Option<RoutingTable>,
+    /// See [`SBCScrubber::with_length_aware_hashing`].
+    pub length_aware_hashing: Option<LengthAwareHasher>,
+    /// See [`SBCScrubber::with_max_delta_fraction`].
+    pub max_delta_fraction: Option<f64>,
+    /// See [`SBCScrubber::with_max_children_per_parent`].
+    pub max_children_per_parent: Option<usize>,
+    /// See [`SBCScrubber::with_parent_verification`].
+    pub parent_verification: bool,
+    /// Keys simple chunks by their BLAKE2b-256 hash, see [`SBCScrubber::with_content_addressing`].
+    pub content_addressing: bool,
+    /// See [`SBCScrubber::with_collision_widening`].
+    pub max_collision_rate: Option<f64>,
+    /// File for simple chunks, see [`SBCMap::with_mmap_storage`].
+    pub mmap_path: Option<PathBuf>,
+    /// See [`SBCMap::with_quota`].
+    pub quota: Option<usize>,
+}
+
+impl SbcConfig {
+    pub fn build_scrubber(&self) -> SBCScrubber {
+        let mut scrubber = SBCScrubber::new()
+            .with_similarity_filter(self.similarity_filter.clone())
+            .with_budget(self.budget.clone())
+            .with_preprocessing(self.preprocessing)
+            .with_hashing_threads(self.hashing_threads)
+            .with_encoding_threads(self.encoding_threads)
+            .with_min_chunk_size(self.min_chunk_size);
+        if let Some(min_resemblance) = self.min_resemblance {
+            scrubber = scrubber.with_resemblance_refinement(min_resemblance);
+        }
+        if let Some(batch_len) = self.pipeline_batch_len {
+            scrubber = scrubber.with_pipeline_batch_len(batch_len);
+        }
+        if let Some(max_entropy) = self.max_entropy {
+            scrubber = scrubber.with_entropy_skip(max_entropy);
+        }
+        if let Some(routing) = &self.routing {
+            scrubber = scrubber.with_routing(routing.clone());
+        }
+        if let Some(hasher) = self.length_aware_hashing {
+            scrubber = scrubber.with_length_aware_hashing(hasher);
+        }
+        if let Some(fraction) = self.max_delta_fraction {
+            scrubber = scrubber.with_max_delta_fraction(fraction);
+        }
+        if let Some(max_children) = self.max_children_per_parent {
+            scrubber = scrubber.with_max_children_per_parent(max_children);
+        }
+        if self.parent_verification {
+            scrubber = scrubber.with_parent_verification();
+        }
+        if self.content_addressing {
+            scrubber = scrubber.with_content_addressing(blake2b_content_hash);
+        }
+        if let Some(max_rate) = self.max_collision_rate {
+            scrubber = scrubber.with_collision_widening(max_rate);
+        }
+        scrubber
+    }
+
+    pub fn build_map(&self) -> Result<SBCMap> {
+        let map = match &self.mmap_path {
+            None => SBCMap::new(),
+            #[cfg(feature = "mmap")]
+            Some(path) => SBCMap::with_mmap_storage(path)?,
+            #[cfg(not(feature = "mmap"))]
+            Some(_) => {
+                return Err(crate::SbcError::Config(
+                    "mmap storage requires the `mmap` feature".to_string(),
+                ))
+            }
+        };
+        Ok(match self.quota {
+            None => map,
+            Some(quota) => map.with_quota(quota),
+        })
+    }
+
+    pub fn build(&self) -> Result<(SBCScrubber, SBCMap)> {
+        Ok((self.build_scrubber(), self.build_map()?))
+    }
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_config_from_toml() {
+        let config: SbcConfig = toml::from_str(
+            r#"
+            min_resemblance = 0.6
+            preprocessing = { IntegerDelta = { width = 4 } }
+
+            [similarity_filter]
+            max_size_difference = 1000
+            window_sample_count = 32
+
+            [budget]
+            bytes = 1048576
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.min_resemblance, Some(0.6));
+        assert_eq!(
+            config.preprocessing,
+            Preprocessing::IntegerDelta { width: 4 }
+        );
+        assert_eq!(config.similarity_filter.max_size_difference, 1000);
+        assert_eq!(config.similarity_filter.window_sample_count, 32);
+        assert_eq!(config.similarity_filter.sample_count, 0);
+        assert_eq!(config.budget.bytes, Some(1 << 20));
+        assert!(config.build().is_ok());
+    }
+
+    #[test]
+    fn test_config_round_trip() {
+        let config = SbcConfig {
+            min_resemblance: Some(0.5),
+            preprocessing: Preprocessing::ByteTranspose { width: 8 },
+            ..SbcConfig::default()
+        };
+        let text = toml::to_string(&config).unwrap();
+        let parsed: SbcConfig = toml::from_str(text.as_str()).unwrap();
+
+        assert_eq!(parsed.min_resemblance, config.min_resemblance);
+        assert_eq!(parsed.preprocessing, config.preprocessing);
+        assert!(toml::from_str::<SbcConfig>("").is_ok());
+    }
+}
@@ -0,0 +1,133 @@
+//! Cases every delta codec must restore exactly. A codec is a pair of functions
+//! `encode(data, parent) -> Option<delta>` and `decode(parent, delta) -> Option<data>`,
+//! registered with one line of [`conformance_tests`]. Encoders may refuse a case
+//! by returning `None`.
+
+use crate::clusterer::{encode_delta_chunk_with_fallback, EncodeSettings};
+use crate::{ChunkType, SBCHash, SBCMap};
+use chunkfs::Database;
+
+/// Deterministic xorshift generator, so failures are reproducible.
+struct Generator(u64);
+
+impl Generator {
+    fn bytes(&mut self, len: usize) -> Vec<u8> {
+        (0..len)
+            .map(|_| {
+                self.0 ^= self.0 << 13;
+                self.0 ^= self.0 >> 7;
+                self.0 ^= self.0 << 17;
+                self.0 as u8
+            })
+            .collect()
+    }
+}
+
+/// Pairs of a case name, the chunk and its parent.
+fn cases() -> Vec<(&'static str, Vec<u8>, Vec<u8>)> {
+    let mut generator = Generator(0x9e37_79b9_7f4a_7c15);
+    let parent = generator.bytes(2048);
+    let edit = |position: usize| {
+        let mut data = parent.clone();
+        data[position] ^= 0x5a;
+        data
+    };
+    let mut inserted = parent.clone();
+    inserted.insert(1000, 7);
+    let mut deleted = parent.clone();
+    deleted.remove(1000);
+    let duplicated = [&parent[..1024], &parent[512..]].concat();
+    let noise = generator.bytes(2048);
+    let shifted = [&generator.bytes(16)[..], &parent[..]].concat();
+    vec![
+        ("identical", parent.clone(), parent.clone()),
+        ("edit at start", edit(0), parent.clone()),
+        ("edit in the middle", edit(1024), parent.clone()),
+        ("edit at end", edit(2047), parent.clone()),
+        ("inserted byte", inserted, parent.clone()),
+        ("deleted byte", deleted, parent.clone()),
+        ("shifted", shifted, parent.clone()),
+        ("truncated head", parent[100..].to_vec(), parent.clone()),
+        ("truncated tail", parent[..1900].to_vec(), parent.clone()),
+        ("extended", parent.clone(), parent[..1900].to_vec()),
+        ("duplicated", duplicated, parent.clone()),
+        ("random noise", noise, parent.clone()),
+        ("empty chunk", Vec::new(), parent.clone()),
+        ("empty parent", parent.clone(), Vec::new()),
+        ("both empty", Vec::new(), Vec::new()),
+        ("single byte", vec![1], vec![2]),
+    ]
+}
+
+/// Generates a test checking that `$decode` restores every case encoded by `$encode`.
+macro_rules! conformance_tests {
+    ($name:ident, $encode:expr, $decode:expr) => {
+        #[test]
+        fn $name() {
+            let encode: fn(&[u8], &[u8]) -> Option<Vec<u8>> = $encode;
+            let decode: fn(&[u8], &[u8]) -> Option<Vec<u8>> = $decode;
+            let mut encoded_cases = 0;
+            for (case, data, parent) in cases() {
+                if let Some(delta) = encode(&data, &parent) {
+                    assert_eq!(decode(&parent, &delta).as_ref(), Some(&data), "{case}");
+                    encoded_cases += 1;
+                }
+            }
+            assert!(encoded_cases > 0);
+        }
+    };
+}
+
+conformance_tests!(
+    levenshtein,
+    |data, parent| crate::encode_delta(data, parent, 0),
+    |parent, delta| crate::decode_delta(parent, delta).ok()
+);
+
+#[cfg(feature = "zstd")]
+conformance_tests!(
+    zstd,
+    |data, parent| {
+        let delta = crate::zstd_ref::encode(data, parent, 3, usize::MAX)?;
+        Some([&[0; 4][..], &delta].concat())
+    },
+    crate::zstd_ref::decode
+);
+
+fn map_with_parent(parent: &[u8]) -> (SBCMap, SBCHash) {
+    let parent_hash = SBCHash {
+        key: 1,
+        chunk_type: ChunkType::Simple(0),
+    };
+    let mut map = SBCMap::new();
+    map.insert(parent_hash.clone(), parent.to_vec()).unwrap();
+    (map, parent_hash)
+}
+
+/// Deltas as stored in a map by a scrub, with the parent reference and digest.
+fn encode_stored_delta(data: &[u8], parent: &[u8]) -> Option<Vec<u8>> {
+    let (mut map, parent_hash) = map_with_parent(parent);
+    let settings = EncodeSettings {
+        zstd_level: cfg!(feature = "zstd").then_some(3),
+        parent_digest: true,
+        ..EncodeSettings::default()
+    };
+    let (outcome, delta_hash) =
+        encode_delta_chunk_with_fallback(&mut map, data, 2, parent, &parent_hash, &settings);
+    if outcome.fallback_simple {
+        return None;
+    }
+    map.stored_value(&delta_hash).map(<[u8]>::to_vec)
+}
+
+fn decode_stored_delta(parent: &[u8], delta: &[u8]) -> Option<Vec<u8>> {
+    let (mut map, _) = map_with_parent(parent);
+    let delta_hash = SBCHash {
+        key: 2,
+        chunk_type: ChunkType::Delta(0),
+    };
+    map.insert(delta_hash.clone(), delta.to_vec()).unwrap();
+    map.decode(&delta_hash).ok()
+}
+
+conformance_tests!(sbc_map, encode_stored_delta, decode_stored_delta);
@@ -0,0 +1,22 @@
+use crate::{ChunkType, SBCHash};
+use blake2::digest::consts::U32;
+use blake2::{Blake2b, Digest};
+
+pub(crate) const CONTENT_HASH_LEN: usize = 32;
+
+/// Strong hash of the stored data of a chunk, e.g. SHA-256 or BLAKE3.
+pub type ContentHasher = fn(&[u8]) -> [u8; CONTENT_HASH_LEN];
+
+/// BLAKE2b-256 of `data`, the [`ContentHasher`] available without other dependencies.
+pub fn blake2b_content_hash(data: &[u8]) -> [u8; CONTENT_HASH_LEN] {
+    Blake2b::<U32>::digest(data).into()
+}
+
+/// Key of a chunk addressed by its content hash `content_hash`. The numeric
+/// part of the key is taken from the hash, so it only identifies the chunk in errors.
+pub(crate) fn content_key(content_hash: [u8; CONTENT_HASH_LEN]) -> SBCHash {
+    SBCHash {
+        key: u32::from_be_bytes(content_hash[..4].try_into().unwrap()),
+        chunk_type: ChunkType::Content(content_hash),
+    }
+}
@@ -0,0 +1,170 @@
+use crate::levenshtein_functions::{get_delta_action, is_levenshtein_delta, Action};
+use crate::{parent_digest, parent_ref, zstd_ref, Result, SBCHash, SBCMap};
+
+/// Instructions met while decoding chunks, returned by
+/// [`SBCMap::decode_with_stats`] and summed by [`SBCMap::decode_stats`].
+/// Few copied bytes point at matching failures, many small copies at the
+/// overhead of Levenshtein actions, each of which is stored in 4 bytes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DecodeStats {
+    pub chunk_count: usize,
+    /// Stored size of the chunks, headers of deltas included.
+    pub stored_bytes: usize,
+    pub decoded_bytes: usize,
+    /// Bytes of chunks stored whole.
+    pub literal_bytes: usize,
+    /// Runs of bytes of Levenshtein deltas copied unchanged from the parent.
+    pub copies: usize,
+    pub copied_bytes: usize,
+    /// Bytes added by Levenshtein actions.
+    pub inserts: usize,
+    pub replaces: usize,
+    pub deletes: usize,
+    /// zstd deltas, which are not broken down into instructions.
+    pub zstd_chunks: usize,
+}
+
+impl DecodeStats {
+    pub fn average_copy_len(&self) -> f64 {
+        match self.copies {
+            0 => 0.0,
+            copies => self.copied_bytes as f64 / copies as f64,
+        }
+    }
+
+    /// Stored size of the Levenshtein actions.
+    pub fn instruction_bytes(&self) -> usize {
+        4 * (self.inserts + self.replaces + self.deletes)
+    }
+
+    fn add(&mut self, other: &DecodeStats) {
+        self.chunk_count += other.chunk_count;
+        self.stored_bytes += other.stored_bytes;
+        self.decoded_bytes += other.decoded_bytes;
+        self.literal_bytes += other.literal_bytes;
+        self.copies += other.copies;
+        self.copied_bytes += other.copied_bytes;
+        self.inserts += other.inserts;
+        self.replaces += other.replaces;
+        self.deletes += other.deletes;
+        self.zstd_chunks += other.zstd_chunks;
+    }
+}
+
+impl SBCMap {
+    /// Same as `get`, also returning the instructions the chunk was decoded from.
+    pub fn decode_with_stats(&self, sbc_hash: &SBCHash) -> Result<(Vec<u8>, DecodeStats)> {
+        let data = self.decode(sbc_hash)?;
+        let stored = self.stored_value(sbc_hash).unwrap_or_default();
+        let mut stats = DecodeStats {
+            chunk_count: 1,
+            stored_bytes: self.stored_len(sbc_hash).unwrap_or_default(),
+            decoded_bytes: data.len(),
+            ..DecodeStats::default()
+        };
+        match self.parent_of(sbc_hash) {
+            None => stats.literal_bytes = stored.len(),
+            Some(parent_hash) => {
+                let parent_len = self.stored_value(&parent_hash).map_or(0, <[u8]>::len);
+                let delta_chunk = parent_ref::split(stored)
+                    .and_then(|(_, delta_chunk)| {
+                        Some(parent_digest::split(&delta_chunk)?.1.to_vec())
+                    })
+                    .unwrap_or_default();
+                if zstd_ref::is_zstd_delta(&delta_chunk) {
+                    stats.zstd_chunks = 1;
+                } else if is_levenshtein_delta(&delta_chunk) {
+                    count_actions(parent_len, &delta_chunk, &mut stats);
+                }
+            }
+        }
+        Ok((data, stats))
+    }
+
+    /// Sums the stats of decoding the chunks `keys`, e.g. the manifest of a file.
+    pub fn decode_stats<'a>(
+        &self,
+        keys: impl IntoIterator<Item = &'a SBCHash>,
+    ) -> Result<DecodeStats> {
+        let mut stats = DecodeStats::default();
+        for sbc_hash in keys {
+            stats.add(&self.decode_with_stats(sbc_hash)?.1);
+        }
+        Ok(stats)
+    }
+}
+
+/// Replays the actions of a delta already decoded successfully, tracking which
+/// bytes of the chunk still come from the parent.
+fn count_actions(parent_len: usize, delta_chunk: &[u8], stats: &mut DecodeStats) {
+    let mut from_parent = vec![true; parent_len];
+    for code in delta_chunk.get(4..).unwrap_or_default().chunks_exact(4) {
+        let (action, index, _) = get_delta_action(u32::from_be_bytes(code.try_into().unwrap()));
+        match action {
+            Action::Del if index < from_parent.len() => {
+                from_parent.remove(index);
+                stats.deletes += 1;
+            }
+            Action::Add if index <= from_parent.len() => {
+                from_parent.insert(index, false);
+                stats.inserts += 1;
+            }
+            Action::Rep if index < from_parent.len() => {
+                from_parent[index] = false;
+                stats.replaces += 1;
+            }
+            _ => return,
+        }
+    }
+    stats.copied_bytes = from_parent.iter().filter(|&&copied| copied).count();
+    stats.copies = from_parent
+        .iter()
+        .enumerate()
+        .filter(|&(index, &copied)| copied && (index == 0 || !from_parent[index - 1]))
+        .count();
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ChunkType;
+    use chunkfs::Database;
+
+    #[test]
+    fn test_decode_stats() {
+        let data: Vec<u8> = (0..8192).map(|_| rand::random::<u8>()).collect();
+        let mut similar_data = data.clone();
+        similar_data[100] ^= 1;
+        similar_data.insert(5000, 7);
+        let parent_hash = SBCHash {
+            key: 1,
+            chunk_type: ChunkType::Simple(0),
+        };
+        let delta_hash = SBCHash {
+            key: 2,
+            chunk_type: ChunkType::Delta(0),
+        };
+        let mut map = SBCMap::new();
+        map.insert(parent_hash.clone(), data.clone()).unwrap();
+        let delta = crate::encode_delta(&similar_data, &data, 1).unwrap();
+        map.insert(delta_hash.clone(), delta).unwrap();
+
+        let (restored, stats) = map.decode_with_stats(&parent_hash).unwrap();
+        assert_eq!(restored, data);
+        assert_eq!(stats.literal_bytes, 8192);
+        assert_eq!(stats.copies, 0);
+
+        let (restored, stats) = map.decode_with_stats(&delta_hash).unwrap();
+        assert_eq!(restored, similar_data);
+        assert_eq!((stats.replaces, stats.inserts, stats.deletes), (1, 1, 0));
+        assert_eq!(stats.copies, 3);
+        assert_eq!(stats.copied_bytes, 8191);
+        assert_eq!(stats.instruction_bytes(), 8);
+        assert_eq!(stats.literal_bytes, 0);
+
+        let total = map.decode_stats([&parent_hash, &delta_hash]).unwrap();
+        assert_eq!(total.chunk_count, 2);
+        assert_eq!(total.decoded_bytes, 2 * 8192 + 1);
+        assert_eq!(total.average_copy_len(), 8191.0 / 3.0);
+    }
+}
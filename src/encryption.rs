@@ -0,0 +1,49 @@
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+
+const NONCE_LEN: usize = 24;
+
+/// Source of the 256-bit key encrypting chunks of saved maps, see
+/// [`crate::SBCMap::write_encrypted_to`]. Chunks kept in memory or in the file
+/// of [`crate::SBCMap::with_mmap_storage`] are not encrypted.
+pub trait KeyProvider {
+    fn key(&self) -> [u8; 32];
+}
+
+impl KeyProvider for [u8; 32] {
+    fn key(&self) -> [u8; 32] {
+        *self
+    }
+}
+
+pub(crate) struct ChunkCipher(XChaCha20Poly1305);
+
+impl ChunkCipher {
+    pub fn new(keys: &impl KeyProvider) -> ChunkCipher {
+        ChunkCipher(XChaCha20Poly1305::new(&keys.key().into()))
+    }
+
+    /// Encrypts `data` with a random nonce, which is returned in front of the
+    /// ciphertext. `header` is authenticated, so a chunk cannot be moved to another key.
+    pub fn seal(&self, header: &[u8], data: &[u8]) -> Vec<u8> {
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let payload = Payload {
+            msg: data,
+            aad: header,
+        };
+        let ciphertext = self.0.encrypt(&nonce, payload).unwrap();
+        [nonce.as_slice(), ciphertext.as_slice()].concat()
+    }
+
+    pub fn open(&self, header: &[u8], sealed: &[u8]) -> Option<Vec<u8>> {
+        if sealed.len() < NONCE_LEN {
+            return None;
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        let payload = Payload {
+            msg: ciphertext,
+            aad: header,
+        };
+        self.0.decrypt(XNonce::from_slice(nonce), payload).ok()
+    }
+}
@@ -0,0 +1,38 @@
+const SAMPLE_LEN: usize = 4096;
+
+/// Shannon entropy in bits per byte of the byte histogram of `data`. Long
+/// chunks are estimated from evenly spaced bytes.
+pub(crate) fn byte_entropy(data: &[u8]) -> f64 {
+    if data.is_empty() {
+        return 0.0;
+    }
+    let step = data.len().div_ceil(SAMPLE_LEN);
+    let mut histogram = [0usize; 256];
+    let mut sample_len = 0;
+    for &byte in data.iter().step_by(step) {
+        histogram[byte as usize] += 1;
+        sample_len += 1;
+    }
+    histogram
+        .iter()
+        .filter(|&&count| count > 0)
+        .map(|&count| {
+            let probability = count as f64 / sample_len as f64;
+            -probability * probability.log2()
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_byte_entropy() {
+        let random_data: Vec<u8> = (0..1 << 16).map(|_| rand::random::<u8>()).collect();
+        assert!(byte_entropy(random_data.as_slice()) > 7.8);
+        assert_eq!(byte_entropy(&[7; 100]), 0.0);
+        assert_eq!(byte_entropy(&[0, 1, 0, 1]), 1.0);
+        assert_eq!(byte_entropy(&[]), 0.0);
+    }
+}
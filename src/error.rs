@@ -0,0 +1,70 @@
+use std::io;
+
+#[derive(Debug, thiserror::Error)]
+pub enum SbcError {
+    #[error("storage error: {0}")]
+    Storage(#[from] io::Error),
+    #[error("failed to encode chunk {key}: {reason}")]
+    Encode { key: u32, reason: String },
+    #[error("failed to decode chunk {key}: {reason}")]
+    Decode { key: u32, reason: String },
+    #[error("clustering failed: {0}")]
+    Clustering(String),
+    #[error("invalid configuration: {0}")]
+    Config(String),
+    #[error("chunk {key} is stored with different data in both maps")]
+    KeyConflict { key: u32 },
+    #[error("storage quota of {quota} bytes exceeded")]
+    QuotaExceeded { quota: usize },
+    #[error("snapshot was already rolled back or released")]
+    UnknownSnapshot,
+}
+
+pub type Result<T> = std::result::Result<T, SbcError>;
+
+/// Why [`crate::decode_delta`] refused a Levenshtein delta.
+#[derive(Clone, Debug, PartialEq, Eq, thiserror::Error)]
+pub enum DeltaError {
+    #[error("delta of {len} bytes is not a parent key followed by whole actions")]
+    Truncated { len: usize },
+    #[error("word at byte {offset} is not a Levenshtein action")]
+    InvalidAction { offset: usize },
+    #[error("action at byte {offset} refers to byte {index} of a {len}-byte chunk")]
+    IndexOutOfRange {
+        offset: usize,
+        index: usize,
+        len: usize,
+    },
+}
+
+impl From<SbcError> for io::Error {
+    fn from(error: SbcError) -> io::Error {
+        match error {
+            SbcError::Storage(error) => error,
+            SbcError::Config(_) => io::Error::new(io::ErrorKind::InvalidInput, error),
+            SbcError::Decode { .. } => io::Error::new(io::ErrorKind::InvalidData, error),
+            _ => io::Error::other(error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_conversion_to_io_error() {
+        let error = io::Error::from(SbcError::Decode {
+            key: 7,
+            reason: "missing parent chunk".to_string(),
+        });
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(
+            error.to_string(),
+            "failed to decode chunk 7: missing parent chunk"
+        );
+
+        let error = io::Error::from(SbcError::from(io::Error::from(io::ErrorKind::NotFound)));
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+    }
+}
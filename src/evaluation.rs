@@ -0,0 +1,96 @@
+/// Pair of chunks labeled as similar or dissimilar, e.g. a chunk and its mutated copy.
+#[derive(Clone, Debug)]
+pub struct LabeledPair {
+    pub first: Vec<u8>,
+    pub second: Vec<u8>,
+    pub similar: bool,
+}
+
+/// Quality of a similarity hash, when pairs whose hashes differ by at most
+/// `threshold` are predicted to be similar.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct HasherQuality {
+    pub threshold: u32,
+    pub precision: f64,
+    pub recall: f64,
+}
+
+/// Computes the precision and recall of `hasher` on `pairs` for every threshold.
+/// Precision is 1 when no pair is predicted similar, recall is 1 when no pair is
+/// labeled similar.
+pub fn evaluate_hasher<H>(
+    pairs: &[LabeledPair],
+    hasher: H,
+    thresholds: &[u32],
+) -> Vec<HasherQuality>
+where
+    H: Fn(&[u8]) -> u32,
+{
+    let distances: Vec<(u32, bool)> = pairs
+        .iter()
+        .map(|pair| {
+            let distance = hasher(pair.first.as_slice()).abs_diff(hasher(pair.second.as_slice()));
+            (distance, pair.similar)
+        })
+        .collect();
+
+    thresholds
+        .iter()
+        .map(|&threshold| {
+            let (mut true_positives, mut false_positives, mut false_negatives) = (0, 0, 0);
+            for &(distance, similar) in distances.iter() {
+                match (distance <= threshold, similar) {
+                    (true, true) => true_positives += 1,
+                    (true, false) => false_positives += 1,
+                    (false, true) => false_negatives += 1,
+                    (false, false) => {}
+                }
+            }
+            HasherQuality {
+                threshold,
+                precision: ratio(true_positives, true_positives + false_positives),
+                recall: ratio(true_positives, true_positives + false_negatives),
+            }
+        })
+        .collect()
+}
+
+fn ratio(count: usize, total: usize) -> f64 {
+    match total {
+        0 => 1.0,
+        _ => count as f64 / total as f64,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::sbc_hashing;
+
+    #[test]
+    fn test_evaluate_sbc_hashing() {
+        let mut pairs = Vec::new();
+        for _ in 0..8 {
+            let data: Vec<u8> = (0..4096).map(|_| rand::random::<u8>()).collect();
+            let mut mutated_data = data.clone();
+            mutated_data[2000] = mutated_data[2000].wrapping_add(1);
+            pairs.push(LabeledPair {
+                first: data.clone(),
+                second: mutated_data,
+                similar: true,
+            });
+            pairs.push(LabeledPair {
+                first: data,
+                second: (0..4096).map(|_| rand::random::<u8>()).collect(),
+                similar: false,
+            });
+        }
+
+        let qualities = evaluate_hasher(pairs.as_slice(), sbc_hashing, &[0, 32, u32::MAX]);
+        assert_eq!(qualities.len(), 3);
+        assert!(qualities[0].recall <= qualities[1].recall);
+        assert_eq!(qualities[2].recall, 1.0);
+        assert_eq!(qualities[2].precision, 0.5);
+        assert_eq!(evaluate_hasher(&[], sbc_hashing, &[0])[0].recall, 1.0);
+    }
+}
@@ -0,0 +1,167 @@
+use crate::{compress_chunks, Result, SBCScrubber, SizeBucket};
+use std::collections::HashSet;
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+/// Format of [`ExperimentReport::write_to`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReportFormat {
+    /// One object with all fields, times in microseconds and `size_buckets`
+    /// as an array of `{"min_size", "max_size", "chunk_count", "original_bytes",
+    /// "stored_bytes", "encode_time_us"}`.
+    Json,
+    /// Header line and one row with all fields but `size_buckets`.
+    Csv,
+}
+
+/// Results of [`run_experiment`].
+#[derive(Clone, Debug)]
+pub struct ExperimentReport {
+    pub chunk_count: usize,
+    pub original_bytes: usize,
+    /// Stored size of all chunks, deltas included.
+    pub stored_bytes: usize,
+    /// Parents with at least one delta chunk.
+    pub cluster_count: usize,
+    pub delta_chunk_count: usize,
+    /// Chunks skipped by the entropy check or as too small.
+    pub skipped_chunk_count: usize,
+    pub hashing_time: Duration,
+    pub total_time: Duration,
+    pub size_buckets: Vec<SizeBucket>,
+}
+
+const CSV_HEADER: &str = "chunk_count,original_bytes,stored_bytes,ratio,cluster_count,\
+                          delta_chunk_count,skipped_chunk_count,hashing_time_us,total_time_us";
+
+impl ExperimentReport {
+    /// Original size relative to the stored size.
+    pub fn ratio(&self) -> f64 {
+        match self.stored_bytes {
+            0 => 1.0,
+            stored_bytes => self.original_bytes as f64 / stored_bytes as f64,
+        }
+    }
+
+    pub fn write_to<W: Write>(&self, writer: &mut W, format: ReportFormat) -> Result<()> {
+        let fields = [
+            ("chunk_count", self.chunk_count.to_string()),
+            ("original_bytes", self.original_bytes.to_string()),
+            ("stored_bytes", self.stored_bytes.to_string()),
+            ("ratio", self.ratio().to_string()),
+            ("cluster_count", self.cluster_count.to_string()),
+            ("delta_chunk_count", self.delta_chunk_count.to_string()),
+            ("skipped_chunk_count", self.skipped_chunk_count.to_string()),
+            ("hashing_time_us", self.hashing_time.as_micros().to_string()),
+            ("total_time_us", self.total_time.as_micros().to_string()),
+        ];
+        match format {
+            ReportFormat::Csv => {
+                writeln!(writer, "{CSV_HEADER}")?;
+                let row: Vec<&str> = fields.iter().map(|(_, value)| value.as_str()).collect();
+                writeln!(writer, "{}", row.join(","))?;
+            }
+            ReportFormat::Json => {
+                let size_buckets: Vec<String> = self
+                    .size_buckets
+                    .iter()
+                    .map(|bucket| {
+                        format!(
+                            "{{\"min_size\":{},\"max_size\":{},\"chunk_count\":{},\
+                             \"original_bytes\":{},\"stored_bytes\":{},\"encode_time_us\":{}}}",
+                            bucket.min_size,
+                            bucket
+                                .max_size
+                                .map_or("null".to_string(), |size| size.to_string()),
+                            bucket.chunk_count,
+                            bucket.original_bytes,
+                            bucket.stored_bytes,
+                            bucket.encode_time.as_micros()
+                        )
+                    })
+                    .collect();
+                let fields: Vec<String> = fields
+                    .iter()
+                    .map(|(name, value)| format!("\"{name}\":{value}"))
+                    .collect();
+                writeln!(
+                    writer,
+                    "{{{},\"size_buckets\":[{}]}}",
+                    fields.join(","),
+                    size_buckets.join(",")
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Compresses `chunks` with `scrubber` and reports timings, ratios and
+/// cluster statistics, e.g. for benchmark scripts.
+pub fn run_experiment<I>(chunks: I, scrubber: &mut SBCScrubber) -> Result<ExperimentReport>
+where
+    I: IntoIterator<Item = Vec<u8>>,
+{
+    let chunks: Vec<Vec<u8>> = chunks.into_iter().collect();
+    let original_bytes = chunks.iter().map(Vec::len).sum();
+    let time_start = Instant::now();
+    let (map, manifest) = compress_chunks(chunks, scrubber)?;
+    let total_time = time_start.elapsed();
+
+    let keys = map.keys();
+    let parents: Vec<_> = keys
+        .iter()
+        .filter_map(|sbc_hash| map.parent_of(sbc_hash))
+        .collect();
+    Ok(ExperimentReport {
+        chunk_count: manifest.len(),
+        original_bytes,
+        stored_bytes: keys
+            .iter()
+            .filter_map(|sbc_hash| map.stored_len(sbc_hash))
+            .sum(),
+        cluster_count: parents.iter().collect::<HashSet<_>>().len(),
+        delta_chunk_count: parents.len(),
+        skipped_chunk_count: scrubber.skipped_chunk_count(),
+        hashing_time: scrubber.hashing_time(),
+        total_time,
+        size_buckets: scrubber.size_buckets().to_vec(),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_experiment_report() {
+        let data: Vec<u8> = (0..8192).map(|_| rand::random::<u8>()).collect();
+        // Copies share the similarity hash, so they always end up in the
+        // cluster of the first one, as a one-byte change may not.
+        let report = run_experiment(
+            vec![data.clone(), data.clone(), data],
+            &mut SBCScrubber::new(),
+        )
+        .unwrap();
+        assert_eq!(report.chunk_count, 3);
+        assert_eq!(report.original_bytes, 3 * 8192);
+        assert_eq!(report.cluster_count, 1);
+        assert_eq!(report.delta_chunk_count, 2);
+        assert!(report.ratio() > 2.0);
+
+        let mut csv = Vec::new();
+        report.write_to(&mut csv, ReportFormat::Csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].split(',').count(), lines[1].split(',').count());
+        assert!(lines[1].starts_with("3,24576,"));
+
+        let mut json = Vec::new();
+        report.write_to(&mut json, ReportFormat::Json).unwrap();
+        let json = String::from_utf8(json).unwrap();
+        assert!(json.starts_with("{\"chunk_count\":3,\"original_bytes\":24576,"));
+        assert!(json.contains("\"size_buckets\":[{\"min_size\":0,\"max_size\":2048,"));
+        assert!(json.contains("\"max_size\":null"));
+    }
+}
@@ -0,0 +1,239 @@
+use chunkfs::{Chunk, Chunker};
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+/// Random values of bytes in the gear rolling hash, from a fixed splitmix64 sequence.
+const GEAR: [u64; 256] = gear_table();
+
+const fn gear_table() -> [u64; 256] {
+    let mut table = [0; 256];
+    let mut state: u64 = 0x9e37_79b9_7f4a_7c15;
+    let mut i = 0;
+    while i < 256 {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut value = state;
+        value = (value ^ (value >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        value = (value ^ (value >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        table[i] = value ^ (value >> 31);
+        i += 1;
+    }
+    table
+}
+
+/// Chunk found by [`GearChunker`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GearChunk<'a> {
+    pub data: &'a [u8],
+    /// Largest gear hash within the chunk. It depends only on the content around
+    /// a single position, so similar chunks usually share it.
+    pub feature: u32,
+}
+
+/// Features of the chunks cut by a [`GearChunker`], shared with
+/// [`crate::SBCScrubber::with_gear_features`] so the scrubber does not hash
+/// the chunks again.
+///
+/// Chunks are looked up by their length and the gear hash at their end, which
+/// depends only on the last 64 bytes. A chunk of other data matching both gets
+/// a wrong feature, which makes a worse parent choice but no wrong data.
+#[derive(Clone, Debug, Default)]
+pub struct GearFeatures {
+    features: Arc<RwLock<HashMap<(usize, u64), u32>>>,
+}
+
+impl GearFeatures {
+    pub(crate) fn get(&self, data: &[u8]) -> Option<u32> {
+        let features = self.features.read().unwrap();
+        features.get(&(data.len(), end_hash(data))).copied()
+    }
+
+    fn insert(&self, len: usize, end_hash: u64, feature: u32) {
+        self.features
+            .write()
+            .unwrap()
+            .insert((len, end_hash), feature);
+    }
+
+    pub fn len(&self) -> usize {
+        self.features.read().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Forgets all features, e.g. after the chunks were scrubbed.
+    pub fn clear(&self) {
+        self.features.write().unwrap().clear();
+    }
+}
+
+/// Gear hash at the end of the chunk, computed from its last 64 bytes.
+fn end_hash(data: &[u8]) -> u64 {
+    data[data.len().saturating_sub(64)..]
+        .iter()
+        .fold(0, |hash, &byte| {
+            (hash << 1).wrapping_add(GEAR[byte as usize])
+        })
+}
+
+/// Content-defined chunker based on the gear rolling hash, which computes a
+/// similarity feature of every chunk during the same pass. It implements the
+/// chunkfs [`Chunker`] trait, and with [`GearChunker::with_features`] hands
+/// the features over to the scrubber.
+#[derive(Clone, Debug)]
+pub struct GearChunker {
+    min_len: usize,
+    max_len: usize,
+    mask: u64,
+    features: Option<GearFeatures>,
+}
+
+impl GearChunker {
+    /// Chunks average `avg_len` bytes, rounded to a power of two of at least 2,
+    /// and are cut at `max_len` bytes at the latest.
+    pub fn new(min_len: usize, avg_len: usize, max_len: usize) -> GearChunker {
+        let bits = avg_len.max(2).next_power_of_two().trailing_zeros();
+        GearChunker {
+            min_len,
+            max_len: max_len.max(min_len).max(1),
+            // The upper bits of the gear hash depend on the last 64 bytes.
+            mask: (u64::MAX >> (64 - bits)) << (64 - bits),
+            features: None,
+        }
+    }
+
+    /// Records the feature of every chunk cut from now on in `features`.
+    pub fn with_features(mut self, features: GearFeatures) -> GearChunker {
+        self.features = Some(features);
+        self
+    }
+
+    pub fn chunks<'a>(&self, data: &'a [u8]) -> Vec<GearChunk<'a>> {
+        let mut chunks = Vec::new();
+        let mut rest = data;
+        while !rest.is_empty() {
+            let (len, feature) = self.next_chunk(rest);
+            let (chunk, tail) = rest.split_at(len);
+            chunks.push(GearChunk {
+                data: chunk,
+                feature,
+            });
+            rest = tail;
+        }
+        chunks
+    }
+
+    fn next_chunk(&self, data: &[u8]) -> (usize, u32) {
+        let mut hash = 0u64;
+        let mut feature = 0u64;
+        let end = data.len().min(self.max_len);
+        let mut len = end;
+        for (i, &byte) in data[..end].iter().enumerate() {
+            hash = (hash << 1).wrapping_add(GEAR[byte as usize]);
+            feature = feature.max(hash);
+            if i + 1 >= self.min_len && hash & self.mask == 0 {
+                len = i + 1;
+                break;
+            }
+        }
+        let feature = (feature >> 32) as u32;
+        if let Some(features) = &self.features {
+            features.insert(len, hash, feature);
+        }
+        (len, feature)
+    }
+}
+
+impl Chunker for GearChunker {
+    fn chunk_data(&mut self, data: &[u8], mut empty: Vec<Chunk>) -> Vec<Chunk> {
+        let mut offset = 0;
+        while offset < data.len() {
+            let (len, _) = self.next_chunk(&data[offset..]);
+            empty.push(Chunk::new(offset, len));
+            offset += len;
+        }
+        empty
+    }
+
+    fn estimate_chunk_count(&self, data: &[u8]) -> usize {
+        (data.len() >> self.mask.count_ones()) + 1
+    }
+}
+
+impl Default for GearChunker {
+    fn default() -> Self {
+        GearChunker::new(2048, 8192, 65536)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_gear_chunks() {
+        let data: Vec<u8> = (0..1 << 20).map(|_| rand::random::<u8>()).collect();
+        let chunker = GearChunker::default();
+        let chunks = chunker.chunks(&data);
+        assert_eq!(
+            chunks.iter().map(|chunk| chunk.data.len()).sum::<usize>(),
+            data.len()
+        );
+        assert!(chunks[..chunks.len() - 1]
+            .iter()
+            .all(|chunk| (2048..=65536).contains(&chunk.data.len())));
+
+        let mut edited_data = data.clone();
+        edited_data[data.len() / 2] ^= 1;
+        let edited_chunks = chunker.chunks(&edited_data);
+        let common_prefix = chunks
+            .iter()
+            .zip(edited_chunks.iter())
+            .take_while(|(chunk, edited_chunk)| chunk == edited_chunk)
+            .count();
+        let common_suffix = chunks
+            .iter()
+            .rev()
+            .zip(edited_chunks.iter().rev())
+            .take_while(|(chunk, edited_chunk)| chunk == edited_chunk)
+            .count();
+        assert!(common_prefix + common_suffix + 3 >= chunks.len());
+
+        let _ = GearChunker::new(0, 0, 16).chunks(&data);
+    }
+
+    #[test]
+    fn test_gear_features() {
+        let data: Vec<u8> = (0..1 << 18).map(|_| rand::random::<u8>()).collect();
+        let features = GearFeatures::default();
+        let mut chunker = GearChunker::default().with_features(features.clone());
+        let chunks = chunker.chunks(&data);
+        assert!(chunks
+            .iter()
+            .all(|chunk| features.get(chunk.data) == Some(chunk.feature)));
+
+        let ranges: Vec<_> = chunker
+            .chunk_data(&data, Vec::new())
+            .iter()
+            .map(Chunk::range)
+            .collect();
+        let mut offset = 0;
+        for (chunk, range) in chunks.iter().zip(ranges.iter()) {
+            assert_eq!(range.clone(), offset..offset + chunk.data.len());
+            offset += chunk.data.len();
+        }
+        assert_eq!(ranges.len(), chunks.len());
+
+        let mut chunk_data: Vec<Vec<u8>> = chunks.iter().map(|chunk| chunk.data.to_vec()).collect();
+        chunk_data.push(chunk_data[0].clone());
+        let mut scrubber = crate::SBCScrubber::new().with_gear_features(features);
+        let (map, manifest) = crate::compress_chunks(chunk_data.clone(), &mut scrubber).unwrap();
+        assert_eq!(manifest.keys()[0].key, chunks[0].feature);
+        assert!(map.parent_of(manifest.keys().last().unwrap()).is_some());
+        let restored: Vec<Vec<u8>> = crate::restore(&manifest, &map)
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(restored, chunk_data);
+    }
+}
@@ -1,6 +1,6 @@
 use std::collections::HashMap;
 
-const MAX_WEIGHT_EDGE: u32 = 1 << 5;
+pub(crate) const MAX_WEIGHT_EDGE: u32 = 1 << 5;
 
 struct Vertex {
     parent: u32,
@@ -130,7 +130,10 @@ fn processing_of_c_f_spectrum(byte_value_byte_frequency: HashMap<u8, u32>) -> u3
 pub fn sbc_hashing(data: &[u8]) -> u32 {
     let mut byte_value_byte_frequency = HashMap::new();
     let mut pair_value_pair_frequency = HashMap::new();
-    let mut last_byte = data[0];
+    let mut last_byte = match data.first() {
+        None => return 0,
+        Some(&byte) => byte,
+    };
     byte_value_byte_frequency.insert(last_byte, 1u32);
     for byte in &data[1..] {
         let byte_count = byte_value_byte_frequency.entry(*byte).or_insert(0);
@@ -148,9 +151,85 @@ pub fn sbc_hashing(data: &[u8]) -> u32 {
     c_f_hash ^ p_hash
 }
 
+/// Similarity hash of [`sbc_hashing`] shifted down to make room for the length
+/// class of the chunk and coarse positional features in the top bits of the key,
+/// so chunks of very different sizes or layouts do not get close keys and are
+/// not clustered together. Every field takes at most 8 bits.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LengthAwareHasher {
+    /// Bits of the binary logarithm of the chunk length.
+    pub length_bits: u32,
+    /// Bits of the top two bits of the bytes starting every quarter of the chunk.
+    pub position_bits: u32,
+}
+
+impl Default for LengthAwareHasher {
+    fn default() -> Self {
+        LengthAwareHasher {
+            length_bits: 5,
+            position_bits: 2,
+        }
+    }
+}
+
+impl LengthAwareHasher {
+    pub fn hash(&self, data: &[u8]) -> u32 {
+        let length_bits = self.length_bits.min(8);
+        let position_bits = self.position_bits.min(8);
+        let length_class = (data.len() as u32).checked_ilog2().unwrap_or(0);
+        let length_class = length_class.min((1 << length_bits) - 1);
+        let positions = (0..4).fold(0u32, |positions, quarter| {
+            let byte = data.get(quarter * data.len() / 4).copied().unwrap_or(0);
+            positions << 2 | (byte >> 6) as u32
+        }) >> (8 - position_bits);
+        let feature_bits = length_bits + position_bits;
+        let spectrum_hash = sbc_hashing(data).checked_shr(feature_bits).unwrap_or(0);
+        (length_class << position_bits | positions)
+            .checked_shl(32 - feature_bits)
+            .unwrap_or(0)
+            | spectrum_hash
+    }
+}
+
+/// Similarity hash of `data` by `hasher`, or by [`sbc_hashing`] without one.
+pub(crate) fn similarity_hash(hasher: Option<LengthAwareHasher>, data: &[u8]) -> u32 {
+    match hasher {
+        None => sbc_hashing(data),
+        Some(hasher) => hasher.hash(data),
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
+
+    #[test]
+    fn test_length_aware_hash() {
+        let hasher = LengthAwareHasher::default();
+        let data: Vec<u8> = (0..8192).map(|_| rand::random::<u8>()).collect();
+        let hash = hasher.hash(&data);
+        assert_eq!(hash >> 27, 13);
+        assert_eq!(hash & ((1 << 25) - 1), sbc_hashing(&data) >> 7);
+        assert_eq!(hasher.hash(&data[..100]) >> 27, 6);
+        assert_eq!(hasher.hash(&[]), 0);
+
+        let no_features = LengthAwareHasher {
+            length_bits: 0,
+            position_bits: 0,
+        };
+        assert_eq!(no_features.hash(&data), sbc_hashing(&data));
+        let all_features = LengthAwareHasher {
+            length_bits: 8,
+            position_bits: 8,
+        };
+        assert_eq!(all_features.hash(&data) >> 24, 13);
+
+        let text: Vec<u8> = (0..8000).map(|i| b"abcdefgh"[i % 7]).collect();
+        assert_eq!(sbc_hashing(&text[..1000]), sbc_hashing(&text));
+        assert!(hasher.hash(&text[..1000]).abs_diff(hasher.hash(&text)) > 1 << 20);
+    }
+
     #[test]
     fn test_processing_of_pair() {
         let a = 175u8;
@@ -191,7 +270,7 @@ mod test {
         assert_eq!(name, "1001001111000000000000000000000")
     }
 
-    pub fn return_p_spectrum_hash<'a>(data: &[u8]) -> u32 {
+    pub fn return_p_spectrum_hash(data: &[u8]) -> u32 {
         let mut pair_value_pair_frequency = HashMap::new();
         let mut last_byte = data[0];
         for byte in &data[1..] {
@@ -1,40 +1,100 @@
+use crate::DeltaError;
 use std::cmp::min;
 use Action::*;
 
+#[derive(Clone, Copy, PartialEq, Eq)]
 pub(crate) enum Action {
     Del,
     Add,
     Rep,
 }
 
-fn find_id_non_eq_byte(data_chunk: &[u8], data_chunk_parent: &[u8]) -> (usize, usize) {
-    let mut id_non_eq_byte_start = 0;
-    while data_chunk[id_non_eq_byte_start] == data_chunk_parent[id_non_eq_byte_start] {
-        id_non_eq_byte_start += 1;
-        if id_non_eq_byte_start == min(data_chunk_parent.len(), data_chunk.len()) {
-            break;
+const WORD_LEN: usize = 8;
+/// Actions address bytes with 22-bit indices.
+pub(crate) const MAX_CHUNK_LEN: usize = 1 << 22;
+
+fn word(data: &[u8]) -> u64 {
+    let mut buf = [0u8; WORD_LEN];
+    buf.copy_from_slice(&data[..WORD_LEN]);
+    u64::from_le_bytes(buf)
+}
+
+/// Length of the common prefix, compared a word at a time.
+fn common_prefix_len(data_1: &[u8], data_2: &[u8]) -> usize {
+    let len = min(data_1.len(), data_2.len());
+    let mut prefix_len = 0;
+    while prefix_len + WORD_LEN <= len {
+        let diff = word(&data_1[prefix_len..]) ^ word(&data_2[prefix_len..]);
+        if diff != 0 {
+            return prefix_len + diff.trailing_zeros() as usize / 8;
         }
+        prefix_len += WORD_LEN;
     }
-    let mut id_non_eq_byte_end = 0;
-    if !((data_chunk.len() <= id_non_eq_byte_start)
-        | (data_chunk_parent.len() <= id_non_eq_byte_start))
-    {
-        while data_chunk[data_chunk.len() - id_non_eq_byte_end - 1]
-            == data_chunk_parent[data_chunk_parent.len() - id_non_eq_byte_end - 1]
-        {
-            id_non_eq_byte_end += 1;
-            if min(data_chunk.len(), data_chunk_parent.len()) - id_non_eq_byte_end
-                == id_non_eq_byte_start
-            {
-                break;
-            }
+    while prefix_len < len && data_1[prefix_len] == data_2[prefix_len] {
+        prefix_len += 1;
+    }
+    prefix_len
+}
+
+/// Length of the common suffix, compared a word at a time.
+fn common_suffix_len(data_1: &[u8], data_2: &[u8]) -> usize {
+    let len = min(data_1.len(), data_2.len());
+    let mut suffix_len = 0;
+    while suffix_len + WORD_LEN <= len {
+        let diff = word(&data_1[data_1.len() - suffix_len - WORD_LEN..])
+            ^ word(&data_2[data_2.len() - suffix_len - WORD_LEN..]);
+        if diff != 0 {
+            return suffix_len + diff.leading_zeros() as usize / 8;
         }
+        suffix_len += WORD_LEN;
+    }
+    while suffix_len < len
+        && data_1[data_1.len() - suffix_len - 1] == data_2[data_2.len() - suffix_len - 1]
+    {
+        suffix_len += 1;
     }
+    suffix_len
+}
+
+fn find_id_non_eq_byte(data_chunk: &[u8], data_chunk_parent: &[u8]) -> (usize, usize) {
+    let id_non_eq_byte_start = common_prefix_len(data_chunk, data_chunk_parent);
+    let id_non_eq_byte_end = min(
+        common_suffix_len(data_chunk, data_chunk_parent),
+        min(data_chunk.len(), data_chunk_parent.len()) - id_non_eq_byte_start,
+    );
     (id_non_eq_byte_start, id_non_eq_byte_end)
 }
 
 pub(crate) fn encode(data_chunk: &[u8], data_chunk_parent: &[u8]) -> Option<Vec<u32>> {
-    let max_len_delta_code = data_chunk.len() as u32;
+    encode_within(data_chunk, data_chunk_parent, data_chunk.len())
+}
+
+/// Whether actions can encode `data_chunk` against `data_chunk_parent`: both
+/// are non-empty and their indices fit into actions.
+fn is_encodable(data_chunk: &[u8], data_chunk_parent: &[u8]) -> bool {
+    !data_chunk.is_empty()
+        && !data_chunk_parent.is_empty()
+        && data_chunk.len() < MAX_CHUNK_LEN
+        && data_chunk_parent.len() < MAX_CHUNK_LEN
+}
+
+/// Largest number of actions of a delta chunk of at most `max_len` bytes.
+fn max_distance(max_len: usize) -> Option<u32> {
+    Some(u32::try_from(max_len.checked_sub(4)? / 4).unwrap_or(u32::MAX))
+}
+
+/// Same as [`encode`], but gives up as soon as the delta chunk, with the parent
+/// key, is known to exceed `max_len` bytes. Chunks whose indices do not fit
+/// into actions are not encoded.
+pub(crate) fn encode_within(
+    data_chunk: &[u8],
+    data_chunk_parent: &[u8],
+    max_len: usize,
+) -> Option<Vec<u32>> {
+    if !is_encodable(data_chunk, data_chunk_parent) {
+        return None;
+    }
+    let max_distance = max_distance(max_len)?;
     let mut delta_code = Vec::new();
     let (id_non_eq_byte_start, id_non_eq_byte_end) =
         find_id_non_eq_byte(data_chunk, data_chunk_parent);
@@ -45,11 +105,24 @@ pub(crate) fn encode(data_chunk: &[u8], data_chunk_parent: &[u8]) -> Option<Vec<
         [id_non_eq_byte_start..data_chunk_parent.len() - id_non_eq_byte_end]
         .to_vec();
 
-    let matrix = levenshtein_matrix(data_chunk.as_slice(), data_chunk_parent.as_slice());
-
-    if matrix[matrix.len() - 1][matrix[0].len() - 1] * 4 + 4 > max_len_delta_code {
-        return None;
+    if (data_chunk.len() + 1) * (data_chunk_parent.len() + 1) >= HIRSCHBERG_MIN_CELLS {
+        distance_row(
+            data_chunk.as_slice(),
+            data_chunk_parent.as_slice(),
+            max_distance,
+        )?;
+        return Some(linear_space_delta_code(
+            data_chunk.as_slice(),
+            data_chunk_parent.as_slice(),
+            id_non_eq_byte_start,
+        ));
     }
+
+    let matrix = levenshtein_matrix(
+        data_chunk.as_slice(),
+        data_chunk_parent.as_slice(),
+        max_distance,
+    )?;
     let mut x = matrix[0].len() - 1;
     let mut y = matrix.len() - 1;
     while x > 0 || y > 0 {
@@ -83,6 +156,129 @@ pub(crate) fn encode(data_chunk: &[u8], data_chunk_parent: &[u8]) -> Option<Vec<
     Some(delta_code)
 }
 
+/// Above this number of matrix cells the delta code is built by Hirschberg's
+/// algorithm, which needs memory linear in the chunk length.
+const HIRSCHBERG_MIN_CELLS: usize = 1 << 26;
+/// Subproblems of Hirschberg's algorithm up to this size are solved with a full matrix.
+const HIRSCHBERG_BASE_CELLS: usize = 1 << 12;
+
+#[derive(Clone, Copy)]
+enum Step {
+    Match,
+    Rep(u8),
+    Del,
+    Add(u8),
+}
+
+/// Builds the same delta code as [`encode`] in linear space. Indices of the
+/// actions are shifted by `offset`.
+fn linear_space_delta_code(data_chunk: &[u8], data_chunk_parent: &[u8], offset: usize) -> Vec<u32> {
+    let mut steps = Vec::new();
+    hirschberg(data_chunk, data_chunk_parent, &mut steps);
+
+    let (mut x, mut y) = (0, offset);
+    let mut delta_code = Vec::new();
+    for step in steps {
+        match step {
+            Step::Match => {}
+            Step::Rep(byte_value) => delta_code.push(encode_delta_action(Rep, y, byte_value)),
+            Step::Del => delta_code.push(encode_delta_action(Del, y, 0)),
+            Step::Add(byte_value) => delta_code.push(encode_delta_action(Add, y, byte_value)),
+        }
+        if !matches!(step, Step::Add(_)) {
+            y += 1;
+        }
+        if !matches!(step, Step::Del) {
+            x += 1;
+        }
+    }
+    debug_assert_eq!(x, data_chunk.len());
+    // Actions are applied from the end of the parent, so that indices stay valid.
+    delta_code.reverse();
+    delta_code
+}
+
+/// Appends the steps of an optimal alignment of `data_chunk_parent` to `data_chunk`.
+fn hirschberg(data_chunk: &[u8], data_chunk_parent: &[u8], steps: &mut Vec<Step>) {
+    if data_chunk_parent.len() <= 1
+        || (data_chunk.len() + 1) * (data_chunk_parent.len() + 1) <= HIRSCHBERG_BASE_CELLS
+    {
+        steps.extend(matrix_steps(data_chunk, data_chunk_parent));
+        return;
+    }
+    let middle = data_chunk_parent.len() / 2;
+    let forward = distance_row(data_chunk, &data_chunk_parent[..middle], u32::MAX).unwrap();
+    let reversed_chunk: Vec<u8> = data_chunk.iter().rev().copied().collect();
+    let reversed_parent: Vec<u8> = data_chunk_parent[middle..].iter().rev().copied().collect();
+    let backward = distance_row(
+        reversed_chunk.as_slice(),
+        reversed_parent.as_slice(),
+        u32::MAX,
+    )
+    .unwrap();
+
+    let split = (0..=data_chunk.len())
+        .min_by_key(|&x| forward[x] + backward[data_chunk.len() - x])
+        .unwrap();
+    hirschberg(&data_chunk[..split], &data_chunk_parent[..middle], steps);
+    hirschberg(&data_chunk[split..], &data_chunk_parent[middle..], steps);
+}
+
+/// Last row of the Levenshtein matrix: distances from `data_chunk_parent` to
+/// every prefix of `data_chunk`. Returns `None` as soon as a row shows that the
+/// distance to the whole chunk exceeds `max_distance`.
+fn distance_row(
+    data_chunk: &[u8],
+    data_chunk_parent: &[u8],
+    max_distance: u32,
+) -> Option<Vec<u32>> {
+    let mut row: Vec<u32> = (0..data_chunk.len() as u32 + 1).collect();
+    for (y, &parent_byte) in data_chunk_parent.iter().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = y as u32 + 1;
+        let mut row_min = row[0];
+        for x in 1..data_chunk.len() + 1 {
+            let replace = diagonal + (parent_byte != data_chunk[x - 1]) as u32;
+            diagonal = row[x];
+            row[x] = min(min(row[x] + 1, row[x - 1] + 1), replace);
+            row_min = min(row_min, row[x]);
+        }
+        if row_min > max_distance {
+            return None;
+        }
+    }
+    (row[data_chunk.len()] <= max_distance).then_some(row)
+}
+
+fn matrix_steps(data_chunk: &[u8], data_chunk_parent: &[u8]) -> Vec<Step> {
+    let matrix = levenshtein_matrix_sequential(data_chunk, data_chunk_parent, u32::MAX).unwrap();
+    let mut steps = Vec::new();
+    let (mut x, mut y) = (data_chunk.len(), data_chunk_parent.len());
+    while x > 0 || y > 0 {
+        if x > 0
+            && y > 0
+            && data_chunk[x - 1] == data_chunk_parent[y - 1]
+            && matrix[y - 1][x - 1] == matrix[y][x]
+        {
+            steps.push(Step::Match);
+            x -= 1;
+            y -= 1;
+        } else if x > 0 && y > 0 && matrix[y - 1][x - 1] + 1 == matrix[y][x] {
+            steps.push(Step::Rep(data_chunk[x - 1]));
+            x -= 1;
+            y -= 1;
+        } else if y > 0 && matrix[y - 1][x] + 1 == matrix[y][x] {
+            steps.push(Step::Del);
+            y -= 1;
+        } else {
+            steps.push(Step::Add(data_chunk[x - 1]));
+            x -= 1;
+        }
+    }
+    steps.reverse();
+    steps
+}
+
 #[allow(dead_code)]
 pub(crate) fn levenshtein_distance(data_chunk: &[u8], data_chunk_parent: &[u8]) -> u32 {
     let mut id_eq_byte = 0;
@@ -92,17 +288,49 @@ pub(crate) fn levenshtein_distance(data_chunk: &[u8], data_chunk_parent: &[u8])
         }
         id_eq_byte += 1;
     }
-    let levenshtein_matrix =
-        levenshtein_matrix(&data_chunk[id_eq_byte..], &data_chunk_parent[id_eq_byte..]);
+    let levenshtein_matrix = levenshtein_matrix(
+        &data_chunk[id_eq_byte..],
+        &data_chunk_parent[id_eq_byte..],
+        u32::MAX,
+    )
+    .unwrap();
     levenshtein_matrix[data_chunk_parent.len()][data_chunk.len()]
 }
 
-fn levenshtein_matrix(data_chunk: &[u8], data_chunk_parent: &[u8]) -> Vec<Vec<u32>> {
+#[cfg(all(feature = "parallel", not(feature = "no-parallel")))]
+const PARALLEL_MATRIX_MIN_CELLS: usize = 1 << 20;
+
+/// Returns `None` when the distance exceeds `max_distance`, without filling the
+/// rest of the matrix if possible.
+fn levenshtein_matrix(
+    data_chunk: &[u8],
+    data_chunk_parent: &[u8],
+    max_distance: u32,
+) -> Option<Vec<Vec<u32>>> {
+    // A small `max_distance` lets the sequential fill give up within a few rows,
+    // before a parallel fill pays off.
+    #[cfg(all(feature = "parallel", not(feature = "no-parallel")))]
+    if (data_chunk.len() + 1) * (data_chunk_parent.len() + 1) >= PARALLEL_MATRIX_MIN_CELLS
+        && (max_distance as usize).saturating_mul(data_chunk.len() + 1) >= PARALLEL_MATRIX_MIN_CELLS
+    {
+        return levenshtein_matrix_wavefront(data_chunk, data_chunk_parent, max_distance);
+    }
+    levenshtein_matrix_sequential(data_chunk, data_chunk_parent, max_distance)
+}
+
+/// Fills the matrix row by row. Minimums of the rows never decrease, so the
+/// matrix is abandoned at the first row whose minimum exceeds `max_distance`.
+fn levenshtein_matrix_sequential(
+    data_chunk: &[u8],
+    data_chunk_parent: &[u8],
+    max_distance: u32,
+) -> Option<Vec<Vec<u32>>> {
     let mut levenshtein_matrix =
         vec![vec![0u32; data_chunk.len() + 1]; data_chunk_parent.len() + 1];
     levenshtein_matrix[0] = (0..data_chunk.len() as u32 + 1).collect();
     for y in 1..data_chunk_parent.len() + 1 {
         levenshtein_matrix[y][0] = y as u32;
+        let mut row_min = y as u32;
         for x in 1..data_chunk.len() + 1 {
             let add = levenshtein_matrix[y - 1][x] + 1;
             let del = levenshtein_matrix[y][x - 1] + 1;
@@ -111,9 +339,84 @@ fn levenshtein_matrix(data_chunk: &[u8], data_chunk_parent: &[u8]) -> Vec<Vec<u3
                 replace += 1;
             }
             levenshtein_matrix[y][x] = min(min(del, add), replace);
+            row_min = min(row_min, levenshtein_matrix[y][x]);
+        }
+        if row_min > max_distance {
+            return None;
         }
     }
-    levenshtein_matrix
+    (levenshtein_matrix[data_chunk_parent.len()][data_chunk.len()] <= max_distance)
+        .then_some(levenshtein_matrix)
+}
+
+/// Fills the matrix by anti-diagonals, cells of one anti-diagonal are computed
+/// in parallel. Every path to the last cell crosses every anti-diagonal and
+/// never decreases, so the matrix is abandoned at the first anti-diagonal whose
+/// minimum exceeds `max_distance`.
+#[cfg(all(feature = "parallel", not(feature = "no-parallel")))]
+fn levenshtein_matrix_wavefront(
+    data_chunk: &[u8],
+    data_chunk_parent: &[u8],
+    max_distance: u32,
+) -> Option<Vec<Vec<u32>>> {
+    use rayon::prelude::*;
+
+    /// Rows of the matrix shared by the threads filling an anti-diagonal.
+    struct Rows(Vec<*mut u32>);
+
+    // SAFETY: the threads write distinct cells of one anti-diagonal and read
+    // only cells of the two previous anti-diagonals, which are written already.
+    unsafe impl Sync for Rows {}
+
+    impl Rows {
+        fn cell(&self, y: usize, x: usize) -> *mut u32 {
+            self.0[y].wrapping_add(x)
+        }
+    }
+
+    let width = data_chunk.len() + 1;
+    let height = data_chunk_parent.len() + 1;
+    let mut matrix = vec![vec![0u32; width]; height];
+    matrix[0] = (0..width as u32).collect();
+    for (y, row) in matrix.iter_mut().enumerate() {
+        row[0] = y as u32;
+    }
+    let rows = Rows(matrix.iter_mut().map(|row| row.as_mut_ptr()).collect());
+
+    for diagonal in 2..width + height - 1 {
+        let x_start = std::cmp::max(1, diagonal.saturating_sub(height - 1));
+        let x_end = min(width, diagonal);
+        let inner_min = (x_start..x_end)
+            .into_par_iter()
+            .with_min_len(1024)
+            .map(|x| {
+                let y = diagonal - x;
+                // SAFETY: see `Rows`, the cells are within the matrix.
+                unsafe {
+                    let add = *rows.cell(y - 1, x) + 1;
+                    let del = *rows.cell(y, x - 1) + 1;
+                    let mut replace = *rows.cell(y - 1, x - 1);
+                    if data_chunk_parent[y - 1] != data_chunk[x - 1] {
+                        replace += 1;
+                    }
+                    let distance = min(min(del, add), replace);
+                    *rows.cell(y, x) = distance;
+                    distance
+                }
+            })
+            .min();
+        // The cells of the first row and column on the anti-diagonal equal its number.
+        let border_min = (diagonal < std::cmp::max(width, height)).then_some(diagonal as u32);
+        if min(
+            inner_min.unwrap_or(u32::MAX),
+            border_min.unwrap_or(u32::MAX),
+        ) > max_distance
+        {
+            return None;
+        }
+    }
+    drop(rows);
+    (matrix[height - 1][width - 1] <= max_distance).then_some(matrix)
 }
 
 fn encode_delta_action(action: Action, index: usize, byte_value: u8) -> u32 {
@@ -128,13 +431,106 @@ fn encode_delta_action(action: Action, index: usize, byte_value: u8) -> u32 {
         Rep => {}
     }
     code += byte_value as u32 * (1 << 22);
-    if index >= (1 << 22) {
+    if index >= MAX_CHUNK_LEN {
         panic!()
     }
     code += index as u32;
     code
 }
 
+/// Encodes `data` as Levenshtein actions against its parent, in the format read
+/// by [`decode_delta`]. Returns `None` when the delta would not be smaller than the chunk.
+pub fn encode_delta(data: &[u8], parent_data: &[u8], parent_key: u32) -> Option<Vec<u8>> {
+    let delta_code = encode(data, parent_data)?;
+    Some(
+        [parent_key]
+            .into_iter()
+            .chain(delta_code)
+            .flat_map(u32::to_be_bytes)
+            .collect(),
+    )
+}
+
+/// Size of the delta [`encode_delta`] would build for `data`, computed from the
+/// Levenshtein distance alone in linear space, without building the actions.
+/// Returns `None` where [`encode_delta`] refuses to encode the chunk, giving up
+/// as early as it does.
+pub fn estimate_delta_size(data: &[u8], parent_data: &[u8]) -> Option<usize> {
+    if !is_encodable(data, parent_data) {
+        return None;
+    }
+    let (id_non_eq_byte_start, id_non_eq_byte_end) = find_id_non_eq_byte(data, parent_data);
+    let distance = distance_row(
+        &data[id_non_eq_byte_start..data.len() - id_non_eq_byte_end],
+        &parent_data[id_non_eq_byte_start..parent_data.len() - id_non_eq_byte_end],
+        max_distance(data.len())?,
+    )?
+    .pop()
+    .unwrap();
+    Some(4 + 4 * distance as usize)
+}
+
+/// Whether the first word after the parent key of `delta_chunk` can be a
+/// Levenshtein action. Words with both top bits set are not actions, they are
+/// reserved for markers of other delta formats.
+pub(crate) fn is_levenshtein_delta(delta_chunk: &[u8]) -> bool {
+    delta_chunk.get(4).is_none_or(|&byte| byte >> 6 != 3)
+}
+
+/// Length of the chunk `delta_chunk` decodes to against a parent of
+/// `parent_len` bytes, counted from its actions without applying them.
+pub(crate) fn decoded_len(parent_len: usize, delta_chunk: &[u8]) -> Option<usize> {
+    let (mut added, mut deleted) = (0, 0);
+    for code in delta_chunk.get(4..)?.chunks_exact(4) {
+        match code[0] >> 6 {
+            1 => added += 1,
+            2 => deleted += 1,
+            _ => {}
+        }
+    }
+    (parent_len + added).checked_sub(deleted)
+}
+
+/// Restores a chunk from its parent and its stored delta, whose first 4 bytes
+/// are the key of the parent. Truncated deltas, words which are not actions
+/// and actions out of the bounds of the chunk are refused.
+pub fn decode_delta(
+    parent_data: &[u8],
+    delta_chunk: &[u8],
+) -> std::result::Result<Vec<u8>, DeltaError> {
+    if delta_chunk.len() < 4 || !delta_chunk.len().is_multiple_of(4) {
+        return Err(DeltaError::Truncated {
+            len: delta_chunk.len(),
+        });
+    }
+    // The decoded chunk is longer than the parent by the number of added bytes
+    // at most, so it is allocated once.
+    let added = delta_chunk[4..]
+        .chunks_exact(4)
+        .filter(|code| code[0] >> 6 == 1)
+        .count();
+    let mut data = Vec::with_capacity(parent_data.len() + added);
+    data.extend_from_slice(parent_data);
+    for (word, code) in delta_chunk[4..].chunks_exact(4).enumerate() {
+        let offset = 4 + 4 * word;
+        let code = u32::from_be_bytes(code.try_into().unwrap());
+        if code >> 30 == 3 {
+            return Err(DeltaError::InvalidAction { offset });
+        }
+        let (action, index, byte_value) = get_delta_action(code);
+        let len = data.len();
+        match action {
+            Del if index < len => {
+                data.remove(index);
+            }
+            Add if index <= len => data.insert(index, byte_value),
+            Rep if index < len => data[index] = byte_value,
+            _ => return Err(DeltaError::IndexOutOfRange { offset, index, len }),
+        }
+    }
+    Ok(data)
+}
+
 pub(crate) fn get_delta_action(code: u32) -> (Action, usize, u8) {
     let action = match code / (1 << 30) {
         0 => Rep,
@@ -152,6 +548,60 @@ mod test {
     use crate::levenshtein_functions;
     use crate::levenshtein_functions::{get_delta_action, Action};
 
+    #[cfg(all(feature = "parallel", not(feature = "no-parallel")))]
+    #[test]
+    fn test_wavefront_matrix_eq_sequential_matrix() {
+        use crate::levenshtein_functions::{
+            levenshtein_matrix_sequential, levenshtein_matrix_wavefront,
+        };
+        let data_chunk_parent: Vec<u8> = (0..1500).map(|_| rand::random::<u8>() % 4).collect();
+        let mut data_chunk = data_chunk_parent[10..].to_vec();
+        data_chunk[700] = 7;
+        data_chunk.extend_from_slice(&[1, 2, 3]);
+
+        assert_eq!(
+            levenshtein_matrix_wavefront(
+                data_chunk.as_slice(),
+                data_chunk_parent.as_slice(),
+                u32::MAX
+            ),
+            levenshtein_matrix_sequential(
+                data_chunk.as_slice(),
+                data_chunk_parent.as_slice(),
+                u32::MAX
+            )
+        );
+        assert_eq!(
+            levenshtein_matrix_wavefront(&[], data_chunk_parent.as_slice(), u32::MAX),
+            levenshtein_matrix_sequential(&[], data_chunk_parent.as_slice(), u32::MAX)
+        );
+
+        let distance = *levenshtein_matrix_sequential(
+            data_chunk.as_slice(),
+            data_chunk_parent.as_slice(),
+            u32::MAX,
+        )
+        .unwrap()
+        .last()
+        .unwrap()
+        .last()
+        .unwrap();
+        for max_distance in [distance, distance - 1, 10] {
+            assert_eq!(
+                levenshtein_matrix_wavefront(
+                    data_chunk.as_slice(),
+                    data_chunk_parent.as_slice(),
+                    max_distance
+                ),
+                levenshtein_matrix_sequential(
+                    data_chunk.as_slice(),
+                    data_chunk_parent.as_slice(),
+                    max_distance
+                )
+            );
+        }
+    }
+
     #[test]
     fn test_chunk_recovery() {
         let parent_chunk_data = vec![134u8, 69, 17, 85, 92, 21, 249, 94]; //11, 0, 91, 47, 251, 176, 255, 205, 9, 133, 241, 99, 199, 99, 53, 179, 30, 181, 160, 236, 9, 39, 97, 135, 189, 116, 145, 93, 221, 182, 65, 183, 104, 235, 72, 45, 204, 191, 10, 171, 222, 219, 152, 62, 6, 247, 199, 88, 125, 35, 119, 147, 62, 38, 57, 58, 19, 61, 189, 88, 7, 251, 84, 153, 25, 6, 90, 144, 194, 135, 234, 190, 113, 209, 208, 58, 157, 16, 10, 86, 250, 98, 93, 207, 129, 150, 76, 94, 56, 52, 118, 204, 98, 123, 148, 71, 110, 4, 98, 98, 29, 215, 126, 95, 169, 54, 156, 150, 149, 124, 174, 249, 168, 161, 76, 225, 244, 173, 182, 150, 225, 139, 107, 211, 191, 201, 161, 198, 75, 107, 107, 81, 130, 167, 183, 213, 216, 238, 242, 25, 35, 30, 53, 159, 167, 60, 148, 1, 84, 16, 54, 216, 251, 103, 97, 249, 227, 113, 143, 14, 16, 231, 162, 71, 122, 198, 70, 81, 65, 135, 23, 180, 95, 32, 53, 95, 83, 234, 123, 3, 158, 55, 164, 14, 238, 36, 239, 58, 154, 48, 0, 152, 242, 94, 23, 38, 230, 111, 219, 113, 163, 17, 61, 58, 47, 105, 64, 72, 190, 253, 39, 187, 9, 124, 53, 63, 111, 153, 7, 48, 193, 215, 178, 51, 80, 230, 216, 196, 18, 73, 84, 29, 191, 102, 191, 43, 14, 217, 30, 158, 130, 159, 38, 29, 53, 73, 87, 130, 98, 83, 29, 13, 24, 41, 62, 215, 234, 226, 187, 195, 104, 17, 173, 251, 54, 204, 13, 249, 238, 57, 163, 40, 151, 106, 5, 232, 162, 48, 41, 160, 26, 199, 121, 72, 53, 102, 191, 22, 71, 104, 17, 200, 36, 9, 43, 169, 100, 72, 164, 186, 118, 199, 24, 106, 5, 113, 162, 55, 81, 7, 26, 113, 244, 61, 65, 237, 141, 116, 23, 194, 90, 117, 43, 174, 34, 95, 102, 243, 224, 190, 128, 72, 25, 102, 131, 94, 82, 4, 113, 87, 178, 199, 99, 160, 147, 137, 208, 19, 82, 29, 35, 67, 20, 180, 124, 164, 18, 73, 7, 118, 254, 25, 197, 225, 79, 36, 176, 103, 80, 23, 123, 68, 166, 89, 210, 156, 80, 200, 70, 173, 170, 184, 5, 108, 181, 164, 236, 178, 146, 108, 63, 239, 166, 245, 141, 136, 45, 179, 183, 83, 162, 18, 54, 91, 165, 126, 242, 202, 157, 170, 133, 35, 113, 7, 131, 141, 9, 15, 95, 253, 161, 14, 92, 127, 2, 186, 165, 133, 23, 250, 177, 107, 48, 32, 202, 69, 143, 48, 128, 87, 77, 81, 96, 30, 231, 185, 40, 78, 174, 44, 126, 64, 93, 188, 201, 38, 46, 148, 219, 172, 96, 72, 4, 73, 125, 25, 191, 138, 148, 65, 209, 143, 99, 215, 165, 154, 183, 2, 6, 125, 126, 145, 45, 234, 64, 247, 139, 235, 71, 135, 70, 36, 233, 93, 43, 83, 55, 222, 155, 41, 134, 61, 46, 32, 42, 103, 101, 238, 213, 248, 42, 28, 131, 100, 153, 247, 40, 129, 57, 187, 254, 235, 21, 3, 242, 78, 145, 191, 9, 102, 67, 168, 181, 33, 64, 167, 209, 13, 194, 143, 43, 178, 124, 81, 150, 84, 70, 225, 159, 98, 123, 80, 224, 104, 129, 55, 70, 79, 158, 132, 221, 166, 28, 88, 62, 219, 37, 204, 163, 56, 120, 101, 48, 155, 22, 186, 34, 83, 131, 36, 116, 203, 121, 47, 80, 50, 72, 97, 87, 151, 122, 245, 109, 146, 216, 110, 251, 0, 13, 105, 84, 111, 108, 219, 131, 200, 77, 129, 160, 107, 206, 31, 90, 206, 253, 23, 152, 252, 121, 218, 35, 216, 185, 71, 226, 159, 188, 196, 241, 251, 178, 105, 199, 66, 113, 170, 27, 42, 84, 214, 238, 200, 133, 177, 119, 11, 37, 14, 190, 226, 162, 227, 68, 181, 84, 69, 41, 46, 16, 198, 51, 176, 21, 53, 166, 2, 189, 174, 118, 214, 157, 13, 143, 56, 6, 78, 250, 205, 163, 6, 241, 0, 232, 33, 110, 203, 38, 102, 20, 216, 132, 185, 159, 20, 11, 222, 38, 171, 58, 231, 60, 162, 171, 75, 6, 234, 226, 89, 107, 128, 52, 145, 215, 122, 131, 220, 57, 182, 47, 231, 223, 45, 144, 243, 120, 74, 219, 191, 152, 89, 55, 183, 132, 4, 205, 136, 200, 177, 187, 232, 162, 44, 157, 26, 239, 74, 34, 79, 160, 217, 152, 194, 54, 159, 156, 15, 13, 76, 240, 103, 134, 177, 156, 8, 96, 65, 102, 36, 228, 68, 247, 194, 152, 106, 233, 49, 92, 176, 249, 101, 52, 95, 74, 86, 99, 207, 118, 148, 191, 182, 0, 71, 27, 197, 126, 24, 156, 26, 167, 33, 11, 143, 19, 156, 98, 225, 194, 225, 198, 250, 5, 253, 9, 124, 37, 170, 116, 253, 197, 60, 8, 79, 143, 233, 146, 141, 158, 3, 117, 204, 164, 229, 46, 41, 0, 117, 134, 175, 147, 108, 72, 154, 41, 224, 64, 41, 207, 219, 19, 228, 5, 2, 57, 246, 134, 35, 226, 248, 88, 138, 161, 120, 120, 237, 134, 249, 93, 157, 57, 74, 188, 97, 162, 150, 211, 241, 120, 183, 185, 9, 150, 57, 67, 68, 149, 156, 146, 117, 161, 177, 148, 159, 6, 216, 35, 253, 123, 89, 104, 195, 102, 235, 189, 177, 19, 90, 37, 92, 70, 252, 189, 53, 48, 158, 172, 143, 50, 170, 54, 49, 226, 48, 177, 189, 238, 12, 222, 203, 205, 59, 200, 252, 183, 128, 226, 158, 40, 207, 19, 139, 227, 166, 27, 215, 193, 197, 23, 220, 233, 126, 217, 207, 186, 24, 171, 35, 181, 213, 69, 142, 88, 130, 81, 249, 85, 223, 22, 236, 248, 69, 171, 238, 179, 84, 159, 31, 82, 244, 33, 114, 144, 50, 217, 193, 9, 91, 39, 47, 27, 114, 138, 147, 37, 136, 54, 136, 136, 214, 157, 31, 237, 70, 255, 37, 36, 175, 148, 225, 182, 39, 253, 198, 124, 40, 176, 133, 217, 198, 192, 83, 156, 130, 56, 134, 171, 162, 72, 19, 80, 84, 238, 154, 212, 8, 72, 98, 201, 152, 106, 175, 153, 116, 114, 95, 56, 94, 167, 142, 89, 12, 227, 45, 220, 239, 207, 209, 34, 0, 122, 59, 139, 211, 178, 20, 40, 120, 176, 179, 11, 175, 253, 6, 143, 115, 44, 96, 159, 19, 46, 106, 33, 202, 40, 19, 234, 77, 80, 134, 145, 127, 187, 89, 82, 121, 79, 86, 181, 140, 93, 104, 167, 80, 214, 224, 102, 141, 169, 159, 2, 15, 239, 73, 227, 161, 7, 244, 127, 242, 144, 189, 24, 158, 108, 216, 56, 16, 106, 97, 22, 140, 49, 20, 130, 220, 188, 37, 152, 223, 213, 54, 71, 34, 124, 93, 175, 146, 194, 231, 64, 137, 28, 247, 52, 110, 118, 184, 55, 116, 163, 41, 162, 103, 180, 11, 74, 3, 68, 110, 94, 175, 197, 195, 177, 255, 125, 239, 214, 190, 44, 62, 146, 244, 116, 231, 7, 151, 217, 211, 172, 213, 225, 123, 192, 22, 200, 188, 183, 95, 8, 234, 18, 235, 112, 153, 207, 160, 184, 142, 98, 145, 73, 247, 146, 251, 27, 13, 250, 131, 237, 107, 242, 208, 10, 70, 66, 233, 180, 159, 138, 23, 195, 63, 56, 245, 254, 23, 162, 135, 184, 111, 116, 95, 211, 118, 27, 48, 157, 181, 30, 115, 89, 243, 114, 11, 217, 205, 173, 203, 207, 62, 108, 248, 24, 250, 226, 232, 183, 169, 72, 73, 156, 216, 104, 223, 61, 203, 208, 38, 79, 15, 75, 127, 72, 141, 183, 198, 91, 134, 151, 144, 58, 186, 216, 103, 3, 254, 32, 254, 173, 196, 17, 132, 171, 72, 191, 104, 76, 233, 227, 255, 181, 226, 142, 2, 137, 223, 29, 70, 250, 103, 136, 184, 120, 106, 10, 217, 145, 152, 178, 221, 200, 74, 65, 43, 236, 200, 2, 99, 41, 252, 195, 64, 69, 120, 171, 48, 238, 164, 216, 138, 73, 12, 86, 182, 60, 141, 100, 21, 226, 234, 201, 191, 255, 235, 119, 184, 100, 109, 251, 64, 61, 244, 136, 89, 226, 78, 140, 84, 34, 231, 131, 101, 211, 158, 172, 171, 31, 43, 53, 236, 111, 3, 2, 102, 158, 70, 56, 203, 235, 50, 74, 89, 223, 34, 4, 104, 84, 165, 203, 88, 163, 94, 58, 76, 211, 123, 204, 117, 169, 250, 209, 4, 194, 100, 138, 193, 206, 81, 173, 235, 185, 8, 106, 206, 38, 68, 226, 196, 66, 117, 17, 28, 147, 172, 74, 235, 41, 129, 237, 189, 178, 35, 92, 253, 20, 4, 238, 254, 169, 232, 243, 56, 155, 33, 7, 19, 60, 141, 211, 158, 82, 188, 106, 250, 166, 1, 223, 124, 85, 90, 121, 89, 250, 104, 198, 244, 123, 19, 66, 81, 127, 185, 8, 129, 198, 41, 89, 62, 57, 138, 14, 7, 228, 226, 31, 26, 1, 53, 152, 123, 229, 76, 224, 152, 158, 19, 132, 75, 48, 205, 74, 3, 88, 205, 185, 82, 22, 57, 70, 235, 224, 207, 94, 116, 115, 216, 34, 161, 225, 128, 163, 25, 58, 177, 223, 197, 67, 146, 243, 137, 199, 130, 77, 209, 119, 213, 88, 239, 115, 6, 126, 78, 137, 235, 10, 130, 157, 35, 228, 37, 11, 185, 199, 58, 237, 239, 88, 46, 152, 167, 63, 29, 117, ];
@@ -200,4 +650,138 @@ mod test {
         assert_eq!(delta_chunk.len(), 8);
         assert_eq!(data_recovery, data);
     }
+
+    #[test]
+    fn test_linear_space_delta_code() {
+        use crate::levenshtein_functions::{decode_delta, distance_row, linear_space_delta_code};
+        let data_chunk_parent: Vec<u8> = (0..2000).map(|_| rand::random::<u8>() % 4).collect();
+        let mut data_chunk = data_chunk_parent.clone();
+        data_chunk.remove(1500);
+        data_chunk[900] = 7;
+        data_chunk.insert(300, 9);
+        data_chunk.extend_from_slice(&[1, 2, 3]);
+
+        let delta_code = linear_space_delta_code(&data_chunk[5..], &data_chunk_parent[5..], 5);
+        assert_eq!(
+            delta_code.len() as u32,
+            distance_row(
+                data_chunk.as_slice(),
+                data_chunk_parent.as_slice(),
+                u32::MAX
+            )
+            .unwrap()[data_chunk.len()]
+        );
+        let delta_chunk: Vec<u8> = [0u32]
+            .into_iter()
+            .chain(delta_code)
+            .flat_map(u32::to_be_bytes)
+            .collect();
+        assert_eq!(
+            decode_delta(data_chunk_parent.as_slice(), delta_chunk.as_slice()).unwrap(),
+            data_chunk
+        );
+    }
+
+    #[test]
+    fn test_common_prefix_and_suffix_len() {
+        use crate::levenshtein_functions::{common_prefix_len, common_suffix_len};
+        let data: Vec<u8> = (0..100).map(|_| rand::random::<u8>()).collect();
+        for position in 0..data.len() {
+            let mut changed_data = data.clone();
+            changed_data[position] = changed_data[position].wrapping_add(1);
+            assert_eq!(common_prefix_len(&data, &changed_data), position);
+            assert_eq!(
+                common_suffix_len(&data, &changed_data),
+                data.len() - position - 1
+            );
+            assert_eq!(common_prefix_len(&data[..position], &data), position);
+            assert_eq!(
+                common_suffix_len(&data[position..], &data),
+                data.len() - position
+            );
+        }
+    }
+
+    #[test]
+    fn test_estimate_delta_size() {
+        use crate::levenshtein_functions::{encode_delta, estimate_delta_size};
+        let parent: Vec<u8> = (0..3000).map(|_| rand::random::<u8>()).collect();
+        let mut data = parent.clone();
+        data[100] ^= 1;
+        data.insert(2000, 5);
+        data.remove(2500);
+        assert_eq!(
+            estimate_delta_size(&data, &parent),
+            Some(encode_delta(&data, &parent, 0).unwrap().len())
+        );
+        assert_eq!(estimate_delta_size(&parent, &parent), Some(4));
+        assert_eq!(estimate_delta_size(&[], &parent[..10]), None);
+        assert_eq!(estimate_delta_size(&parent[..10], &[]), None);
+
+        let other: Vec<u8> = (0..3000).map(|_| rand::random::<u8>()).collect();
+        assert_eq!(encode_delta(&other, &parent, 0), None);
+        assert_eq!(estimate_delta_size(&other, &parent), None);
+    }
+
+    #[test]
+    fn test_decode_corrupted_delta() {
+        use crate::levenshtein_functions::{decode_delta, encode_delta};
+        use crate::DeltaError;
+        let parent: Vec<u8> = (0..1000).map(|_| rand::random::<u8>()).collect();
+        let mut data = parent.clone();
+        data[100] ^= 1;
+        data.insert(500, 5);
+        let delta = encode_delta(&data, &parent, 0).unwrap();
+
+        assert_eq!(
+            decode_delta(&parent, &delta[..delta.len() - 1]),
+            Err(DeltaError::Truncated {
+                len: delta.len() - 1
+            })
+        );
+        assert_eq!(
+            decode_delta(&parent, &delta[..2]),
+            Err(DeltaError::Truncated { len: 2 })
+        );
+        let mut garbled = delta.clone();
+        garbled[4] = 0xff;
+        assert_eq!(
+            decode_delta(&parent, &garbled),
+            Err(DeltaError::InvalidAction { offset: 4 })
+        );
+        let out_of_range = [&delta[..4], &(2u32 << 30 | 1000).to_be_bytes()].concat();
+        assert_eq!(
+            decode_delta(&parent, &out_of_range),
+            Err(DeltaError::IndexOutOfRange {
+                offset: 4,
+                index: 1000,
+                len: 1000
+            })
+        );
+
+        for _ in 0..1000 {
+            let mut corrupted = delta.clone();
+            let position = rand::random::<usize>() % corrupted.len();
+            corrupted[position] = rand::random();
+            corrupted.truncate(rand::random::<usize>() % (corrupted.len() + 1));
+            let _ = decode_delta(&parent, &corrupted);
+        }
+    }
+
+    #[test]
+    fn test_chunks_near_max_len() {
+        use crate::levenshtein_functions::{decode_delta, encode_delta, MAX_CHUNK_LEN};
+        let parent: Vec<u8> = (0..MAX_CHUNK_LEN).map(|i| (i % 251) as u8).collect();
+        let mut data = parent[..MAX_CHUNK_LEN - 1].to_vec();
+        data[MAX_CHUNK_LEN - 2] ^= 1;
+        let delta = encode_delta(&data, &parent[..MAX_CHUNK_LEN - 1], 0).unwrap();
+        assert_eq!(
+            decode_delta(&parent[..MAX_CHUNK_LEN - 1], &delta).unwrap(),
+            data
+        );
+
+        data.push(0);
+        assert!(encode_delta(&data, &parent[..MAX_CHUNK_LEN - 1], 0).is_none());
+        assert!(encode_delta(&parent[..1000], &parent, 0).is_none());
+    }
 }
@@ -1,35 +1,485 @@
 pub use chunkfs_sbc::SBCScrubber;
-pub use hash_functions::sbc_hashing;
-use std::collections::HashMap;
+pub use cluster_export::GraphFormat;
+pub use clusterer::{ClusterStatistics, HashCollisions, ScrubBudget, SizeBucket};
+pub use codec::{Codec, CodecInfo, SpeedClass};
+pub use config::SbcConfig;
+pub use content_hash::{blake2b_content_hash, ContentHasher};
+pub use decode_stats::DecodeStats;
+#[cfg(feature = "encryption")]
+pub use encryption::KeyProvider;
+pub use error::{DeltaError, Result, SbcError};
+pub use evaluation::{evaluate_hasher, HasherQuality, LabeledPair};
+pub use experiments::{run_experiment, ExperimentReport, ReportFormat};
+pub use gear_chunker::{GearChunk, GearChunker, GearFeatures};
+pub use hash_functions::{sbc_hashing, LengthAwareHasher};
+pub use levenshtein_functions::{decode_delta, encode_delta, estimate_delta_size};
+#[cfg(feature = "mmap")]
+pub use maintenance::{Maintenance, MaintenanceProgress};
+pub use manifest_diff::{ManifestDiff, ManifestRun};
+pub use membership::ClusterMembership;
+#[cfg(feature = "mmap")]
+use mmap_storage::MmapStorage;
+#[cfg(feature = "async")]
+pub use pipeline::compress_chunks_async;
+pub use pipeline::{compress_chunks, compress_revision, restore, Manifest};
+pub use precomputed_clusters::PrecomputedClusterer;
+pub use preprocessing::Preprocessing;
+pub use read_view::SBCMapView;
+pub use recluster::Reclustered;
+#[cfg(feature = "zstd")]
+pub use recompress::Recompressed;
+pub use repair::ParentRepair;
+pub use restore_plan::{RestoreGroup, RestorePlan};
+pub use rolling::{rollsum, Rollsum};
+pub use routing::{ChunkClass, Route, RoutingTable};
+pub use signature::{BlockChecksum, ChunkSignature, RS_BLAKE2_SIG_MAGIC};
+pub use similarity_filter::SimilarityFilter;
+#[cfg(feature = "zstd")]
+pub use solid_cluster::SolidCluster;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::io;
+#[cfg(feature = "mmap")]
+use std::path::Path;
+use std::sync::Arc;
+pub use verify::VerifyReport;
 
+#[cfg(feature = "access-stats")]
+mod access_stats;
+#[cfg(feature = "async")]
+mod async_api;
 mod chunkfs_sbc;
+mod cluster_export;
 mod clusterer;
+mod codec;
+mod config;
+#[cfg(test)]
+mod conformance;
+mod content_hash;
+mod decode_stats;
+#[cfg(feature = "encryption")]
+mod encryption;
+mod entropy;
+mod error;
+mod evaluation;
+mod experiments;
+mod gear_chunker;
 mod graph;
 mod hash_functions;
 mod levenshtein_functions;
+#[cfg(feature = "mmap")]
+mod maintenance;
+mod manifest_diff;
+mod membership;
+mod merge;
+mod min_hash;
+#[cfg(feature = "mmap")]
+mod mmap_storage;
+mod parent_digest;
+mod parent_ref;
+mod persistence;
+mod pipeline;
+mod precomputed_clusters;
+mod preprocessing;
+mod quota;
+mod read_view;
+mod recluster;
+#[cfg(feature = "zstd")]
+mod recompress;
+mod repair;
+mod restore_plan;
+mod rolling;
+mod routing;
+mod signature;
+mod similarity_filter;
+#[cfg(feature = "zstd")]
+mod solid_cluster;
+mod verify;
+mod zstd_ref;
 
-#[derive(Hash, PartialEq, Eq, Clone, Default, Debug)]
-enum ChunkType {
+/// Chunks with the same similarity hash are told apart by their number.
+#[derive(Hash, PartialEq, Eq, Clone, Debug)]
+pub enum ChunkType {
     Delta(u16),
-    #[default]
-    Simple,
+    Simple(u16),
+    /// Simple chunk addressed by the content hash of its stored data.
+    Content([u8; content_hash::CONTENT_HASH_LEN]),
 }
 
-#[derive(Hash, PartialEq, Eq, Clone, Default)]
+impl Default for ChunkType {
+    fn default() -> Self {
+        ChunkType::Simple(0)
+    }
+}
+
+#[derive(Hash, PartialEq, Eq, Clone, Default, Debug)]
 pub struct SBCHash {
     key: u32,
     chunk_type: ChunkType,
 }
 
+impl SBCHash {
+    /// Similarity hash of the chunk.
+    pub fn hash(&self) -> u32 {
+        self.key
+    }
+
+    /// Whether the chunk is simple, delta encoded or addressed by content, with its number.
+    pub fn chunk_type(&self) -> &ChunkType {
+        &self.chunk_type
+    }
+
+    /// Parent of the chunk if it is a delta chunk stored in `map`, see
+    /// [`SBCMap::parent_of`]. The key alone does not encode the parent, it is
+    /// read from the stored delta, so the chunk has to be looked up in the map.
+    pub fn parent_hash(&self, map: &SBCMap) -> Option<SBCHash> {
+        map.parent_of(self)
+    }
+}
+
+/// Formats keys as `s<hash>.<number>` for simple chunks, `d<hash>.<number>`
+/// for delta chunks and `c<content hash in hex>` for content-addressed chunks.
+impl fmt::Display for SBCHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.chunk_type {
+            ChunkType::Simple(number) => write!(f, "s{}.{}", self.key, number),
+            ChunkType::Delta(number) => write!(f, "d{}.{}", self.key, number),
+            ChunkType::Content(content_hash) => {
+                write!(f, "c")?;
+                content_hash
+                    .iter()
+                    .try_for_each(|byte| write!(f, "{byte:02x}"))
+            }
+        }
+    }
+}
+
 pub struct SBCMap {
-    sbc_hashmap: HashMap<SBCHash, Vec<u8>>,
+    sbc_hashmap: Arc<HashMap<SBCHash, Arc<[u8]>>>,
+    #[cfg(feature = "mmap")]
+    simple_storage: Option<MmapStorage>,
+    preprocessing: Arc<HashMap<SBCHash, Preprocessing>>,
+    /// Solid clusters of the chunks stored with [`SBCMap::store_solid`], under
+    /// the key of every chunk in them.
+    #[cfg(feature = "zstd")]
+    solid_clusters: Arc<HashMap<SBCHash, Arc<SolidCluster>>>,
+    /// Numbers of the simple and delta chunks stored under every similarity
+    /// hash, simple chunks first, see [`SBCMap::find_by_hash`].
+    chunk_numbers: HashMap<u32, BTreeSet<(bool, u16)>>,
+    journal: Option<Journal>,
+    snapshot_count: u64,
+    quota: quota::Quota,
+    #[cfg(feature = "access-stats")]
+    access_stats: access_stats::AccessStats,
 }
 
+type JournalEntry = (SBCHash, Option<Arc<[u8]>>, Option<Preprocessing>);
+
+/// Insertions made since the oldest outstanding snapshot was taken.
+#[derive(Default)]
+struct Journal {
+    entries: Vec<JournalEntry>,
+    /// Outstanding snapshots with the journal length when they were taken, oldest first.
+    snapshots: Vec<(u64, usize)>,
+}
+
+/// Marker of the [`SBCMap`] state returned by [`SBCMap::snapshot`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SBCMapSnapshot(u64);
+
 impl SBCMap {
     pub fn new() -> SBCMap {
         SBCMap {
-            sbc_hashmap: HashMap::new(),
+            sbc_hashmap: Arc::default(),
+            #[cfg(feature = "mmap")]
+            simple_storage: None,
+            preprocessing: Arc::default(),
+            #[cfg(feature = "zstd")]
+            solid_clusters: Arc::default(),
+            chunk_numbers: HashMap::new(),
+            journal: None,
+            snapshot_count: 0,
+            quota: quota::Quota::default(),
+            #[cfg(feature = "access-stats")]
+            access_stats: access_stats::AccessStats::default(),
+        }
+    }
+
+    /// Creates a map which keeps simple chunks in an append-only file at `path`,
+    /// accessed through a memory mapping. Delta chunks and chunks addressed by
+    /// content hash stay in memory. Chunks in the file are not encrypted, so
+    /// such maps cannot be saved with `write_encrypted_to`.
+    ///
+    /// An existing file is not overwritten, reopen it with
+    /// [`SBCMap::open_mmap_storage`] instead. The file is exclusively locked
+    /// while the map is alive, and [`SBCMap::flush`] writes the index needed to
+    /// reopen it.
+    #[cfg(feature = "mmap")]
+    pub fn with_mmap_storage<P: AsRef<Path>>(path: P) -> Result<SBCMap> {
+        Ok(SBCMap {
+            sbc_hashmap: Arc::default(),
+            simple_storage: Some(MmapStorage::create(path.as_ref())?),
+            preprocessing: Arc::default(),
+            #[cfg(feature = "zstd")]
+            solid_clusters: Arc::default(),
+            chunk_numbers: HashMap::new(),
+            journal: None,
+            snapshot_count: 0,
+            quota: quota::Quota::default(),
+            #[cfg(feature = "access-stats")]
+            access_stats: access_stats::AccessStats::default(),
+        })
+    }
+
+    /// Starts journaling insertions and returns a marker of the current state,
+    /// which can later be restored with [`SBCMap::rollback`]. Insertions are
+    /// journaled until the snapshot is rolled back or released.
+    pub fn snapshot(&mut self) -> SBCMapSnapshot {
+        let journal = self.journal.get_or_insert_with(Journal::default);
+        let snapshot = SBCMapSnapshot(self.snapshot_count);
+        self.snapshot_count += 1;
+        journal.snapshots.push((snapshot.0, journal.entries.len()));
+        snapshot
+    }
+
+    /// Undoes all insertions made since `snapshot` was taken. The snapshot and
+    /// the ones taken after it are released.
+    pub fn rollback(&mut self, snapshot: SBCMapSnapshot) -> Result<()> {
+        let (depth, position) = self.find_snapshot(snapshot)?;
+        let mut journal = self.journal.take().unwrap();
+        while journal.entries.len() > position {
+            let (sbc_hash, previous, preprocessing) = journal.entries.pop().unwrap();
+            match preprocessing {
+                None => Arc::make_mut(&mut self.preprocessing).remove(&sbc_hash),
+                Some(preprocessing) => {
+                    Arc::make_mut(&mut self.preprocessing).insert(sbc_hash.clone(), preprocessing)
+                }
+            };
+            match previous {
+                None => self.remove_value(&sbc_hash),
+                Some(chunk) => self.store_value(sbc_hash, chunk)?,
+            }
+        }
+        journal.snapshots.truncate(depth);
+        self.journal = Some(journal);
+        self.trim_journal();
+        Ok(())
+    }
+
+    /// Keeps the insertions made since `snapshot` was taken and forgets the
+    /// snapshot. The journal is dropped once no snapshot is outstanding.
+    pub fn release(&mut self, snapshot: SBCMapSnapshot) -> Result<()> {
+        let (depth, _) = self.find_snapshot(snapshot)?;
+        if let Some(journal) = &mut self.journal {
+            journal.snapshots.remove(depth);
+        }
+        self.trim_journal();
+        Ok(())
+    }
+
+    fn find_snapshot(&self, snapshot: SBCMapSnapshot) -> Result<(usize, usize)> {
+        self.journal
+            .iter()
+            .flat_map(|journal| journal.snapshots.iter())
+            .enumerate()
+            .find(|(_, (id, _))| *id == snapshot.0)
+            .map(|(depth, (_, position))| (depth, *position))
+            .ok_or(SbcError::UnknownSnapshot)
+    }
+
+    /// Drops the journal entries no outstanding snapshot can roll back.
+    fn trim_journal(&mut self) {
+        let Some(journal) = &mut self.journal else {
+            return;
+        };
+        match journal.snapshots.first() {
+            None => self.journal = None,
+            Some(&(_, oldest)) => {
+                journal.entries.drain(..oldest);
+                for (_, position) in journal.snapshots.iter_mut() {
+                    *position -= oldest;
+                }
+            }
+        }
+    }
+
+    /// Computes the librsync-style block signature of the decoded chunk stored under `sbc_hash`.
+    pub fn signature(
+        &self,
+        sbc_hash: &SBCHash,
+        block_len: usize,
+        strong_sum_len: usize,
+    ) -> Result<ChunkSignature> {
+        let data = self.decode(sbc_hash)?;
+        ChunkSignature::new(data.as_slice(), block_len, strong_sum_len)
+    }
+
+    /// Returns the keys of all chunks stored under the similarity hash `hash`,
+    /// simple chunks first. Chunks addressed by content hash are not found.
+    pub fn find_by_hash(&self, hash: u32) -> Vec<SBCHash> {
+        self.chunk_numbers
+            .get(&hash)
+            .into_iter()
+            .flatten()
+            .map(|&(delta, number)| SBCHash {
+                key: hash,
+                chunk_type: match delta {
+                    false => ChunkType::Simple(number),
+                    true => ChunkType::Delta(number),
+                },
+            })
+            .collect()
+    }
+
+    /// Records whether a chunk is stored under `sbc_hash` in the index of
+    /// [`SBCMap::find_by_hash`].
+    fn index_number(&mut self, sbc_hash: &SBCHash, stored: bool) {
+        let number = match sbc_hash.chunk_type {
+            ChunkType::Simple(number) => (false, number),
+            ChunkType::Delta(number) => (true, number),
+            ChunkType::Content(_) => return,
+        };
+        if stored {
+            self.chunk_numbers
+                .entry(sbc_hash.key)
+                .or_default()
+                .insert(number);
+        } else if let Some(numbers) = self.chunk_numbers.get_mut(&sbc_hash.key) {
+            numbers.remove(&number);
+            if numbers.is_empty() {
+                self.chunk_numbers.remove(&sbc_hash.key);
+            }
+        }
+    }
+
+    /// Iterates over all stored chunks in no particular order, decoding every
+    /// chunk only when the iterator reaches it.
+    pub fn iter_decoded(&self) -> impl Iterator<Item = (SBCHash, Result<Vec<u8>>)> + '_ {
+        self.keys().into_iter().map(move |sbc_hash| {
+            let data = self.decode(&sbc_hash);
+            (sbc_hash, data)
+        })
+    }
+
+    /// Size of the stored, possibly delta encoded, value of the chunk.
+    pub fn stored_len(&self, sbc_hash: &SBCHash) -> Option<usize> {
+        #[cfg(feature = "zstd")]
+        if let Some(cluster) = self.solid_clusters.get(sbc_hash) {
+            return cluster.frame_len(sbc_hash);
+        }
+        self.stored_value(sbc_hash).map(<[u8]>::len)
+    }
+
+    /// Decodes a chunk stored under the similarity hash `hash`, preferring the simple one.
+    pub fn get_any(&self, hash: u32) -> Result<Vec<u8>> {
+        match self.find_by_hash(hash).first() {
+            None => Err(SbcError::Decode {
+                key: hash,
+                reason: "no chunk with this hash is stored".to_string(),
+            }),
+            Some(sbc_hash) => self.decode(sbc_hash),
+        }
+    }
+
+    fn keys(&self) -> Vec<SBCHash> {
+        #[allow(unused_mut)]
+        let mut keys: Vec<SBCHash> = self.sbc_hashmap.keys().cloned().collect();
+        #[cfg(feature = "mmap")]
+        if let Some(storage) = &self.simple_storage {
+            keys.extend(storage.iter().map(|((key, number), _)| SBCHash {
+                key,
+                chunk_type: ChunkType::Simple(number),
+            }));
         }
+        #[cfg(feature = "zstd")]
+        keys.extend(self.solid_clusters.keys().cloned());
+        keys
+    }
+
+    fn record_insert(&mut self, sbc_hash: &SBCHash) {
+        if self.journal.is_some() {
+            let previous = self.shared_value(sbc_hash);
+            let preprocessing = self.preprocessing.get(sbc_hash).copied();
+            if let Some(journal) = &mut self.journal {
+                journal
+                    .entries
+                    .push((sbc_hash.clone(), previous, preprocessing));
+            }
+        }
+        if self.preprocessing.contains_key(sbc_hash) {
+            Arc::make_mut(&mut self.preprocessing).remove(sbc_hash);
+        }
+    }
+
+    fn set_preprocessing(&mut self, sbc_hash: SBCHash, preprocessing: Preprocessing) {
+        match preprocessing {
+            Preprocessing::None => Arc::make_mut(&mut self.preprocessing).remove(&sbc_hash),
+            _ => Arc::make_mut(&mut self.preprocessing).insert(sbc_hash, preprocessing),
+        };
+    }
+
+    /// Stores a chunk given as a `Vec` or as an `Arc` shared with other chunks.
+    fn store_value<C>(&mut self, sbc_hash: SBCHash, chunk: C) -> io::Result<()>
+    where
+        C: AsRef<[u8]> + Into<Arc<[u8]>>,
+    {
+        self.quota.stored_bytes -= self.stored_len(&sbc_hash).unwrap_or_default();
+        self.quota.stored_bytes += chunk.as_ref().len();
+        #[cfg(feature = "access-stats")]
+        self.access_stats.forget(&sbc_hash);
+        #[cfg(feature = "zstd")]
+        self.forget_solid(&sbc_hash);
+        self.index_number(&sbc_hash, true);
+        #[cfg(feature = "mmap")]
+        if let (&ChunkType::Simple(number), Some(storage)) =
+            (&sbc_hash.chunk_type, &mut self.simple_storage)
+        {
+            return storage.insert((sbc_hash.key, number), chunk.as_ref());
+        }
+        Arc::make_mut(&mut self.sbc_hashmap).insert(sbc_hash, chunk.into());
+        Ok(())
+    }
+
+    /// Inserts a chunk like [`chunkfs::Database::insert`], without copying a shared value.
+    fn insert_shared(&mut self, sbc_hash: SBCHash, chunk: Arc<[u8]>) -> io::Result<()> {
+        self.account(&sbc_hash, &chunk)?;
+        self.record_insert(&sbc_hash);
+        self.store_value(sbc_hash, chunk)
+    }
+
+    fn stored_value(&self, sbc_hash: &SBCHash) -> Option<&[u8]> {
+        #[cfg(feature = "mmap")]
+        if let (&ChunkType::Simple(number), Some(storage)) =
+            (&sbc_hash.chunk_type, &self.simple_storage)
+        {
+            return storage.get((sbc_hash.key, number));
+        }
+        self.sbc_hashmap.get(sbc_hash).map(AsRef::as_ref)
+    }
+
+    /// Stored value of the chunk, shared with the map unless it is kept in a
+    /// memory-mapped file.
+    fn shared_value(&self, sbc_hash: &SBCHash) -> Option<Arc<[u8]>> {
+        match self.sbc_hashmap.get(sbc_hash) {
+            Some(chunk) => Some(Arc::clone(chunk)),
+            None => self.stored_value(sbc_hash).map(Arc::from),
+        }
+    }
+
+    fn remove_value(&mut self, sbc_hash: &SBCHash) {
+        self.quota.stored_bytes -= self.stored_len(sbc_hash).unwrap_or_default();
+        #[cfg(feature = "access-stats")]
+        self.access_stats.forget(sbc_hash);
+        #[cfg(feature = "zstd")]
+        self.forget_solid(sbc_hash);
+        self.index_number(sbc_hash, false);
+        #[cfg(feature = "mmap")]
+        if let (&ChunkType::Simple(number), Some(storage)) =
+            (&sbc_hash.chunk_type, &mut self.simple_storage)
+        {
+            return storage.remove((sbc_hash.key, number));
+        }
+        Arc::make_mut(&mut self.sbc_hashmap).remove(sbc_hash);
     }
 }
 
@@ -38,3 +488,202 @@ impl Default for SBCMap {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chunkfs::Database;
+
+    fn simple_hash(key: u32) -> SBCHash {
+        SBCHash {
+            key,
+            chunk_type: ChunkType::Simple(0),
+        }
+    }
+
+    #[test]
+    fn test_rollback_removes_chunks_inserted_after_snapshot() {
+        let mut sbc_map = SBCMap::new();
+        sbc_map.insert(simple_hash(1), vec![1; 16]).unwrap();
+        let snapshot = sbc_map.snapshot();
+        sbc_map.insert(simple_hash(2), vec![2; 16]).unwrap();
+        sbc_map.insert(simple_hash(1), vec![3; 16]).unwrap();
+
+        sbc_map.rollback(snapshot).unwrap();
+
+        assert!(!sbc_map.contains(&simple_hash(2)));
+        assert_eq!(sbc_map.get(&simple_hash(1)).unwrap(), vec![1; 16]);
+    }
+
+    #[test]
+    fn test_rollback_to_nested_snapshots() {
+        let mut sbc_map = SBCMap::new();
+        let first = sbc_map.snapshot();
+        sbc_map.insert(simple_hash(1), vec![1; 16]).unwrap();
+        let second = sbc_map.snapshot();
+        sbc_map.insert(simple_hash(2), vec![2; 16]).unwrap();
+
+        sbc_map.rollback(second).unwrap();
+        assert!(sbc_map.contains(&simple_hash(1)));
+        assert!(!sbc_map.contains(&simple_hash(2)));
+
+        sbc_map.rollback(first).unwrap();
+        assert!(!sbc_map.contains(&simple_hash(1)));
+    }
+
+    #[test]
+    fn test_released_snapshots_stop_journaling() {
+        let mut sbc_map = SBCMap::new();
+        let first = sbc_map.snapshot();
+        sbc_map.insert(simple_hash(1), vec![1; 16]).unwrap();
+        let second = sbc_map.snapshot();
+        sbc_map.insert(simple_hash(2), vec![2; 16]).unwrap();
+
+        sbc_map.release(first).unwrap();
+        assert_eq!(sbc_map.journal.as_ref().unwrap().entries.len(), 1);
+        assert!(matches!(
+            sbc_map.rollback(first),
+            Err(SbcError::UnknownSnapshot)
+        ));
+        sbc_map.rollback(second).unwrap();
+        assert!(sbc_map.contains(&simple_hash(1)));
+        assert!(!sbc_map.contains(&simple_hash(2)));
+        assert!(sbc_map.journal.is_none());
+
+        let snapshot = sbc_map.snapshot();
+        sbc_map.release(snapshot).unwrap();
+        sbc_map.insert(simple_hash(3), vec![3; 16]).unwrap();
+        assert!(sbc_map.journal.is_none());
+        assert!(sbc_map.release(snapshot).is_err());
+    }
+
+    #[test]
+    fn test_missing_chunk_is_decode_error() {
+        let sbc_map = SBCMap::new();
+        assert!(matches!(
+            sbc_map.decode(&simple_hash(3)),
+            Err(SbcError::Decode { key: 3, .. })
+        ));
+        assert_eq!(
+            sbc_map.get(&simple_hash(3)).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+    }
+
+    #[test]
+    fn test_iter_decoded() {
+        let data: Vec<u8> = (0..4096).map(|_| rand::random::<u8>()).collect();
+        let mut similar_data = data.clone();
+        similar_data[10] ^= 1;
+        let (sbc_map, manifest) = crate::compress_chunks(
+            vec![data.clone(), similar_data.clone()],
+            &mut SBCScrubber::new(),
+        )
+        .unwrap();
+
+        let mut decoded: Vec<(SBCHash, Vec<u8>)> = sbc_map
+            .iter_decoded()
+            .map(|(sbc_hash, data)| (sbc_hash, data.unwrap()))
+            .collect();
+        decoded.sort_by_key(|(sbc_hash, _)| manifest.keys().iter().position(|key| key == sbc_hash));
+        assert_eq!(
+            decoded,
+            vec![
+                (manifest.keys()[0].clone(), data),
+                (manifest.keys()[1].clone(), similar_data)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_find_by_hash() {
+        let mut sbc_map = SBCMap::new();
+        let delta_hash = |number| SBCHash {
+            key: 5,
+            chunk_type: ChunkType::Delta(number),
+        };
+        sbc_map.insert(simple_hash(5), vec![1, 2, 3]).unwrap();
+        sbc_map.insert(delta_hash(0), vec![0, 0, 0, 5]).unwrap();
+        sbc_map.insert(delta_hash(1), vec![0, 0, 0, 5]).unwrap();
+
+        assert_eq!(
+            sbc_map.find_by_hash(5),
+            vec![simple_hash(5), delta_hash(0), delta_hash(1)]
+        );
+        assert!(sbc_map.find_by_hash(6).is_empty());
+        assert_eq!(sbc_map.get_any(5).unwrap(), vec![1, 2, 3]);
+        assert!(sbc_map.get_any(6).is_err());
+
+        sbc_map.remove_value(&delta_hash(0));
+        sbc_map.remove_value(&simple_hash(5));
+        let second_simple_hash = SBCHash {
+            key: 5,
+            chunk_type: ChunkType::Simple(1),
+        };
+        sbc_map.insert(second_simple_hash.clone(), vec![4]).unwrap();
+        assert_eq!(
+            sbc_map.find_by_hash(5),
+            vec![second_simple_hash, delta_hash(1)]
+        );
+        assert_eq!(sbc_map.get_any(5).unwrap(), vec![4]);
+    }
+
+    #[test]
+    fn test_chunks_are_shared_with_snapshots() {
+        let mut sbc_map = SBCMap::new();
+        sbc_map
+            .insert_shared(simple_hash(1), Arc::from(vec![1; 16]))
+            .unwrap();
+        let chunk = sbc_map.shared_value(&simple_hash(1)).unwrap();
+        let snapshot = sbc_map.snapshot();
+        sbc_map.insert(simple_hash(1), vec![2; 16]).unwrap();
+        assert_eq!(sbc_map.get(&simple_hash(1)).unwrap(), vec![2; 16]);
+
+        sbc_map.rollback(snapshot).unwrap();
+        assert!(Arc::ptr_eq(
+            &sbc_map.shared_value(&simple_hash(1)).unwrap(),
+            &chunk
+        ));
+    }
+
+    #[test]
+    fn test_display_keys() {
+        assert_eq!(simple_hash(7).to_string(), "s7.0");
+        let delta_hash = SBCHash {
+            key: 7,
+            chunk_type: ChunkType::Delta(2),
+        };
+        assert_eq!(delta_hash.to_string(), "d7.2");
+        assert_eq!(delta_hash.hash(), 7);
+        assert_eq!(delta_hash.chunk_type(), &ChunkType::Delta(2));
+        let content_hash = content_hash::content_key([0xab; content_hash::CONTENT_HASH_LEN]);
+        assert_eq!(content_hash.to_string(), format!("c{}", "ab".repeat(32)));
+
+        let mut similar_data = vec![1; 16];
+        similar_data[3] = 2;
+        let mut sbc_map = SBCMap::new();
+        sbc_map.insert(simple_hash(7), vec![1; 16]).unwrap();
+        sbc_map
+            .insert(
+                delta_hash.clone(),
+                encode_delta(&similar_data, &[1; 16], 7).unwrap(),
+            )
+            .unwrap();
+        assert_eq!(delta_hash.parent_hash(&sbc_map), Some(simple_hash(7)));
+        assert_eq!(simple_hash(7).parent_hash(&sbc_map), None);
+    }
+
+    #[test]
+    fn test_unknown_delta_format_is_refused() {
+        let mut sbc_map = SBCMap::new();
+        sbc_map.insert(simple_hash(1), vec![1; 16]).unwrap();
+        let delta_hash = SBCHash {
+            key: 2,
+            chunk_type: ChunkType::Delta(0),
+        };
+        sbc_map
+            .insert(delta_hash.clone(), vec![0, 0, 0, 1, 0xc0, 0, 0, 0])
+            .unwrap();
+        assert!(sbc_map.get(&delta_hash).is_err());
+    }
+}
@@ -0,0 +1,167 @@
+use crate::{Result, SBCMap};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// Work done by a [`Maintenance`] thread so far.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MaintenanceProgress {
+    /// Passes which found the map idle.
+    pub passes: usize,
+    /// Bytes freed in the file of simple chunks by compaction.
+    pub freed_bytes: usize,
+}
+
+#[derive(Default)]
+struct Shared {
+    paused: AtomicBool,
+    stopped: AtomicBool,
+    passes: AtomicUsize,
+    freed_bytes: AtomicUsize,
+}
+
+/// Handle of a background thread compacting a map created with
+/// [`SBCMap::with_mmap_storage`]. Dropping the handle stops the thread.
+pub struct Maintenance {
+    shared: Arc<Shared>,
+    thread: Option<JoinHandle<Result<()>>>,
+}
+
+impl Maintenance {
+    /// Spawns a thread which every `interval` compacts `map` once at least
+    /// `min_dead_bytes` of its file are taken by removed or replaced chunks.
+    /// Passes finding the map locked are skipped, so the thread does not wait
+    /// for readers and writers.
+    pub fn spawn(
+        map: Arc<Mutex<SBCMap>>,
+        interval: Duration,
+        min_dead_bytes: usize,
+    ) -> Maintenance {
+        let shared = Arc::new(Shared::default());
+        let thread = thread::spawn({
+            let shared = Arc::clone(&shared);
+            move || {
+                while !shared.stopped.load(Ordering::Acquire) {
+                    if !shared.paused.load(Ordering::Acquire) {
+                        if let Ok(mut map) = map.try_lock() {
+                            if map.dead_bytes() >= min_dead_bytes {
+                                let freed_bytes = map.compact()?;
+                                shared.freed_bytes.fetch_add(freed_bytes, Ordering::Relaxed);
+                            }
+                            shared.passes.fetch_add(1, Ordering::Relaxed);
+                        }
+                    }
+                    thread::park_timeout(interval);
+                }
+                Ok(())
+            }
+        });
+        Maintenance {
+            shared,
+            thread: Some(thread),
+        }
+    }
+
+    /// Skips passes until [`Maintenance::resume`] is called.
+    pub fn pause(&self) {
+        self.shared.paused.store(true, Ordering::Release);
+    }
+
+    pub fn resume(&self) {
+        self.shared.paused.store(false, Ordering::Release);
+        if let Some(thread) = &self.thread {
+            thread.thread().unpark();
+        }
+    }
+
+    pub fn progress(&self) -> MaintenanceProgress {
+        MaintenanceProgress {
+            passes: self.shared.passes.load(Ordering::Relaxed),
+            freed_bytes: self.shared.freed_bytes.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Stops the thread and waits for it to finish. Returns the error which
+    /// ended it early, if any.
+    pub fn stop(mut self) -> Result<MaintenanceProgress> {
+        self.join()?;
+        Ok(self.progress())
+    }
+
+    fn join(&mut self) -> Result<()> {
+        let Some(thread) = self.thread.take() else {
+            return Ok(());
+        };
+        self.shared.stopped.store(true, Ordering::Release);
+        thread.thread().unpark();
+        thread.join().expect("maintenance thread panicked")
+    }
+}
+
+impl Drop for Maintenance {
+    fn drop(&mut self) {
+        let _ = self.join();
+    }
+}
+
+impl SBCMap {
+    /// Bytes of the file of simple chunks taken by removed or replaced chunks,
+    /// 0 for maps kept in memory.
+    pub fn dead_bytes(&self) -> usize {
+        self.simple_storage
+            .as_ref()
+            .map_or(0, |storage| storage.dead_bytes())
+    }
+
+    /// Moves the chunks in the file of simple chunks together, freeing the
+    /// space of removed or replaced ones, and flushes the map since the index
+    /// written before no longer matches the file. Returns the number of freed
+    /// bytes.
+    pub fn compact(&mut self) -> Result<usize> {
+        let Some(storage) = &mut self.simple_storage else {
+            return Ok(0);
+        };
+        let freed_bytes = storage.compact()?;
+        self.flush()?;
+        Ok(freed_bytes)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{ChunkType, SBCHash};
+    use chunkfs::Database;
+
+    #[test]
+    fn test_maintenance_compacts_map() {
+        let path = std::env::temp_dir().join(format!("sbc_maintenance_{}", std::process::id()));
+        let mut map = SBCMap::with_mmap_storage(&path).unwrap();
+        let sbc_hash = SBCHash {
+            key: 1,
+            chunk_type: ChunkType::Simple(0),
+        };
+        map.insert(sbc_hash.clone(), vec![1; 1000]).unwrap();
+        map.insert(sbc_hash.clone(), vec![2; 1000]).unwrap();
+        assert_eq!(map.dead_bytes(), 1000);
+        let map = Arc::new(Mutex::new(map));
+
+        let maintenance = Maintenance::spawn(Arc::clone(&map), Duration::from_millis(1), 1);
+        while maintenance.progress().passes == 0 {
+            thread::sleep(Duration::from_millis(1));
+        }
+        maintenance.pause();
+        let progress = maintenance.stop().unwrap();
+        assert_eq!(progress.freed_bytes, 1000);
+
+        let map = map.lock().unwrap();
+        assert_eq!(map.dead_bytes(), 0);
+        assert_eq!(map.get(&sbc_hash).unwrap(), vec![2; 1000]);
+        drop(map);
+        let mut index_path = path.clone().into_os_string();
+        index_path.push(".index");
+        std::fs::remove_file(index_path).unwrap();
+        std::fs::remove_file(path).unwrap();
+    }
+}
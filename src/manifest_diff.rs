@@ -0,0 +1,175 @@
+use crate::{Manifest, SBCHash};
+use std::collections::HashMap;
+
+/// Base positions tried for every key when looking for the longest copied run,
+/// so manifests full of one repeated key are diffed in linear time.
+const MAX_CANDIDATES: usize = 16;
+
+/// Run of keys of a [`ManifestDiff`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ManifestRun {
+    /// `len` keys of the base manifest starting at `start`.
+    Copy { start: usize, len: usize },
+    /// Keys not found in the base manifest at this place.
+    Insert(Vec<SBCHash>),
+}
+
+/// Manifest stored as runs of keys copied from a base manifest and inserted
+/// keys, see [`Manifest::diff`]. Keys of the base left out of every copied run
+/// are deleted.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ManifestDiff {
+    base_len: usize,
+    runs: Vec<ManifestRun>,
+}
+
+impl ManifestDiff {
+    pub fn runs(&self) -> &[ManifestRun] {
+        self.runs.as_slice()
+    }
+
+    /// Keys the diff stores itself, the inserted ones.
+    pub fn inserted_len(&self) -> usize {
+        self.runs
+            .iter()
+            .map(|run| match run {
+                ManifestRun::Copy { .. } => 0,
+                ManifestRun::Insert(keys) => keys.len(),
+            })
+            .sum()
+    }
+
+    /// Fraction of the keys of the manifest copied from the base. Files whose
+    /// manifests share most keys are worth storing as diffs.
+    pub fn shared_fraction(&self) -> f64 {
+        let copied: usize = self
+            .runs
+            .iter()
+            .map(|run| match run {
+                ManifestRun::Copy { len, .. } => *len,
+                ManifestRun::Insert(_) => 0,
+            })
+            .sum();
+        match copied + self.inserted_len() {
+            0 => 1.0,
+            len => copied as f64 / len as f64,
+        }
+    }
+
+    /// Rebuilds the manifest from `base`, `None` if the diff was made
+    /// against a manifest of another length.
+    pub fn apply(&self, base: &Manifest) -> Option<Manifest> {
+        if base.len() != self.base_len {
+            return None;
+        }
+        let mut keys = Vec::new();
+        for run in &self.runs {
+            match run {
+                ManifestRun::Copy { start, len } => {
+                    keys.extend_from_slice(base.keys().get(*start..start + len)?)
+                }
+                ManifestRun::Insert(inserted) => keys.extend_from_slice(inserted),
+            }
+        }
+        Some(Manifest { keys })
+    }
+}
+
+impl Manifest {
+    /// Diffs the manifest against `base`, e.g. the manifest of a previous
+    /// version of the same file: every key is copied as part of the longest
+    /// run of keys found in the base at that place, or inserted.
+    pub fn diff(&self, base: &Manifest) -> ManifestDiff {
+        let mut positions: HashMap<&SBCHash, Vec<usize>> = HashMap::new();
+        for (position, sbc_hash) in base.keys().iter().enumerate() {
+            let candidates = positions.entry(sbc_hash).or_default();
+            if candidates.len() < MAX_CANDIDATES {
+                candidates.push(position);
+            }
+        }
+        let run_len = |start: usize, from: usize| {
+            base.keys()[start..]
+                .iter()
+                .zip(&self.keys()[from..])
+                .take_while(|(base_key, key)| base_key == key)
+                .count()
+        };
+
+        let mut runs = Vec::new();
+        let mut position = 0;
+        while position < self.len() {
+            let sbc_hash = &self.keys()[position];
+            let copy = positions.get(sbc_hash).and_then(|candidates| {
+                candidates
+                    .iter()
+                    .map(|&start| (start, run_len(start, position)))
+                    .max_by_key(|&(start, len)| (len, usize::MAX - start))
+            });
+            match (copy, runs.last_mut()) {
+                (Some((start, len)), _) => {
+                    runs.push(ManifestRun::Copy { start, len });
+                    position += len;
+                }
+                (None, Some(ManifestRun::Insert(keys))) => {
+                    keys.push(sbc_hash.clone());
+                    position += 1;
+                }
+                (None, _) => {
+                    runs.push(ManifestRun::Insert(vec![sbc_hash.clone()]));
+                    position += 1;
+                }
+            }
+        }
+        ManifestDiff {
+            base_len: base.len(),
+            runs,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ChunkType;
+
+    fn manifest(keys: &[u32]) -> Manifest {
+        Manifest {
+            keys: keys
+                .iter()
+                .map(|&key| SBCHash {
+                    key,
+                    chunk_type: ChunkType::Simple(0),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_manifest_diff() {
+        let base = manifest(&[1, 2, 3, 4, 5, 6, 7, 8]);
+        let revision = manifest(&[1, 2, 3, 9, 10, 4, 5, 7, 8, 1]);
+        let diff = revision.diff(&base);
+
+        assert_eq!(
+            diff.runs(),
+            [
+                ManifestRun::Copy { start: 0, len: 3 },
+                ManifestRun::Insert(manifest(&[9, 10]).keys),
+                ManifestRun::Copy { start: 3, len: 2 },
+                ManifestRun::Copy { start: 6, len: 2 },
+                ManifestRun::Copy { start: 0, len: 1 },
+            ]
+        );
+        assert_eq!(diff.inserted_len(), 2);
+        assert_eq!(diff.shared_fraction(), 0.8);
+        assert_eq!(diff.apply(&base).unwrap().keys(), revision.keys());
+        assert!(diff.apply(&revision).is_none());
+
+        let diff = base.diff(&Manifest::default());
+        assert_eq!(diff.shared_fraction(), 0.0);
+        assert_eq!(
+            diff.apply(&Manifest::default()).unwrap().keys(),
+            base.keys()
+        );
+    }
+}
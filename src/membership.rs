@@ -0,0 +1,89 @@
+use crate::{SBCHash, SBCMap};
+use chunkfs::Database;
+
+/// Cluster a chunk belongs to, see [`SBCMap::cluster_of`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ClusterMembership {
+    /// Chunk the delta chunks of the cluster are encoded against.
+    pub parent: SBCHash,
+    /// Delta chunks encoded against the parent, ordered by key.
+    pub children: Vec<SBCHash>,
+}
+
+impl SBCMap {
+    /// Returns the parent and all delta chunks of the cluster of `sbc_hash`,
+    /// which is either the parent or one of the delta chunks, e.g. to see which
+    /// chunks are affected by deleting or updating it. Returns `None` for
+    /// chunks which are not stored.
+    pub fn cluster_of(&self, sbc_hash: &SBCHash) -> Option<ClusterMembership> {
+        if !self.contains(sbc_hash) {
+            return None;
+        }
+        let parent = self.parent_of(sbc_hash).unwrap_or_else(|| sbc_hash.clone());
+        Some(ClusterMembership {
+            children: self.children_of(&parent),
+            parent,
+        })
+    }
+
+    /// Delta chunks encoded against `parent_hash`, ordered by key.
+    pub(crate) fn children_of(&self, parent_hash: &SBCHash) -> Vec<SBCHash> {
+        let mut children: Vec<SBCHash> = self
+            .keys()
+            .into_iter()
+            .filter(|sbc_hash| self.parent_of(sbc_hash).as_ref() == Some(parent_hash))
+            .collect();
+        children.sort_by_key(|sbc_hash| sbc_hash.key);
+        children
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ChunkType;
+    use chunkfs::Database;
+
+    #[test]
+    fn test_cluster_of() {
+        let data: Vec<u8> = (0..4096).map(|_| rand::random::<u8>()).collect();
+        let parent = SBCHash {
+            key: 1,
+            chunk_type: ChunkType::Simple(0),
+        };
+        let mut map = SBCMap::new();
+        map.insert(parent.clone(), data.clone()).unwrap();
+        let mut children = Vec::new();
+        for key in [3, 2] {
+            let mut similar_data = data.clone();
+            similar_data[key as usize] ^= 1;
+            let delta_hash = SBCHash {
+                key,
+                chunk_type: ChunkType::Delta(0),
+            };
+            let delta = crate::encode_delta(&similar_data, &data, 1).unwrap();
+            map.insert(delta_hash.clone(), delta).unwrap();
+            children.insert(0, delta_hash);
+        }
+        let other = SBCHash {
+            key: 5,
+            chunk_type: ChunkType::Simple(0),
+        };
+        map.insert(other.clone(), vec![5; 64]).unwrap();
+
+        let membership = ClusterMembership { parent, children };
+        assert_eq!(map.cluster_of(&membership.parent), Some(membership.clone()));
+        assert_eq!(
+            map.cluster_of(&membership.children[1]),
+            Some(membership.clone())
+        );
+        assert_eq!(
+            map.cluster_of(&other),
+            Some(ClusterMembership {
+                parent: other,
+                children: Vec::new(),
+            })
+        );
+        assert_eq!(map.cluster_of(&SBCHash::default()), None);
+    }
+}
@@ -0,0 +1,126 @@
+use crate::{Result, SBCHash, SBCMap, SbcError};
+use chunkfs::Database;
+#[cfg(feature = "zstd")]
+use std::sync::Arc;
+
+impl SBCMap {
+    /// Moves all chunks of `other` into the map under the same keys, e.g. to
+    /// combine stores of separate datasets. Chunks stored in both maps must be
+    /// equal, otherwise nothing is moved.
+    pub fn absorb(&mut self, other: SBCMap) -> Result<()> {
+        let keys = other.keys();
+        self.check_conflicts(&other, keys.as_slice())?;
+        other.copy_chunks(keys, self)
+    }
+
+    /// Copies the chunk `parent_hash` and all delta chunks encoded against it
+    /// to `dest` under the same keys, e.g. to split a map into shards. Returns
+    /// the keys of the copied chunks.
+    pub fn copy_cluster(&self, parent_hash: &SBCHash, dest: &mut SBCMap) -> Result<Vec<SBCHash>> {
+        if !self.contains(parent_hash) {
+            return Err(SbcError::Decode {
+                key: parent_hash.key,
+                reason: "parent chunk is not stored".to_string(),
+            });
+        }
+        let mut keys = vec![parent_hash.clone()];
+        keys.extend(self.children_of(parent_hash));
+        dest.check_conflicts(self, keys.as_slice())?;
+        self.copy_chunks(keys.clone(), dest)?;
+        Ok(keys)
+    }
+
+    fn check_conflicts(&self, other: &SBCMap, keys: &[SBCHash]) -> Result<()> {
+        for sbc_hash in keys {
+            #[cfg(feature = "zstd")]
+            if let Some(cluster) = self.solid_clusters.get(sbc_hash) {
+                match other.solid_clusters.get(sbc_hash) {
+                    Some(other_cluster) if Arc::ptr_eq(cluster, other_cluster) => continue,
+                    _ => return Err(SbcError::KeyConflict { key: sbc_hash.key }),
+                }
+            }
+            let Some(data) = self.stored_value(sbc_hash) else {
+                continue;
+            };
+            if Some(data) != other.stored_value(sbc_hash)
+                || self.preprocessing.get(sbc_hash) != other.preprocessing.get(sbc_hash)
+            {
+                return Err(SbcError::KeyConflict { key: sbc_hash.key });
+            }
+        }
+        Ok(())
+    }
+
+    fn copy_chunks(&self, keys: Vec<SBCHash>, dest: &mut SBCMap) -> Result<()> {
+        for sbc_hash in keys {
+            #[cfg(feature = "zstd")]
+            if let Some(cluster) = self.solid_clusters.get(&sbc_hash) {
+                dest.insert_solid(sbc_hash, Arc::clone(cluster))?;
+                continue;
+            }
+            let Some(data) = self.shared_value(&sbc_hash) else {
+                continue;
+            };
+            dest.insert_shared(sbc_hash.clone(), data)?;
+            if let Some(&preprocessing) = self.preprocessing.get(&sbc_hash) {
+                dest.set_preprocessing(sbc_hash, preprocessing);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ChunkType;
+    use chunkfs::Database;
+
+    fn map_with_cluster(parent_key: u32) -> (SBCMap, SBCHash, SBCHash, Vec<u8>) {
+        let data: Vec<u8> = (0..4096).map(|_| rand::random::<u8>()).collect();
+        let mut similar_data = data.clone();
+        similar_data[10] ^= 1;
+        let parent_hash = SBCHash {
+            key: parent_key,
+            chunk_type: ChunkType::Simple(0),
+        };
+        let delta_hash = SBCHash {
+            key: parent_key + 1,
+            chunk_type: ChunkType::Delta(0),
+        };
+        let mut map = SBCMap::new();
+        map.insert(parent_hash.clone(), data.clone()).unwrap();
+        let delta = crate::encode_delta(&similar_data, &data, parent_key).unwrap();
+        map.insert(delta_hash.clone(), delta).unwrap();
+        (map, parent_hash, delta_hash, similar_data)
+    }
+
+    #[test]
+    fn test_absorb() {
+        let (mut map, _, first_delta, first_data) = map_with_cluster(1);
+        let (other, _, second_delta, second_data) = map_with_cluster(10);
+        map.absorb(other).unwrap();
+        assert_eq!(map.get(&first_delta).unwrap(), first_data);
+        assert_eq!(map.get(&second_delta).unwrap(), second_data);
+
+        let (other, ..) = map_with_cluster(1);
+        assert!(matches!(
+            map.absorb(other),
+            Err(SbcError::KeyConflict { .. })
+        ));
+        assert_eq!(map.get(&first_delta).unwrap(), first_data);
+    }
+
+    #[test]
+    fn test_copy_cluster() {
+        let (mut map, parent_hash, delta_hash, data) = map_with_cluster(1);
+        let (other, other_parent, ..) = map_with_cluster(10);
+        map.absorb(other).unwrap();
+
+        let mut dest = SBCMap::new();
+        let keys = map.copy_cluster(&parent_hash, &mut dest).unwrap();
+        assert_eq!(keys, vec![parent_hash, delta_hash.clone()]);
+        assert_eq!(dest.get(&delta_hash).unwrap(), data);
+        assert!(!dest.contains(&other_parent));
+    }
+}
@@ -0,0 +1,114 @@
+const SHINGLE_LEN: usize = 8;
+const SKETCH_SIZE: usize = 64;
+
+fn mix(mut value: u64) -> u64 {
+    value = (value ^ (value >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    value = (value ^ (value >> 27)).wrapping_mul(0x94d049bb133111eb);
+    value ^ (value >> 31)
+}
+
+fn shingle_hash(shingle: &[u8]) -> u64 {
+    let mut buf = [0u8; SHINGLE_LEN];
+    buf[..shingle.len()].copy_from_slice(shingle);
+    mix(u64::from_le_bytes(buf))
+}
+
+pub(crate) struct MinHashSketch {
+    min_hashes: [u64; SKETCH_SIZE],
+}
+
+impl MinHashSketch {
+    pub fn new(data: &[u8]) -> MinHashSketch {
+        let mut min_hashes = [u64::MAX; SKETCH_SIZE];
+        let shingle_count = data.len().saturating_sub(SHINGLE_LEN) + 1;
+        for start in 0..shingle_count {
+            let end = std::cmp::min(start + SHINGLE_LEN, data.len());
+            let hash = shingle_hash(&data[start..end]);
+            for (seed, min_hash) in min_hashes.iter_mut().enumerate() {
+                let permuted_hash = mix(hash ^ (seed as u64 + 1).wrapping_mul(0x9e3779b97f4a7c15));
+                if permuted_hash < *min_hash {
+                    *min_hash = permuted_hash;
+                }
+            }
+        }
+        MinHashSketch { min_hashes }
+    }
+
+    /// Estimation of the Jaccard similarity of shingle sets of two chunks.
+    pub fn resemblance(&self, other: &MinHashSketch) -> f64 {
+        let eq_count = self
+            .min_hashes
+            .iter()
+            .zip(other.min_hashes.iter())
+            .filter(|(a, b)| a == b)
+            .count();
+        eq_count as f64 / SKETCH_SIZE as f64
+    }
+}
+
+/// Splits chunks into groups, where every chunk resembles the first chunk of its group.
+pub(crate) fn group_by_resemblance(
+    sketches: &[MinHashSketch],
+    min_resemblance: f64,
+) -> Vec<Vec<usize>> {
+    let mut groups: Vec<Vec<usize>> = Vec::new();
+    for (chunk_id, sketch) in sketches.iter().enumerate() {
+        match groups
+            .iter_mut()
+            .find(|group| sketches[group[0]].resemblance(sketch) >= min_resemblance)
+        {
+            None => groups.push(vec![chunk_id]),
+            Some(group) => group.push(chunk_id),
+        }
+    }
+    groups
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_resemblance_of_similar_chunks() {
+        let data: Vec<u8> = (0..8192).map(|_| rand::random::<u8>()).collect();
+        let mut similar_data = data[15..].to_vec();
+        similar_data[4000] = similar_data[4000].wrapping_add(1);
+
+        let sketch = MinHashSketch::new(data.as_slice());
+        assert_eq!(
+            sketch.resemblance(&MinHashSketch::new(data.as_slice())),
+            1.0
+        );
+        assert!(sketch.resemblance(&MinHashSketch::new(similar_data.as_slice())) > 0.8);
+    }
+
+    #[test]
+    fn test_resemblance_of_different_chunks() {
+        let data: Vec<u8> = (0..8192).map(|_| rand::random::<u8>()).collect();
+        let other_data: Vec<u8> = (0..8192).map(|_| rand::random::<u8>()).collect();
+
+        let sketch = MinHashSketch::new(data.as_slice());
+        assert!(sketch.resemblance(&MinHashSketch::new(other_data.as_slice())) < 0.1);
+    }
+
+    #[test]
+    fn test_sketch_of_short_chunk() {
+        let sketch = MinHashSketch::new(&[1, 2, 3]);
+        assert_eq!(sketch.resemblance(&MinHashSketch::new(&[1, 2, 3])), 1.0);
+    }
+
+    #[test]
+    fn test_group_by_resemblance() {
+        let data: Vec<u8> = (0..4096).map(|_| rand::random::<u8>()).collect();
+        let other_data: Vec<u8> = (0..4096).map(|_| rand::random::<u8>()).collect();
+        let sketches = vec![
+            MinHashSketch::new(data.as_slice()),
+            MinHashSketch::new(other_data.as_slice()),
+            MinHashSketch::new(&data[10..]),
+            MinHashSketch::new(&other_data[..4000]),
+        ];
+
+        let groups = group_by_resemblance(sketches.as_slice(), 0.5);
+        assert_eq!(groups, vec![vec![0, 2], vec![1, 3]]);
+    }
+}
@@ -0,0 +1,242 @@
+use memmap2::MmapMut;
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::path::{Path, PathBuf};
+
+const INITIAL_CAPACITY: usize = 1 << 20;
+
+/// Similarity hash and number of a simple chunk.
+pub(crate) type StorageKey = (u32, u16);
+
+pub(crate) struct MmapStorage {
+    path: PathBuf,
+    file: File,
+    mmap: MmapMut,
+    len: usize,
+    chunks: HashMap<StorageKey, (usize, usize)>,
+}
+
+impl MmapStorage {
+    /// Creates the file at `path`, refusing to overwrite an existing one.
+    pub fn create(path: &Path) -> io::Result<MmapStorage> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(path)?;
+        MmapStorage::map(path, file, 0, HashMap::new())
+    }
+
+    /// Reopens the file at `path` whose first `len` bytes hold `chunks`, as
+    /// recorded by an index written before.
+    pub fn open(
+        path: &Path,
+        len: usize,
+        chunks: HashMap<StorageKey, (usize, usize)>,
+    ) -> io::Result<MmapStorage> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        MmapStorage::map(path, file, len, chunks)
+    }
+
+    fn map(
+        path: &Path,
+        file: File,
+        len: usize,
+        chunks: HashMap<StorageKey, (usize, usize)>,
+    ) -> io::Result<MmapStorage> {
+        file.try_lock()?;
+        let file_len = file.metadata()?.len() as usize;
+        if file_len < len
+            || chunks.values().any(|&(offset, chunk_len)| {
+                offset.checked_add(chunk_len).is_none_or(|end| end > len)
+            })
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "index refers to chunks past the end of the file",
+            ));
+        }
+        if file_len < INITIAL_CAPACITY {
+            file.set_len(INITIAL_CAPACITY as u64)?;
+        }
+        // SAFETY: the file is exclusively locked while this storage is alive, so
+        // other storages do not modify it. The lock is advisory: a process
+        // writing to the file without taking it breaks the mapping.
+        let mmap = unsafe { MmapMut::map_mut(&file)? };
+        Ok(MmapStorage {
+            path: path.to_path_buf(),
+            file,
+            mmap,
+            len,
+            chunks,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Bytes of the file holding chunks, removed ones included.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Offsets and lengths of the stored chunks in the file.
+    pub fn places(&self) -> impl Iterator<Item = (StorageKey, (usize, usize))> + '_ {
+        self.chunks.iter().map(|(&key, &place)| (key, place))
+    }
+
+    /// Writes the modified pages of the file to disk.
+    pub fn flush(&self) -> io::Result<()> {
+        self.mmap.flush()
+    }
+
+    pub fn insert(&mut self, key: StorageKey, data: &[u8]) -> io::Result<()> {
+        let end = self.len + data.len();
+        if end > self.mmap.len() {
+            self.grow(end)?;
+        }
+        self.mmap[self.len..end].copy_from_slice(data);
+        self.chunks.insert(key, (self.len, data.len()));
+        self.len = end;
+        Ok(())
+    }
+
+    pub fn get(&self, key: StorageKey) -> Option<&[u8]> {
+        self.chunks
+            .get(&key)
+            .map(|&(offset, len)| &self.mmap[offset..offset + len])
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (StorageKey, &[u8])> {
+        self.chunks
+            .iter()
+            .map(|(&key, &(offset, len))| (key, &self.mmap[offset..offset + len]))
+    }
+
+    pub fn remove(&mut self, key: StorageKey) {
+        self.chunks.remove(&key);
+    }
+
+    /// Bytes of removed or replaced chunks still taking space in the file.
+    pub fn dead_bytes(&self) -> usize {
+        self.len - self.chunks.values().map(|&(_, len)| len).sum::<usize>()
+    }
+
+    /// Moves the stored chunks to the start of the file, in their order,
+    /// dropping the space of removed ones. Returns the number of freed bytes.
+    pub fn compact(&mut self) -> io::Result<usize> {
+        let mut chunks: Vec<(StorageKey, (usize, usize))> = self
+            .chunks
+            .iter()
+            .map(|(&key, &place)| (key, place))
+            .collect();
+        chunks.sort_by_key(|&(_, (offset, _))| offset);
+        let mut end = 0;
+        for (key, (offset, len)) in chunks {
+            self.mmap.copy_within(offset..offset + len, end);
+            self.chunks.insert(key, (end, len));
+            end += len;
+        }
+        let freed_bytes = self.len - end;
+        self.len = end;
+        self.mmap.flush()?;
+        Ok(freed_bytes)
+    }
+
+    fn grow(&mut self, min_capacity: usize) -> io::Result<()> {
+        let capacity = std::cmp::max(min_capacity, self.mmap.len() * 2);
+        self.mmap.flush()?;
+        self.file.set_len(capacity as u64)?;
+        // SAFETY: see `map`, the file stays locked by this storage.
+        self.mmap = unsafe { MmapMut::map_mut(&self.file)? };
+        Ok(())
+    }
+}
+
+impl Drop for MmapStorage {
+    fn drop(&mut self) {
+        let _ = self.mmap.flush();
+        let _ = self.file.set_len(self.len as u64);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("sbc_{}_{}", name, std::process::id()))
+    }
+
+    #[test]
+    fn test_insert_and_get() {
+        let path = temp_path("mmap_insert_and_get");
+        let mut storage = MmapStorage::create(&path).unwrap();
+        let data: Vec<u8> = (0..8192).map(|_| rand::random::<u8>()).collect();
+        storage.insert((7, 0), data.as_slice()).unwrap();
+
+        assert!(storage.get((7, 1)).is_none());
+        assert_eq!(storage.get((7, 0)).unwrap(), data.as_slice());
+        assert!(matches!(
+            MmapStorage::create(&path),
+            Err(error) if error.kind() == io::ErrorKind::AlreadyExists
+        ));
+        assert!(MmapStorage::open(&path, 0, HashMap::new()).is_err());
+
+        let places: HashMap<StorageKey, (usize, usize)> = storage.places().collect();
+        let len = storage.len();
+        drop(storage);
+        let storage = MmapStorage::open(&path, len, places).unwrap();
+        assert_eq!(storage.get((7, 0)).unwrap(), data.as_slice());
+        drop(storage);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_storage_grows_past_initial_capacity() {
+        let path = temp_path("mmap_grow");
+        let mut storage = MmapStorage::create(&path).unwrap();
+        let chunks: Vec<Vec<u8>> = (0..200)
+            .map(|_| (0..16384).map(|_| rand::random::<u8>()).collect())
+            .collect();
+        for (key, chunk) in chunks.iter().enumerate() {
+            storage.insert((key as u32, 0), chunk.as_slice()).unwrap();
+        }
+
+        for (key, chunk) in chunks.iter().enumerate() {
+            assert_eq!(storage.get((key as u32, 0)).unwrap(), chunk.as_slice());
+        }
+        drop(storage);
+        assert_eq!(
+            std::fs::metadata(&path).unwrap().len(),
+            (200 * 16384) as u64
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_compact() {
+        let path = temp_path("mmap_compact");
+        let mut storage = MmapStorage::create(&path).unwrap();
+        let chunks: Vec<Vec<u8>> = (0..4)
+            .map(|_| (0..1000).map(|_| rand::random::<u8>()).collect())
+            .collect();
+        for (key, chunk) in chunks.iter().enumerate() {
+            storage.insert((key as u32, 0), chunk.as_slice()).unwrap();
+        }
+        storage.remove((1, 0));
+        storage.insert((2, 0), &chunks[3][..500]).unwrap();
+        assert_eq!(storage.dead_bytes(), 2000);
+
+        assert_eq!(storage.compact().unwrap(), 2000);
+        assert_eq!(storage.dead_bytes(), 0);
+        assert_eq!(storage.get((0, 0)).unwrap(), chunks[0].as_slice());
+        assert_eq!(storage.get((2, 0)).unwrap(), &chunks[3][..500]);
+        assert_eq!(storage.get((3, 0)).unwrap(), chunks[3].as_slice());
+        drop(storage);
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), 2500);
+        std::fs::remove_file(path).unwrap();
+    }
+}
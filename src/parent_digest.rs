@@ -0,0 +1,68 @@
+use blake2::digest::consts::U8;
+use blake2::{Blake2b, Digest};
+use std::borrow::Cow;
+
+/// Word following the parent key in deltas which carry a digest of their parent.
+/// Like the zstd marker it is not a valid Levenshtein action.
+const PARENT_DIGEST_MARKER: [u8; 4] = [0xff, 0xff, 0xff, 0xfe];
+const PARENT_DIGEST_LEN: usize = 8;
+
+type ParentDigest = [u8; PARENT_DIGEST_LEN];
+
+pub(crate) fn digest(parent_data: &[u8]) -> ParentDigest {
+    Blake2b::<U8>::digest(parent_data).into()
+}
+
+/// Marker and digest of the parent, inserted after the parent key of a delta.
+pub(crate) fn header(parent_data: &[u8]) -> Vec<u8> {
+    [&PARENT_DIGEST_MARKER[..], &digest(parent_data)[..]].concat()
+}
+
+/// Checks the parent digest of `delta_chunk`, if it has one, and returns the
+/// delta without it. Returns `None` when the digest does not match.
+pub(crate) fn strip<'a>(delta_chunk: &'a [u8], parent_data: &[u8]) -> Option<Cow<'a, [u8]>> {
+    let (stored_digest, delta_chunk) = split(delta_chunk)?;
+    if stored_digest.is_some_and(|stored_digest| stored_digest != digest(parent_data)) {
+        return None;
+    }
+    Some(delta_chunk)
+}
+
+/// Returns the parent digest of `delta_chunk`, if it has one, and the delta
+/// without it, without checking the digest.
+pub(crate) fn split(delta_chunk: &[u8]) -> Option<(Option<ParentDigest>, Cow<'_, [u8]>)> {
+    if delta_chunk.get(4..8) != Some(&PARENT_DIGEST_MARKER[..]) {
+        return Some((None, Cow::Borrowed(delta_chunk)));
+    }
+    let stored_digest = delta_chunk.get(8..8 + PARENT_DIGEST_LEN)?;
+    Some((
+        Some(stored_digest.try_into().unwrap()),
+        Cow::Owned([&delta_chunk[..4], &delta_chunk[8 + PARENT_DIGEST_LEN..]].concat()),
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_strip_parent_digest() {
+        let parent_data = [1, 2, 3, 4];
+        let delta_chunk = [&[0, 0, 0, 9][..], &header(&parent_data), &[0, 0, 0, 1]].concat();
+
+        assert_eq!(
+            strip(delta_chunk.as_slice(), &parent_data)
+                .unwrap()
+                .as_ref(),
+            [0, 0, 0, 9, 0, 0, 0, 1]
+        );
+        assert!(strip(delta_chunk.as_slice(), &[1, 2, 3]).is_none());
+        assert!(strip(&delta_chunk[..10], &parent_data).is_none());
+        assert_eq!(
+            strip(&[0, 0, 0, 9, 0, 0, 0, 1], &parent_data)
+                .unwrap()
+                .as_ref(),
+            [0, 0, 0, 9, 0, 0, 0, 1]
+        );
+    }
+}
@@ -0,0 +1,92 @@
+use crate::content_hash::CONTENT_HASH_LEN;
+use crate::{ChunkType, SBCHash};
+use std::borrow::Cow;
+
+/// Word following the parent key in deltas whose parent is not the first simple
+/// chunk with its hash. Like the zstd marker it is not a valid Levenshtein action.
+const PARENT_NUMBER_MARKER: [u8; 4] = [0xff, 0xff, 0xff, 0xfd];
+/// Word following the parent key in deltas whose parent is addressed by content hash.
+const PARENT_CONTENT_MARKER: [u8; 4] = [0xff, 0xff, 0xff, 0xfc];
+
+/// Reference to the parent chunk which starts every delta: the parent key,
+/// followed by a marker and the parent number, when the number is not 0, or
+/// the content hash of the parent.
+pub(crate) fn header(parent_hash: &SBCHash) -> Vec<u8> {
+    let mut header = parent_hash.key.to_be_bytes().to_vec();
+    match parent_hash.chunk_type {
+        ChunkType::Simple(number @ 1..) => {
+            header.extend_from_slice(&PARENT_NUMBER_MARKER);
+            header.extend_from_slice(&(number as u32).to_be_bytes());
+        }
+        ChunkType::Content(content_hash) => {
+            header.extend_from_slice(&PARENT_CONTENT_MARKER);
+            header.extend_from_slice(&content_hash);
+        }
+        _ => {}
+    }
+    header
+}
+
+/// Returns the parent of `delta_chunk` and the delta with only the parent key
+/// left of its reference, as deltas with the first simple chunk as a parent are stored.
+pub(crate) fn split(delta_chunk: &[u8]) -> Option<(SBCHash, Cow<'_, [u8]>)> {
+    let key = u32::from_be_bytes(delta_chunk.get(..4)?.try_into().unwrap());
+    let (chunk_type, reference_len) = match delta_chunk.get(4..8) {
+        Some(marker) if marker == PARENT_NUMBER_MARKER => {
+            let number = u32::from_be_bytes(delta_chunk.get(8..12)?.try_into().unwrap());
+            (ChunkType::Simple(u16::try_from(number).ok()?), 4)
+        }
+        Some(marker) if marker == PARENT_CONTENT_MARKER => {
+            let content_hash = delta_chunk.get(8..8 + CONTENT_HASH_LEN)?;
+            (
+                ChunkType::Content(content_hash.try_into().unwrap()),
+                CONTENT_HASH_LEN,
+            )
+        }
+        _ => {
+            let parent_hash = SBCHash {
+                key,
+                chunk_type: ChunkType::Simple(0),
+            };
+            return Some((parent_hash, Cow::Borrowed(delta_chunk)));
+        }
+    };
+    let parent_hash = SBCHash { key, chunk_type };
+    let delta_chunk = [&delta_chunk[..4], &delta_chunk[8 + reference_len..]].concat();
+    Some((parent_hash, Cow::Owned(delta_chunk)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_split_parent_reference() {
+        let first_parent = SBCHash {
+            key: 9,
+            chunk_type: ChunkType::Simple(0),
+        };
+        let second_parent = SBCHash {
+            key: 9,
+            chunk_type: ChunkType::Simple(2),
+        };
+        assert_eq!(header(&first_parent), [0, 0, 0, 9]);
+
+        let delta_chunk = [&header(&second_parent)[..], &[0, 0, 0, 1]].concat();
+        let (parent_hash, delta) = split(delta_chunk.as_slice()).unwrap();
+        assert_eq!(parent_hash, second_parent);
+        assert_eq!(delta.as_ref(), [0, 0, 0, 9, 0, 0, 0, 1]);
+
+        let (parent_hash, delta) = split(&[0, 0, 0, 9, 0, 0, 0, 1]).unwrap();
+        assert_eq!(parent_hash, first_parent);
+        assert_eq!(delta.as_ref(), [0, 0, 0, 9, 0, 0, 0, 1]);
+        assert!(split(&delta_chunk[..10]).is_none());
+
+        let content_parent = crate::content_hash::content_key([7; CONTENT_HASH_LEN]);
+        let delta_chunk = [&header(&content_parent)[..], &[0, 0, 0, 1]].concat();
+        let (parent_hash, delta) = split(delta_chunk.as_slice()).unwrap();
+        assert_eq!(parent_hash, content_parent);
+        assert_eq!(delta.as_ref(), [7, 7, 7, 7, 0, 0, 0, 1]);
+        assert!(split(&[0, 0]).is_none());
+    }
+}
@@ -0,0 +1,679 @@
+use crate::clusterer::next_chunk_number;
+#[cfg(feature = "encryption")]
+use crate::encryption::{ChunkCipher, KeyProvider};
+#[cfg(feature = "mmap")]
+use crate::mmap_storage::MmapStorage;
+#[cfg(feature = "zstd")]
+use crate::SolidCluster;
+use crate::{
+    hash_functions, parent_ref, ChunkType, LengthAwareHasher, Preprocessing, Result, SBCHash,
+    SBCMap, SbcError,
+};
+use std::borrow::Cow;
+use std::collections::HashMap;
+#[cfg(feature = "zstd")]
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+const MAGIC: [u8; 4] = *b"SBCM";
+#[cfg(feature = "encryption")]
+const ENCRYPTED_MAGIC: [u8; 4] = *b"SBCE";
+/// Magic of the index written next to the file of simple chunks by [`SBCMap::flush`].
+#[cfg(feature = "mmap")]
+const INDEX_MAGIC: [u8; 4] = *b"SBCI";
+/// Version of the saved map format, written after the magic. Files of the
+/// first format have no version: the chunk count following their magic starts
+/// with a zero byte, read as version 0. Version 2 adds the solid clusters
+/// after the chunks. Readers refuse newer versions.
+const FORMAT_VERSION: u8 = 2;
+
+/// Where and how often a scrub saves the target map.
+#[derive(Clone, Debug)]
+pub(crate) struct Checkpoint {
+    pub path: PathBuf,
+    pub interval_bytes: usize,
+}
+
+impl SBCMap {
+    /// Writes all chunks of the map, together with their preprocessing, to `writer`.
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> Result<()> {
+        self.write_sealed(writer, MAGIC, |_, data| Cow::Borrowed(data))
+    }
+
+    /// Same as [`SBCMap::write_to`], encrypting the stored chunks with
+    /// XChaCha20-Poly1305. Keys and preprocessing of chunks are not encrypted.
+    ///
+    /// Maps created with [`SBCMap::with_mmap_storage`] are refused: their simple
+    /// chunks already sit unencrypted in the storage file, so an encrypted copy
+    /// would not keep them secret.
+    #[cfg(feature = "encryption")]
+    pub fn write_encrypted_to<W: Write>(
+        &self,
+        writer: &mut W,
+        keys: &impl KeyProvider,
+    ) -> Result<()> {
+        #[cfg(feature = "mmap")]
+        if self.simple_storage.is_some() {
+            return Err(SbcError::Config(
+                "maps with memory-mapped storage keep chunks unencrypted".to_string(),
+            ));
+        }
+        let cipher = ChunkCipher::new(keys);
+        self.write_sealed(writer, ENCRYPTED_MAGIC, |header, data| {
+            Cow::Owned(cipher.seal(header, data))
+        })
+    }
+
+    /// Writes the map, passing every stored chunk through `seal` together with
+    /// the header identifying it.
+    fn write_sealed<W: Write>(
+        &self,
+        writer: &mut W,
+        magic: [u8; 4],
+        seal: impl for<'a> Fn(&[u8], &'a [u8]) -> Cow<'a, [u8]>,
+    ) -> Result<()> {
+        self.write_entries(writer, magic, self.entries(), seal)
+    }
+
+    fn write_entries<W: Write>(
+        &self,
+        writer: &mut W,
+        magic: [u8; 4],
+        entries: Vec<(SBCHash, &[u8])>,
+        seal: impl for<'a> Fn(&[u8], &'a [u8]) -> Cow<'a, [u8]>,
+    ) -> Result<()> {
+        writer.write_all(&magic)?;
+        writer.write_all(&[FORMAT_VERSION])?;
+        writer.write_all(&(entries.len() as u64).to_be_bytes())?;
+        for (sbc_hash, data) in entries {
+            let header = self.chunk_header(&sbc_hash);
+            let data = seal(&header, data);
+            writer.write_all(&header)?;
+            writer.write_all(&(data.len() as u64).to_be_bytes())?;
+            writer.write_all(&data)?;
+        }
+        #[cfg(feature = "zstd")]
+        return self.write_solid_clusters(writer, &seal);
+        #[cfg(not(feature = "zstd"))]
+        {
+            writer.write_all(&0u64.to_be_bytes())?;
+            Ok(())
+        }
+    }
+
+    /// Writes the clusters of [`SBCMap::store_solid`] with all their frames,
+    /// each frame flagged with whether its chunk is still stored in the cluster.
+    #[cfg(feature = "zstd")]
+    fn write_solid_clusters<W: Write>(
+        &self,
+        writer: &mut W,
+        seal: &impl for<'a> Fn(&[u8], &'a [u8]) -> Cow<'a, [u8]>,
+    ) -> Result<()> {
+        let mut written = HashSet::new();
+        let clusters: Vec<&Arc<SolidCluster>> = self
+            .solid_clusters
+            .values()
+            .filter(|cluster| written.insert(Arc::as_ptr(cluster)))
+            .collect();
+        writer.write_all(&(clusters.len() as u64).to_be_bytes())?;
+        for cluster in clusters {
+            let frames: Vec<_> = cluster.frames().collect();
+            writer.write_all(&(frames.len() as u64).to_be_bytes())?;
+            for (sbc_hash, len, frame) in frames {
+                let stored = self
+                    .solid_clusters
+                    .get(sbc_hash)
+                    .is_some_and(|member_of| Arc::ptr_eq(member_of, cluster));
+                let header = self.chunk_header(sbc_hash);
+                let frame = seal(&header, frame);
+                writer.write_all(&header)?;
+                writer.write_all(&[stored as u8])?;
+                writer.write_all(&(len as u64).to_be_bytes())?;
+                writer.write_all(&(frame.len() as u64).to_be_bytes())?;
+                writer.write_all(&frame)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Key and preprocessing of a chunk as written before its data.
+    fn chunk_header(&self, sbc_hash: &SBCHash) -> Vec<u8> {
+        let (chunk_tag, number) = match sbc_hash.chunk_type {
+            ChunkType::Simple(number) => (0u8, number),
+            ChunkType::Delta(number) => (1, number),
+            ChunkType::Content(_) => (2, 0),
+        };
+        let (preprocessing_tag, width) = match self
+            .preprocessing
+            .get(sbc_hash)
+            .copied()
+            .unwrap_or_default()
+        {
+            Preprocessing::None => (0u8, 0usize),
+            Preprocessing::IntegerDelta { width } => (1, width),
+            Preprocessing::ByteTranspose { width } => (2, width),
+        };
+        let mut header = sbc_hash.key.to_be_bytes().to_vec();
+        header.push(chunk_tag);
+        header.extend_from_slice(&number.to_be_bytes());
+        if let ChunkType::Content(content_hash) = &sbc_hash.chunk_type {
+            header.extend_from_slice(content_hash);
+        }
+        header.push(preprocessing_tag);
+        header.extend_from_slice(&(width as u32).to_be_bytes());
+        header
+    }
+
+    /// Reads a map written by [`SBCMap::write_to`] into memory.
+    pub fn read_from<R: Read>(reader: &mut R) -> Result<SBCMap> {
+        SBCMap::read_sealed(reader, MAGIC, |_, data| Some(data))
+    }
+
+    /// Reads a map written by [`SBCMap::write_encrypted_to`] with the same key.
+    #[cfg(feature = "encryption")]
+    pub fn read_encrypted_from<R: Read>(reader: &mut R, keys: &impl KeyProvider) -> Result<SBCMap> {
+        let cipher = ChunkCipher::new(keys);
+        SBCMap::read_sealed(reader, ENCRYPTED_MAGIC, |header, data| {
+            cipher.open(header, &data)
+        })
+    }
+
+    /// Reads a map, passing every stored chunk through `open`, which returns
+    /// `None` when the chunk is not authentic.
+    fn read_sealed<R: Read>(
+        reader: &mut R,
+        magic: [u8; 4],
+        open: impl Fn(&[u8], Vec<u8>) -> Option<Vec<u8>>,
+    ) -> Result<SBCMap> {
+        if read_array::<4>(reader)? != magic {
+            return Err(invalid_data("not an SBC map"));
+        }
+        let (version, count) = match read_array::<1>(reader)? {
+            [0] => {
+                let mut count = [0; 8];
+                count[1..].copy_from_slice(&read_array::<7>(reader)?);
+                (0, u64::from_be_bytes(count))
+            }
+            [version @ 1..=FORMAT_VERSION] => (version, u64::from_be_bytes(read_array(reader)?)),
+            [version] => {
+                return Err(invalid_data(&format!(
+                    "map format version {version} is not supported"
+                )))
+            }
+        };
+        let mut map = SBCMap::new();
+        for _ in 0..count {
+            let (sbc_hash, preprocessing, header) = read_chunk_header(reader)?;
+            let data = read_chunk_data(reader)?;
+            let data = open(&header, data).ok_or_else(|| invalid_data("chunk is not authentic"))?;
+            map.store_value(sbc_hash.clone(), data)?;
+            map.set_preprocessing(sbc_hash, preprocessing);
+        }
+        if version >= 2 {
+            map.read_solid_clusters(reader, &open)?;
+        }
+        Ok(map)
+    }
+
+    /// Reads the clusters written by [`SBCMap::write_solid_clusters`].
+    fn read_solid_clusters<R: Read>(
+        &mut self,
+        reader: &mut R,
+        open: &impl Fn(&[u8], Vec<u8>) -> Option<Vec<u8>>,
+    ) -> Result<()> {
+        let count = u64::from_be_bytes(read_array(reader)?);
+        #[cfg(not(feature = "zstd"))]
+        if count > 0 {
+            let _ = open;
+            return Err(invalid_data("solid clusters need the zstd feature"));
+        }
+        #[cfg(feature = "zstd")]
+        for _ in 0..count {
+            let frame_count = u64::from_be_bytes(read_array(reader)?);
+            let mut frames = Vec::new();
+            let mut stored = Vec::new();
+            for _ in 0..frame_count {
+                let (sbc_hash, _, header) = read_chunk_header(reader)?;
+                let [flag] = read_array(reader)?;
+                let len = u64::from_be_bytes(read_array(reader)?) as usize;
+                let frame = read_chunk_data(reader)?;
+                let frame =
+                    open(&header, frame).ok_or_else(|| invalid_data("chunk is not authentic"))?;
+                if flag != 0 {
+                    stored.push(sbc_hash.clone());
+                }
+                frames.push((sbc_hash, len, frame));
+            }
+            let cluster = Arc::new(SolidCluster::from_frames(frames));
+            for sbc_hash in stored {
+                self.insert_solid(sbc_hash, Arc::clone(&cluster))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Saves the map to `path`. The previous file is replaced only after the
+    /// new one is completely written, so a crash leaves one of them intact.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        write_file(path.as_ref(), |writer| self.write_to(writer))
+    }
+
+    /// Loads a map saved with [`SBCMap::save`].
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<SBCMap> {
+        SBCMap::read_from(&mut BufReader::new(File::open(path)?))
+    }
+
+    /// Writes the file of simple chunks of a map created with
+    /// [`SBCMap::with_mmap_storage`] to disk, together with an index of its
+    /// chunks and the chunks kept in memory, stored at the path of the file
+    /// followed by `.index`. The map can then be reopened with
+    /// [`SBCMap::open_mmap_storage`]. Changes made after the last flush are
+    /// lost once the map is dropped. Maps kept in memory are saved with
+    /// [`SBCMap::save`] instead.
+    #[cfg(feature = "mmap")]
+    pub fn flush(&self) -> Result<()> {
+        let Some(storage) = &self.simple_storage else {
+            return Err(SbcError::Config(
+                "map has no memory-mapped storage".to_string(),
+            ));
+        };
+        storage.flush()?;
+        write_file(index_path(storage.path()).as_path(), |writer| {
+            let places: Vec<_> = storage.places().collect();
+            writer.write_all(&INDEX_MAGIC)?;
+            writer.write_all(&[FORMAT_VERSION])?;
+            writer.write_all(&(storage.len() as u64).to_be_bytes())?;
+            writer.write_all(&(places.len() as u64).to_be_bytes())?;
+            for ((key, number), (offset, len)) in places {
+                let sbc_hash = SBCHash {
+                    key,
+                    chunk_type: ChunkType::Simple(number),
+                };
+                writer.write_all(&self.chunk_header(&sbc_hash))?;
+                writer.write_all(&(offset as u64).to_be_bytes())?;
+                writer.write_all(&(len as u64).to_be_bytes())?;
+            }
+            let entries = self
+                .sbc_hashmap
+                .iter()
+                .map(|(sbc_hash, data)| (sbc_hash.clone(), data.as_ref()))
+                .collect();
+            self.write_entries(writer, MAGIC, entries, |_, data| Cow::Borrowed(data))
+        })
+    }
+
+    /// Reopens a map created with [`SBCMap::with_mmap_storage`] as it was at its
+    /// last [`SBCMap::flush`]. Like the map which created it, the reopened map
+    /// holds an exclusive lock on the file of simple chunks.
+    #[cfg(feature = "mmap")]
+    pub fn open_mmap_storage<P: AsRef<Path>>(path: P) -> Result<SBCMap> {
+        let path = path.as_ref();
+        let reader = &mut BufReader::new(File::open(index_path(path))?);
+        if read_array::<4>(reader)? != INDEX_MAGIC {
+            return Err(invalid_data("not an index of simple chunks"));
+        }
+        let [version] = read_array(reader)?;
+        if !(1..=FORMAT_VERSION).contains(&version) {
+            return Err(invalid_data(&format!(
+                "index format version {version} is not supported"
+            )));
+        }
+        let len = u64::from_be_bytes(read_array(reader)?) as usize;
+        let count = u64::from_be_bytes(read_array(reader)?);
+        let mut chunks = HashMap::new();
+        let mut preprocessing = Vec::new();
+        for _ in 0..count {
+            let (sbc_hash, chunk_preprocessing, _) = read_chunk_header(reader)?;
+            let ChunkType::Simple(number) = sbc_hash.chunk_type else {
+                return Err(invalid_data("index lists a chunk which is not simple"));
+            };
+            let offset = u64::from_be_bytes(read_array(reader)?) as usize;
+            let chunk_len = u64::from_be_bytes(read_array(reader)?) as usize;
+            chunks.insert((sbc_hash.key, number), (offset, chunk_len));
+            preprocessing.push((sbc_hash, chunk_preprocessing));
+        }
+        let mut map = SBCMap::read_from(reader)?;
+        let storage = MmapStorage::open(path, len, chunks)?;
+        map.quota.stored_bytes += storage.places().map(|(_, (_, len))| len).sum::<usize>();
+        for ((key, number), _) in storage.places() {
+            map.index_number(
+                &SBCHash {
+                    key,
+                    chunk_type: ChunkType::Simple(number),
+                },
+                true,
+            );
+        }
+        map.simple_storage = Some(storage);
+        for (sbc_hash, preprocessing) in preprocessing {
+            map.set_preprocessing(sbc_hash, preprocessing);
+        }
+        Ok(map)
+    }
+
+    /// Moves simple chunks stored away from their similarity hash, as maps
+    /// written before simple chunks were numbered have them, to numbered keys
+    /// under their hash, and updates the delta chunks referring to them.
+    /// `hasher` is the one the map was scrubbed with, see
+    /// [`crate::SBCScrubber::with_length_aware_hashing`], `None` for
+    /// [`crate::sbc_hashing`]. Returns the new keys of the moved chunks, to update
+    /// references to them held outside the map.
+    pub fn migrate_simple_keys(
+        &mut self,
+        hasher: Option<LengthAwareHasher>,
+    ) -> Result<HashMap<SBCHash, SBCHash>> {
+        let mut displaced: Vec<(SBCHash, u32, Vec<u8>)> = self
+            .entries()
+            .into_iter()
+            .filter(|(sbc_hash, _)| matches!(sbc_hash.chunk_type, ChunkType::Simple(_)))
+            .filter_map(|(sbc_hash, data)| {
+                let hash = hash_functions::similarity_hash(hasher, data);
+                (hash != sbc_hash.key).then(|| (sbc_hash, hash, data.to_vec()))
+            })
+            .collect();
+        displaced.sort_by_key(|(sbc_hash, _, _)| sbc_hash.key);
+        for (sbc_hash, _, _) in displaced.iter() {
+            self.remove_value(sbc_hash);
+        }
+
+        let mut moved = HashMap::new();
+        for (old_hash, hash, data) in displaced {
+            let new_hash = SBCHash {
+                key: hash,
+                chunk_type: ChunkType::Simple(next_chunk_number(self, hash, ChunkType::Simple)),
+            };
+            self.store_value(new_hash.clone(), data)?;
+            if let Some(preprocessing) = Arc::make_mut(&mut self.preprocessing).remove(&old_hash) {
+                self.set_preprocessing(new_hash.clone(), preprocessing);
+            }
+            moved.insert(old_hash, new_hash);
+        }
+
+        let updated_deltas: Vec<(SBCHash, Vec<u8>)> = self
+            .entries()
+            .into_iter()
+            .filter(|(sbc_hash, _)| matches!(sbc_hash.chunk_type, ChunkType::Delta(_)))
+            .filter_map(|(sbc_hash, data)| {
+                let (parent_hash, delta) = parent_ref::split(data)?;
+                let new_parent_hash = moved.get(&parent_hash)?;
+                let delta = [&parent_ref::header(new_parent_hash)[..], &delta[4..]].concat();
+                Some((sbc_hash, delta))
+            })
+            .collect();
+        for (sbc_hash, delta) in updated_deltas {
+            self.store_value(sbc_hash, delta)?;
+        }
+        Ok(moved)
+    }
+
+    fn entries(&self) -> Vec<(SBCHash, &[u8])> {
+        self.keys()
+            .into_iter()
+            .filter_map(|sbc_hash| {
+                let data = self.stored_value(&sbc_hash)?;
+                Some((sbc_hash, data))
+            })
+            .collect()
+    }
+}
+
+/// Writes the file at `path` through a temporary file, which replaces it only
+/// once it is completely written.
+fn write_file(path: &Path, write: impl FnOnce(&mut BufWriter<File>) -> Result<()>) -> Result<()> {
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    let mut writer = BufWriter::new(File::create(&tmp_path)?);
+    write(&mut writer)?;
+    writer.into_inner().map_err(io::Error::from)?.sync_all()?;
+    fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+#[cfg(feature = "mmap")]
+fn index_path(path: &Path) -> PathBuf {
+    let mut index_path = path.as_os_str().to_owned();
+    index_path.push(".index");
+    PathBuf::from(index_path)
+}
+
+/// Reads the header written by [`SBCMap::chunk_header`], returned as well.
+fn read_chunk_header(reader: &mut impl Read) -> Result<(SBCHash, Preprocessing, Vec<u8>)> {
+    let mut header = read_array::<7>(reader)?.to_vec();
+    let key = u32::from_be_bytes(header[..4].try_into().unwrap());
+    let chunk_tag = header[4];
+    let number = u16::from_be_bytes(header[5..7].try_into().unwrap());
+    let chunk_type = match chunk_tag {
+        0 => ChunkType::Simple(number),
+        1 => ChunkType::Delta(number),
+        2 => {
+            let content_hash = read_array(reader)?;
+            header.extend_from_slice(&content_hash);
+            ChunkType::Content(content_hash)
+        }
+        _ => return Err(invalid_data("unknown chunk type")),
+    };
+    let [preprocessing_tag] = read_array(reader)?;
+    let width = read_array::<4>(reader)?;
+    header.push(preprocessing_tag);
+    header.extend_from_slice(&width);
+    let width = u32::from_be_bytes(width) as usize;
+    let preprocessing = match preprocessing_tag {
+        0 => Preprocessing::None,
+        1 => Preprocessing::IntegerDelta { width },
+        2 => Preprocessing::ByteTranspose { width },
+        _ => return Err(invalid_data("unknown preprocessing")),
+    };
+    Ok((SBCHash { key, chunk_type }, preprocessing, header))
+}
+
+/// Reads data written after its length.
+fn read_chunk_data(reader: &mut impl Read) -> Result<Vec<u8>> {
+    let len = u64::from_be_bytes(read_array(reader)?);
+    let mut data = Vec::new();
+    reader.take(len).read_to_end(&mut data)?;
+    if data.len() as u64 != len {
+        return Err(invalid_data("truncated chunk"));
+    }
+    Ok(data)
+}
+
+fn read_array<const N: usize>(reader: &mut impl Read) -> Result<[u8; N]> {
+    let mut buf = [0u8; N];
+    reader.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+fn invalid_data(reason: &str) -> SbcError {
+    SbcError::Storage(io::Error::new(io::ErrorKind::InvalidData, reason))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{compress_chunks, restore, sbc_hashing, SBCScrubber};
+
+    #[test]
+    fn test_write_and_read_map() {
+        let data: Vec<u8> = (0..8192).map(|_| rand::random::<u8>()).collect();
+        let mut similar_data = data.clone();
+        similar_data[100] ^= 1;
+        let chunks = vec![data, similar_data];
+        let mut scrubber =
+            SBCScrubber::new().with_preprocessing(Preprocessing::ByteTranspose { width: 2 });
+        let (map, manifest) = compress_chunks(chunks.clone(), &mut scrubber).unwrap();
+
+        let mut bytes = Vec::new();
+        map.write_to(&mut bytes).unwrap();
+        let read_map = SBCMap::read_from(&mut bytes.as_slice()).unwrap();
+
+        let restored: Vec<Vec<u8>> = restore(&manifest, &read_map).map(Result::unwrap).collect();
+        assert_eq!(restored, chunks);
+        assert!(SBCMap::read_from(&mut &bytes[..bytes.len() - 1]).is_err());
+
+        let mut scrubber = SBCScrubber::new().with_content_addressing(crate::blake2b_content_hash);
+        let (map, manifest) = compress_chunks(chunks.clone(), &mut scrubber).unwrap();
+        let mut bytes = Vec::new();
+        map.write_to(&mut bytes).unwrap();
+        let read_map = SBCMap::read_from(&mut bytes.as_slice()).unwrap();
+        let restored: Vec<Vec<u8>> = restore(&manifest, &read_map).map(Result::unwrap).collect();
+        assert_eq!(restored, chunks);
+    }
+
+    #[test]
+    fn test_read_format_versions() {
+        let data: Vec<u8> = (0..8192).map(|_| rand::random::<u8>()).collect();
+        let (map, manifest) = compress_chunks(vec![data.clone()], &mut SBCScrubber::new()).unwrap();
+        let mut bytes = Vec::new();
+        map.write_to(&mut bytes).unwrap();
+        assert_eq!(bytes[4], FORMAT_VERSION);
+
+        let unversioned_bytes = [&bytes[..4], &bytes[5..]].concat();
+        let read_map = SBCMap::read_from(&mut unversioned_bytes.as_slice()).unwrap();
+        let restored: Vec<Vec<u8>> = restore(&manifest, &read_map).map(Result::unwrap).collect();
+        assert_eq!(restored, vec![data.clone()]);
+
+        let mut first_version_bytes = bytes[..bytes.len() - 8].to_vec();
+        first_version_bytes[4] = 1;
+        let read_map = SBCMap::read_from(&mut first_version_bytes.as_slice()).unwrap();
+        let restored: Vec<Vec<u8>> = restore(&manifest, &read_map).map(Result::unwrap).collect();
+        assert_eq!(restored, vec![data]);
+
+        bytes[4] = FORMAT_VERSION + 1;
+        assert!(SBCMap::read_from(&mut bytes.as_slice()).is_err());
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn test_write_and_read_encrypted_map() {
+        let data: Vec<u8> = (0..8192).map(|_| rand::random::<u8>()).collect();
+        let (map, manifest) = compress_chunks(vec![data.clone()], &mut SBCScrubber::new()).unwrap();
+        let key = [7u8; 32];
+
+        let mut bytes = Vec::new();
+        map.write_encrypted_to(&mut bytes, &key).unwrap();
+        assert!(!bytes.windows(64).any(|window| window == &data[..64]));
+        let read_map = SBCMap::read_encrypted_from(&mut bytes.as_slice(), &key).unwrap();
+        let restored: Vec<Vec<u8>> = restore(&manifest, &read_map).map(Result::unwrap).collect();
+        assert_eq!(restored, vec![data]);
+
+        assert!(SBCMap::read_encrypted_from(&mut bytes.as_slice(), &[8u8; 32]).is_err());
+        assert!(SBCMap::read_from(&mut bytes.as_slice()).is_err());
+        let last = bytes.len() - 1;
+        bytes[last] ^= 1;
+        assert!(SBCMap::read_encrypted_from(&mut bytes.as_slice(), &key).is_err());
+
+        #[cfg(feature = "mmap")]
+        {
+            let path = std::env::temp_dir().join(format!("sbc_encrypted_{}", std::process::id()));
+            let mmap_map = SBCMap::with_mmap_storage(path.as_path()).unwrap();
+            assert!(mmap_map.write_encrypted_to(&mut Vec::new(), &key).is_err());
+            fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_scrub_saves_checkpoints() {
+        let path = std::env::temp_dir().join(format!("sbc_checkpoint_{}", std::process::id()));
+        let data: Vec<u8> = (0..8192).map(|_| rand::random::<u8>()).collect();
+        let chunks: Vec<Vec<u8>> = (0..4).map(|_| data.clone()).collect();
+        let mut scrubber = SBCScrubber::new().with_checkpoint(path.as_path(), 1);
+        let (_, manifest) = compress_chunks(chunks.clone(), &mut scrubber).unwrap();
+
+        let checkpoint = SBCMap::load(path.as_path()).unwrap();
+        let restored: Vec<Vec<u8>> = restore(&manifest, &checkpoint)
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(restored, chunks);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_migrate_displaced_simple_chunks() {
+        let data: Vec<u8> = (0..4096).map(|_| rand::random::<u8>()).collect();
+        let mut similar_data = data.clone();
+        similar_data[10] ^= 1;
+        let hash = sbc_hashing(data.as_slice());
+        let displaced_hash = SBCHash {
+            key: hash.wrapping_add(1),
+            chunk_type: ChunkType::Simple(0),
+        };
+        let delta_hash = SBCHash {
+            key: sbc_hashing(similar_data.as_slice()),
+            chunk_type: ChunkType::Delta(0),
+        };
+        let mut map = SBCMap::new();
+        map.store_value(
+            SBCHash {
+                key: hash,
+                chunk_type: ChunkType::Simple(0),
+            },
+            data.clone(),
+        )
+        .unwrap();
+        map.store_value(displaced_hash.clone(), data.clone())
+            .unwrap();
+        let delta = crate::encode_delta(&similar_data, &data, displaced_hash.key).unwrap();
+        map.store_value(delta_hash.clone(), delta).unwrap();
+
+        let moved = map.migrate_simple_keys(None).unwrap();
+        let new_hash = SBCHash {
+            key: hash,
+            chunk_type: ChunkType::Simple(1),
+        };
+        assert_eq!(
+            moved,
+            HashMap::from([(displaced_hash.clone(), new_hash.clone())])
+        );
+        assert!(map.stored_value(&displaced_hash).is_none());
+        assert_eq!(map.decode(&new_hash).unwrap(), data);
+        assert_eq!(map.parent_of(&delta_hash), Some(new_hash));
+        assert_eq!(map.decode(&delta_hash).unwrap(), similar_data);
+        assert!(map.migrate_simple_keys(None).unwrap().is_empty());
+
+        let hasher = LengthAwareHasher::default();
+        let chunks = vec![data.clone(), similar_data];
+        let mut scrubber = SBCScrubber::new().with_length_aware_hashing(hasher);
+        let (mut map, manifest) = compress_chunks(chunks.clone(), &mut scrubber).unwrap();
+        assert!(map.migrate_simple_keys(Some(hasher)).unwrap().is_empty());
+        let restored: Vec<Vec<u8>> = restore(&manifest, &map).map(Result::unwrap).collect();
+        assert_eq!(restored, chunks);
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn test_flush_and_reopen_mmap_storage() {
+        let path = std::env::temp_dir().join(format!("sbc_reopen_{}", std::process::id()));
+        // Fixed data, as a random flipped byte may move the similar chunk to
+        // another cluster.
+        let data: Vec<u8> = (0..8192u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8)
+            .collect();
+        let mut similar_data = data.clone();
+        similar_data[100] ^= 1;
+        let mut scrubber =
+            SBCScrubber::new().with_preprocessing(Preprocessing::ByteTranspose { width: 2 });
+        let mut map = SBCMap::with_mmap_storage(&path).unwrap();
+        let keys = [
+            scrubber.process_chunk(&data, &mut map).unwrap(),
+            scrubber.process_chunk(&similar_data, &mut map).unwrap(),
+        ];
+        assert!(map.parent_of(&keys[1]).is_some());
+        map.flush().unwrap();
+        assert!(SBCMap::with_mmap_storage(&path).is_err());
+        assert!(SBCMap::open_mmap_storage(&path).is_err());
+        let stored_bytes = map.stored_bytes();
+        drop(map);
+
+        let map = SBCMap::open_mmap_storage(&path).unwrap();
+        assert_eq!(map.stored_bytes(), stored_bytes);
+        assert_eq!(map.decode(&keys[0]).unwrap(), data);
+        assert_eq!(map.decode(&keys[1]).unwrap(), similar_data);
+        assert_eq!(map.find_by_hash(keys[0].hash()).first(), Some(&keys[0]));
+        drop(map);
+        fs::remove_file(index_path(&path)).unwrap();
+        fs::remove_file(path).unwrap();
+    }
+}
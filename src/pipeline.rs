@@ -0,0 +1,712 @@
+use crate::clusterer::{delta_chunk, encode_new_simple_chunk, store_delta_chunk, ChunkContainer};
+use crate::{Preprocessing, Result, SBCHash, SBCMap, SBCScrubber};
+use std::time::Instant;
+
+struct PipelineChunk {
+    data: Vec<u8>,
+    sbc_hash: Option<SBCHash>,
+}
+
+impl ChunkContainer for PipelineChunk {
+    fn chunk_data(&self) -> Option<&[u8]> {
+        match self.sbc_hash {
+            None => Some(self.data.as_slice()),
+            Some(_) => None,
+        }
+    }
+
+    fn set_target(&mut self, sbc_hash: SBCHash) {
+        self.sbc_hash = Some(sbc_hash);
+    }
+
+    fn target(&self) -> Option<&[SBCHash]> {
+        self.sbc_hash.as_ref().map(std::slice::from_ref)
+    }
+}
+
+/// Keys of compressed chunks in the order the chunks were given to [`compress_chunks`].
+/// Clones share all chunks, see [`compress_revision`] to store a changed clone.
+#[derive(Clone, Default)]
+pub struct Manifest {
+    pub(crate) keys: Vec<SBCHash>,
+}
+
+impl Manifest {
+    pub fn keys(&self) -> &[SBCHash] {
+        self.keys.as_slice()
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+}
+
+/// Compresses chunks without a chunkfs file system. Chunks left unprocessed
+/// because of the scrubber budget are stored as simple chunks.
+pub fn compress_chunks<I>(chunks: I, scrubber: &mut SBCScrubber) -> Result<(SBCMap, Manifest)>
+where
+    I: IntoIterator<Item = Vec<u8>>,
+{
+    let mut target_map = SBCMap::new();
+    let mut pipeline_chunks = pipeline_chunks(chunks);
+    scrubber.scrub_chunks(pipeline_chunks.iter_mut(), &mut target_map, Instant::now())?;
+    Ok(store_left_chunks(pipeline_chunks, target_map, scrubber))
+}
+
+fn pipeline_chunks(chunks: impl IntoIterator<Item = Vec<u8>>) -> Vec<PipelineChunk> {
+    chunks
+        .into_iter()
+        .map(|data| PipelineChunk {
+            data,
+            sbc_hash: None,
+        })
+        .collect()
+}
+
+/// Stores chunks left unprocessed by a scrub as simple chunks and returns the
+/// manifest of all chunks.
+fn store_left_chunks(
+    pipeline_chunks: Vec<PipelineChunk>,
+    mut target_map: SBCMap,
+    scrubber: &SBCScrubber,
+) -> (SBCMap, Manifest) {
+    let mut keys = Vec::with_capacity(pipeline_chunks.len());
+    for chunk in pipeline_chunks {
+        let sbc_hash = match chunk.sbc_hash {
+            Some(sbc_hash) => sbc_hash,
+            None => {
+                let hash = scrubber.similarity_hash(chunk.data.as_slice());
+                encode_new_simple_chunk(
+                    &mut target_map,
+                    chunk.data.as_slice(),
+                    hash,
+                    scrubber.content_hasher(),
+                    Preprocessing::None,
+                )
+                .1
+            }
+        };
+        keys.push(sbc_hash);
+    }
+    (target_map, Manifest { keys })
+}
+
+/// Same as [`compress_chunks`], yielding to the executor after every encoded cluster.
+#[cfg(feature = "async")]
+pub async fn compress_chunks_async<I>(
+    chunks: I,
+    scrubber: &mut SBCScrubber,
+) -> Result<(SBCMap, Manifest)>
+where
+    I: IntoIterator<Item = Vec<u8>>,
+{
+    let mut target_map = SBCMap::new();
+    let mut pipeline_chunks = pipeline_chunks(chunks);
+    scrubber
+        .scrub_chunks_async(pipeline_chunks.iter_mut(), &mut target_map, Instant::now())
+        .await?;
+    Ok(store_left_chunks(pipeline_chunks, target_map, scrubber))
+}
+
+/// Stores a new revision of the chunks of `base`, e.g. of a cloned file. Chunks
+/// equal to those of `base` at the same position are shared, changed ones are
+/// encoded against the parent of the original chunk, or the original chunk
+/// itself when it is a simple one.
+pub fn compress_revision<I>(
+    base: &Manifest,
+    chunks: I,
+    map: &mut SBCMap,
+    scrubber: &SBCScrubber,
+) -> Result<Manifest>
+where
+    I: IntoIterator<Item = Vec<u8>>,
+{
+    let settings = scrubber.encode_settings();
+    let mut keys = Vec::new();
+    for (position, data) in chunks.into_iter().enumerate() {
+        let base_key = base.keys.get(position);
+        if let Some(base_key) = base_key {
+            if map.decode(base_key)? == data {
+                keys.push(base_key.clone());
+                continue;
+            }
+        }
+        let data = settings.preprocessing.apply(&data);
+        let hash = scrubber.similarity_hash(&data);
+        let parent = base_key
+            .map(|base_key| map.parent_of(base_key).unwrap_or_else(|| base_key.clone()))
+            .filter(|parent_hash| {
+                map.preprocessing
+                    .get(parent_hash)
+                    .copied()
+                    .unwrap_or_default()
+                    == settings.preprocessing
+            })
+            .and_then(|parent_hash| {
+                let parent_data = map.stored_value(&parent_hash)?;
+                Some(delta_chunk(&data, parent_data, &parent_hash, settings))
+            });
+        let sbc_hash = match parent {
+            Some(delta_chunk) => store_delta_chunk(map, &data, hash, delta_chunk, settings).1,
+            None => {
+                encode_new_simple_chunk(
+                    map,
+                    &data,
+                    hash,
+                    settings.content_hasher,
+                    settings.preprocessing,
+                )
+                .1
+            }
+        };
+        map.ensure_stored(&sbc_hash)?;
+        map.set_preprocessing(sbc_hash.clone(), settings.preprocessing);
+        keys.push(sbc_hash);
+    }
+    Ok(Manifest { keys })
+}
+
+/// Restores the chunks of `manifest` from `map`, in the original order.
+pub fn restore<'a>(
+    manifest: &'a Manifest,
+    map: &'a SBCMap,
+) -> impl Iterator<Item = Result<Vec<u8>>> + 'a {
+    manifest
+        .keys
+        .iter()
+        .map(move |sbc_hash| map.decode(sbc_hash))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Preprocessing, ScrubBudget};
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    fn similar_chunks() -> Vec<Vec<u8>> {
+        let data: Vec<u8> = (0..8192).map(|_| rand::random::<u8>()).collect();
+        let mut chunks = vec![data.clone()];
+        for i in 1..5 {
+            let mut chunk = data.clone();
+            chunk[i * 1000] = chunk[i * 1000].wrapping_add(1);
+            chunks.push(chunk);
+        }
+        chunks.push((0..4096).map(|_| rand::random::<u8>()).collect());
+        chunks
+    }
+
+    #[test]
+    fn test_compress_and_restore() {
+        let chunks = similar_chunks();
+        let (map, manifest) = compress_chunks(chunks.clone(), &mut SBCScrubber::new()).unwrap();
+
+        assert_eq!(manifest.len(), chunks.len());
+        let restored: Vec<Vec<u8>> = restore(&manifest, &map).map(Result::unwrap).collect();
+        assert_eq!(restored, chunks);
+    }
+
+    #[test]
+    fn test_cluster_statistics() {
+        let chunks = similar_chunks();
+        let mut scrubber = SBCScrubber::new();
+        let (map, manifest) = compress_chunks(chunks.clone(), &mut scrubber).unwrap();
+
+        let clusters = scrubber.cluster_statistics();
+        assert_eq!(
+            clusters
+                .iter()
+                .map(|cluster| cluster.chunk_count)
+                .sum::<usize>(),
+            chunks.len()
+        );
+        assert_eq!(
+            clusters
+                .iter()
+                .map(|cluster| cluster.original_bytes)
+                .sum::<usize>(),
+            chunks.iter().map(Vec::len).sum::<usize>()
+        );
+        assert_eq!(
+            clusters
+                .iter()
+                .map(|cluster| cluster.stored_bytes)
+                .sum::<usize>(),
+            manifest
+                .keys()
+                .iter()
+                .filter_map(|sbc_hash| map.stored_len(sbc_hash))
+                .sum::<usize>()
+        );
+        assert_eq!(
+            clusters
+                .iter()
+                .map(|cluster| cluster.delta_chunk_count)
+                .sum::<usize>(),
+            manifest
+                .keys()
+                .iter()
+                .filter(|sbc_hash| map.parent_of(sbc_hash).is_some())
+                .count()
+        );
+        for cluster in clusters {
+            assert!(manifest.keys().contains(&cluster.parent));
+        }
+    }
+
+    #[test]
+    fn test_unprocessed_chunks_are_stored() {
+        let chunks = similar_chunks();
+        let mut scrubber = SBCScrubber::new().with_budget(ScrubBudget {
+            time: Some(Duration::ZERO),
+            bytes: None,
+        });
+        let (map, manifest) = compress_chunks(chunks.clone(), &mut scrubber).unwrap();
+
+        let restored: Vec<Vec<u8>> = restore(&manifest, &map).map(Result::unwrap).collect();
+        assert_eq!(restored, chunks);
+    }
+
+    #[test]
+    fn test_compress_and_restore_with_preprocessing() {
+        let chunks: Vec<Vec<u8>> = (0..6u32)
+            .map(|start| {
+                (start..start + 2048)
+                    .flat_map(|value| (value * 3).to_le_bytes())
+                    .collect()
+            })
+            .collect();
+        let mut scrubber =
+            SBCScrubber::new().with_preprocessing(Preprocessing::IntegerDelta { width: 4 });
+        let (map, manifest) = compress_chunks(chunks.clone(), &mut scrubber).unwrap();
+
+        let restored: Vec<Vec<u8>> = restore(&manifest, &map).map(Result::unwrap).collect();
+        assert_eq!(restored, chunks);
+    }
+
+    #[test]
+    fn test_compress_and_restore_tiny_chunks() {
+        for len in 0..64 {
+            let data: Vec<u8> = (0..len).map(|_| rand::random::<u8>()).collect();
+            let mut similar_data = data.clone();
+            if len > 0 {
+                similar_data[len / 2] ^= 1;
+            }
+            let chunks = vec![data.clone(), similar_data, data[..len / 2].to_vec(), vec![]];
+            let (map, manifest) = compress_chunks(chunks.clone(), &mut SBCScrubber::new()).unwrap();
+
+            let restored: Vec<Vec<u8>> = restore(&manifest, &map).map(Result::unwrap).collect();
+            assert_eq!(restored, chunks);
+        }
+    }
+
+    #[test]
+    fn test_parent_verification() {
+        use chunkfs::Database;
+        let chunks = similar_chunks();
+        let mut scrubber = SBCScrubber::new().with_parent_verification();
+        let (mut map, manifest) = compress_chunks(chunks.clone(), &mut scrubber).unwrap();
+        let restored: Vec<Vec<u8>> = restore(&manifest, &map).map(Result::unwrap).collect();
+        assert_eq!(restored, chunks);
+
+        let delta_hash = manifest
+            .keys()
+            .iter()
+            .find(|sbc_hash| matches!(sbc_hash.chunk_type, crate::ChunkType::Delta(_)))
+            .unwrap();
+        let parent_hash = map.parent_of(delta_hash).unwrap();
+        map.insert(parent_hash, vec![0; 8192]).unwrap();
+        assert!(matches!(
+            map.decode(delta_hash),
+            Err(crate::SbcError::Decode { .. })
+        ));
+    }
+
+    #[test]
+    fn test_content_addressing() {
+        let mut chunks = similar_chunks();
+        chunks.push(chunks[0].clone());
+        let mut scrubber = SBCScrubber::new().with_content_addressing(crate::blake2b_content_hash);
+        let (map, manifest) = compress_chunks(chunks.clone(), &mut scrubber).unwrap();
+        let restored: Vec<Vec<u8>> = restore(&manifest, &map).map(Result::unwrap).collect();
+        assert_eq!(restored, chunks);
+        assert!(manifest
+            .keys()
+            .iter()
+            .all(|sbc_hash| !matches!(sbc_hash.chunk_type, crate::ChunkType::Simple(_))));
+
+        let mut scrubber = SBCScrubber::new()
+            .with_content_addressing(crate::blake2b_content_hash)
+            .with_budget(ScrubBudget {
+                time: Some(Duration::ZERO),
+                bytes: None,
+            });
+        let (map, manifest) = compress_chunks(chunks.clone(), &mut scrubber).unwrap();
+        assert_eq!(manifest.keys()[0], manifest.keys()[chunks.len() - 1]);
+        assert_eq!(map.verify_all().checked_chunks, chunks.len() - 1);
+    }
+
+    #[test]
+    fn test_size_buckets() {
+        let mut scrubber = SBCScrubber::new();
+        compress_chunks(similar_chunks(), &mut scrubber).unwrap();
+
+        let buckets = scrubber.size_buckets();
+        assert_eq!(buckets.len(), 4);
+        assert_eq!(
+            (buckets[2].min_size, buckets[2].max_size),
+            (8192, Some(32768))
+        );
+        assert_eq!(buckets[1].chunk_count, 1);
+        assert_eq!(buckets[2].chunk_count, 5);
+        assert!(buckets[2].ratio() < 0.5);
+        assert_eq!(buckets[3].ratio(), 1.0);
+    }
+
+    #[test]
+    fn test_presets_round_trip() {
+        let chunks = similar_chunks();
+        for mut scrubber in [
+            SBCScrubber::fast(),
+            SBCScrubber::balanced(),
+            SBCScrubber::max_compression(),
+        ] {
+            let (map, manifest) = compress_chunks(chunks.clone(), &mut scrubber).unwrap();
+            let restored: Vec<Vec<u8>> = restore(&manifest, &map).map(Result::unwrap).collect();
+            assert_eq!(restored, chunks);
+        }
+    }
+
+    #[test]
+    fn test_high_entropy_chunks_are_skipped() {
+        let mut chunks = similar_chunks();
+        let text: Vec<u8> = (0..8192).map(|i| b"abcdefgh"[i % 7]).collect();
+        chunks.extend([text.clone(), text]);
+        let mut scrubber = SBCScrubber::new().with_entropy_skip(7.0);
+        let (map, manifest) = compress_chunks(chunks.clone(), &mut scrubber).unwrap();
+
+        assert_eq!(scrubber.skipped_chunk_count(), 6);
+        assert_eq!(manifest.keys()[7].chunk_type, crate::ChunkType::Delta(0));
+        let restored: Vec<Vec<u8>> = restore(&manifest, &map).map(Result::unwrap).collect();
+        assert_eq!(restored, chunks);
+    }
+
+    #[test]
+    fn test_length_aware_hashing() {
+        let data: Vec<u8> = (0..8000).map(|_| rand::random::<u8>()).collect();
+        let text: Vec<u8> = (0..8000).map(|i| b"abcdefgh"[i % 7]).collect();
+        let chunks = vec![data.clone(), data, text.clone(), text[..1000].to_vec()];
+        let mut scrubber =
+            SBCScrubber::new().with_length_aware_hashing(crate::LengthAwareHasher::default());
+        let (map, manifest) = compress_chunks(chunks.clone(), &mut scrubber).unwrap();
+
+        assert_eq!(manifest.keys()[1].chunk_type, crate::ChunkType::Delta(0));
+        assert_eq!(manifest.keys()[3].chunk_type, crate::ChunkType::Simple(0));
+        let restored: Vec<Vec<u8>> = restore(&manifest, &map).map(Result::unwrap).collect();
+        assert_eq!(restored, chunks);
+    }
+
+    #[test]
+    fn test_min_chunk_size() {
+        let data: Vec<u8> = (0..8192).map(|_| rand::random::<u8>()).collect();
+        let chunks = vec![data.clone(), data[..40].to_vec(), data, vec![7; 40]];
+        let mut scrubber = SBCScrubber::new().with_min_chunk_size(64);
+        let (map, manifest) = compress_chunks(chunks.clone(), &mut scrubber).unwrap();
+
+        assert_eq!(scrubber.skipped_chunk_count(), 2);
+        assert!(map.parent_of(&manifest.keys()[2]).is_some());
+        assert!(map.parent_of(&manifest.keys()[1]).is_none());
+        assert!(map.parent_of(&manifest.keys()[3]).is_none());
+        let restored: Vec<Vec<u8>> = restore(&manifest, &map).map(Result::unwrap).collect();
+        assert_eq!(restored, chunks);
+
+        let mut target_map = SBCMap::new();
+        for _ in 0..2 {
+            let sbc_hash = scrubber.process_chunk(&chunks[1], &mut target_map).unwrap();
+            assert!(matches!(sbc_hash.chunk_type, crate::ChunkType::Simple(_)));
+        }
+
+        let small_chunks = vec![chunks[1].clone(), chunks[1].clone()];
+        let mut scrubber = SBCScrubber::new()
+            .with_min_chunk_size(64)
+            .with_precomputed_clusters(crate::PrecomputedClusterer::from_groups([[0, 1]]));
+        let (map, manifest) = compress_chunks(small_chunks, &mut scrubber).unwrap();
+        assert_eq!(scrubber.skipped_chunk_count(), 2);
+        assert!(map.parent_of(&manifest.keys()[1]).is_none());
+    }
+
+    #[test]
+    fn test_seed_from_map() {
+        // Fixed data, as a random flipped byte may move a similar chunk to
+        // another cluster.
+        let data: Vec<u8> = (0..8192u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8)
+            .collect();
+        let mut target_map = SBCMap::new();
+        let mut old_chunks = [PipelineChunk {
+            data: data.clone(),
+            sbc_hash: None,
+        }];
+        SBCScrubber::new()
+            .scrub_chunks(old_chunks.iter_mut(), &mut target_map, Instant::now())
+            .unwrap();
+        let parent_hash = old_chunks[0].sbc_hash.clone().unwrap();
+
+        let new_chunks = || {
+            (1..3)
+                .map(|i| {
+                    let mut similar_data = data.clone();
+                    similar_data[i * 1000] ^= 1;
+                    PipelineChunk {
+                        data: similar_data,
+                        sbc_hash: None,
+                    }
+                })
+                .collect::<Vec<_>>()
+        };
+        let mut map = SBCMap::new();
+        target_map.copy_cluster(&parent_hash, &mut map).unwrap();
+        let mut chunks = new_chunks();
+        SBCScrubber::new()
+            .scrub_chunks(chunks.iter_mut(), &mut map, Instant::now())
+            .unwrap();
+        assert!(map
+            .parent_of(chunks[0].sbc_hash.as_ref().unwrap())
+            .is_none());
+
+        let mut scrubber = SBCScrubber::new();
+        scrubber.seed_from_map(&target_map);
+        let mut chunks = new_chunks();
+        scrubber
+            .scrub_chunks(chunks.iter_mut(), &mut target_map, Instant::now())
+            .unwrap();
+        for chunk in chunks {
+            let sbc_hash = chunk.sbc_hash.unwrap();
+            assert_eq!(target_map.parent_of(&sbc_hash), Some(parent_hash.clone()));
+            assert_eq!(target_map.decode(&sbc_hash).unwrap(), chunk.data);
+        }
+    }
+
+    #[test]
+    fn test_parent_hints() {
+        let old_data: Vec<Vec<u8>> = (0..2)
+            .map(|_| (0..8192).map(|_| rand::random::<u8>()).collect())
+            .collect();
+        let mut target_map = SBCMap::new();
+        let mut old_chunks = pipeline_chunks(old_data.clone());
+        SBCScrubber::new()
+            .scrub_chunks(old_chunks.iter_mut(), &mut target_map, Instant::now())
+            .unwrap();
+        let parents: Vec<SBCHash> = old_chunks
+            .into_iter()
+            .map(|chunk| chunk.sbc_hash.unwrap())
+            .collect();
+
+        let mut chunks = pipeline_chunks([1, 0, 0].map(|i| {
+            let mut similar_data = old_data[i].clone();
+            similar_data[100] ^= 1;
+            similar_data
+        }));
+        let hints = HashMap::from([(0, parents[1].clone()), (2, parents[0].clone())]);
+        SBCScrubber::new()
+            .with_parent_hints(hints)
+            .scrub_chunks(chunks.iter_mut(), &mut target_map, Instant::now())
+            .unwrap();
+        let sbc_hashes: Vec<&SBCHash> = chunks
+            .iter()
+            .map(|chunk| chunk.sbc_hash.as_ref().unwrap())
+            .collect();
+        assert_eq!(
+            target_map.parent_of(sbc_hashes[0]),
+            Some(parents[1].clone())
+        );
+        assert_eq!(target_map.parent_of(sbc_hashes[1]), None);
+        assert_eq!(
+            target_map.parent_of(sbc_hashes[2]),
+            Some(parents[0].clone())
+        );
+        for chunk in chunks {
+            assert_eq!(
+                target_map.decode(&chunk.sbc_hash.unwrap()).unwrap(),
+                chunk.data
+            );
+        }
+    }
+
+    #[test]
+    fn test_recluster_encoded_chunks() {
+        let mut chunks: Vec<PipelineChunk> = similar_chunks()
+            .into_iter()
+            .map(|data| PipelineChunk {
+                data,
+                sbc_hash: None,
+            })
+            .collect();
+        let mut target_map = SBCMap::new();
+        let mut scrubber = SBCScrubber::new();
+        scrubber
+            .scrub_chunks(chunks[..1].iter_mut(), &mut target_map, Instant::now())
+            .unwrap();
+        let parent_hash = chunks[0].sbc_hash.clone().unwrap();
+
+        let mut scrubber = SBCScrubber::new().with_target_reclustering();
+        scrubber
+            .scrub_chunks(chunks.iter_mut(), &mut target_map, Instant::now())
+            .unwrap();
+
+        assert_eq!(chunks[0].sbc_hash, Some(parent_hash));
+        for chunk in chunks {
+            let sbc_hash = chunk.sbc_hash.unwrap();
+            assert_eq!(target_map.decode(&sbc_hash).unwrap(), chunk.data);
+        }
+    }
+
+    #[test]
+    fn test_threaded_hashing_gives_same_result() {
+        let chunks: Vec<Vec<u8>> = (0..8).flat_map(|_| similar_chunks()).collect();
+        let (_, manifest) = compress_chunks(chunks.clone(), &mut SBCScrubber::new()).unwrap();
+        let mut scrubber = SBCScrubber::new().with_hashing_threads(4);
+        let (threaded_map, threaded_manifest) =
+            compress_chunks(chunks.clone(), &mut scrubber).unwrap();
+
+        let delta_count = |manifest: &Manifest| {
+            manifest
+                .keys()
+                .iter()
+                .filter(|sbc_hash| matches!(sbc_hash.chunk_type, crate::ChunkType::Delta(_)))
+                .count()
+        };
+        assert_eq!(delta_count(&threaded_manifest), delta_count(&manifest));
+        let restored: Vec<Vec<u8>> = restore(&threaded_manifest, &threaded_map)
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(restored, chunks);
+    }
+
+    #[test]
+    fn test_pipelined_scrub_gives_same_result() {
+        // Chunks of every cluster are spread over all batches.
+        let chunks: Vec<Vec<u8>> = (0..8).flat_map(|_| similar_chunks()).collect();
+        let scrubber = || SBCScrubber::new().with_max_children_per_parent(3);
+        let (_, manifest) = compress_chunks(chunks.clone(), &mut scrubber()).unwrap();
+        let mut pipelined_scrubber = scrubber()
+            .with_pipeline_batch_len(5)
+            .with_hashing_threads(2)
+            .with_encoding_threads(2);
+        let (pipelined_map, pipelined_manifest) =
+            compress_chunks(chunks.clone(), &mut pipelined_scrubber).unwrap();
+
+        let delta_count = |manifest: &Manifest| {
+            manifest
+                .keys()
+                .iter()
+                .filter(|sbc_hash| matches!(sbc_hash.chunk_type, crate::ChunkType::Delta(_)))
+                .count()
+        };
+        assert!(delta_count(&manifest) > 0);
+        assert_eq!(delta_count(&pipelined_manifest), delta_count(&manifest));
+        let restored: Vec<Vec<u8>> = restore(&pipelined_manifest, &pipelined_map)
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(restored, chunks);
+    }
+
+    #[test]
+    fn test_collision_widening() {
+        // Swapping the blocks keeps the byte and pair frequencies the hash is built of.
+        let blocks: Vec<Vec<u8>> = (0..2)
+            .map(|_| (0..4096).map(|_| rand::random::<u8>()).collect())
+            .collect();
+        let chunk = |first: &[u8], second: &[u8]| [&[0], first, &[0], second, &[0]].concat();
+        let chunks = vec![chunk(&blocks[0], &blocks[1]), chunk(&blocks[1], &blocks[0])];
+
+        let mut scrubber = SBCScrubber::new();
+        let (_, manifest) = compress_chunks(chunks.clone(), &mut scrubber).unwrap();
+        assert_eq!(scrubber.hash_collisions().rate(), 1.0);
+        assert_eq!(scrubber.key_widening(), None);
+        assert!(matches!(
+            manifest.keys()[0].chunk_type,
+            crate::ChunkType::Simple(_)
+        ));
+
+        let mut scrubber = SBCScrubber::new().with_collision_widening(0.5);
+        let (map, manifest) = compress_chunks(chunks.clone(), &mut scrubber).unwrap();
+        assert_eq!(scrubber.key_widening(), Some(scrubber.hash_collisions()));
+        assert!(matches!(
+            manifest.keys()[0].chunk_type,
+            crate::ChunkType::Content(_)
+        ));
+        let restored: Vec<Vec<u8>> = restore(&manifest, &map).map(Result::unwrap).collect();
+        assert_eq!(restored, chunks);
+    }
+
+    #[test]
+    fn test_process_chunk() {
+        let chunks = similar_chunks();
+        let mut scrubber = SBCScrubber::new().with_max_children_per_parent(2);
+        let mut map = SBCMap::new();
+        let keys: Vec<SBCHash> = chunks
+            .iter()
+            .map(|data| scrubber.process_chunk(data, &mut map).unwrap())
+            .collect();
+
+        for (sbc_hash, data) in keys.iter().zip(chunks.iter()) {
+            assert_eq!(&map.decode(sbc_hash).unwrap(), data);
+            if let Some(parent_hash) = map.parent_of(sbc_hash) {
+                assert!(keys.contains(&parent_hash));
+            }
+        }
+    }
+
+    #[test]
+    fn test_compress_revision() {
+        let chunks = similar_chunks();
+        let mut scrubber = SBCScrubber::new();
+        let (mut map, manifest) = compress_chunks(chunks.clone(), &mut scrubber).unwrap();
+        let stored_chunks = map.verify_all().checked_chunks;
+
+        let mut changed_chunks = chunks.clone();
+        changed_chunks[5][100] ^= 1;
+        changed_chunks.push(vec![1; 100]);
+        let revision =
+            compress_revision(&manifest, changed_chunks.clone(), &mut map, &scrubber).unwrap();
+
+        assert_eq!(&revision.keys()[..5], &manifest.keys()[..5]);
+        assert_eq!(
+            map.parent_of(&revision.keys()[5]),
+            Some(manifest.keys()[5].clone())
+        );
+        assert_eq!(map.verify_all().checked_chunks, stored_chunks + 2);
+        let restored: Vec<Vec<u8>> = restore(&revision, &map).map(Result::unwrap).collect();
+        assert_eq!(restored, changed_chunks);
+        let restored: Vec<Vec<u8>> = restore(&manifest, &map).map(Result::unwrap).collect();
+        assert_eq!(restored, chunks);
+    }
+
+    #[test]
+    fn test_scrub_leaves_chunks_beyond_quota() {
+        let mut chunks: Vec<PipelineChunk> = (0..3)
+            .map(|_| PipelineChunk {
+                data: (0..4096).map(|_| rand::random::<u8>()).collect(),
+                sbc_hash: None,
+            })
+            .collect();
+        let mut target_map = SBCMap::new().with_quota(8192);
+        let statistics = SBCScrubber::new()
+            .scrub_chunks(chunks.iter_mut(), &mut target_map, Instant::now())
+            .unwrap();
+
+        assert_eq!(statistics.untouched_chunk_count, 1);
+        assert_eq!(target_map.stored_bytes(), 8192);
+        for chunk in chunks {
+            match chunk.sbc_hash {
+                Some(sbc_hash) => assert_eq!(target_map.decode(&sbc_hash).unwrap(), chunk.data),
+                None => assert_eq!(chunk.data.len(), 4096),
+            }
+        }
+    }
+}
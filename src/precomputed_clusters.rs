@@ -0,0 +1,77 @@
+/// Clusters known to the caller, e.g. chunks of the same file or table
+/// partition, used by [`crate::SBCScrubber::with_precomputed_clusters`] instead
+/// of clusters of similarity hashes.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PrecomputedClusterer {
+    cluster_ids: Vec<Option<u32>>,
+}
+
+impl PrecomputedClusterer {
+    /// Takes the cluster of every chunk in the order the scrub visits them,
+    /// the order of the chunks given to [`crate::compress_chunks`]. Chunks with
+    /// `None` and chunks past the end are not clustered.
+    pub fn new(cluster_ids: impl IntoIterator<Item = Option<u32>>) -> PrecomputedClusterer {
+        PrecomputedClusterer {
+            cluster_ids: cluster_ids.into_iter().collect(),
+        }
+    }
+
+    /// Makes a cluster of every group of chunk positions.
+    pub fn from_groups<G>(groups: impl IntoIterator<Item = G>) -> PrecomputedClusterer
+    where
+        G: IntoIterator<Item = usize>,
+    {
+        let mut cluster_ids = Vec::new();
+        for (cluster_id, group) in groups.into_iter().enumerate() {
+            for position in group {
+                if cluster_ids.len() <= position {
+                    cluster_ids.resize(position + 1, None);
+                }
+                cluster_ids[position] = Some(cluster_id as u32);
+            }
+        }
+        PrecomputedClusterer { cluster_ids }
+    }
+
+    pub(crate) fn cluster_of(&self, position: usize) -> Option<u32> {
+        self.cluster_ids.get(position).copied().flatten()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{compress_chunks, restore, ChunkType, SBCScrubber};
+
+    #[test]
+    fn test_precomputed_clusters() {
+        let data: Vec<u8> = (0..8192).map(|_| rand::random::<u8>()).collect();
+        let chunks: Vec<Vec<u8>> = (0..4)
+            .map(|i| {
+                let mut similar_data = data.clone();
+                similar_data[i * 1000] ^= 1;
+                similar_data
+            })
+            .collect();
+        let clusterer = PrecomputedClusterer::from_groups([vec![3], vec![2, 0]]);
+        assert_eq!(
+            clusterer,
+            PrecomputedClusterer::new([Some(1), None, Some(1), Some(0)])
+        );
+        let mut scrubber = SBCScrubber::new().with_precomputed_clusters(clusterer);
+        let (map, manifest) = compress_chunks(chunks.clone(), &mut scrubber).unwrap();
+
+        let chunk_types: Vec<&ChunkType> =
+            manifest.keys().iter().map(|key| key.chunk_type()).collect();
+        assert!(matches!(chunk_types[0], ChunkType::Simple(_)));
+        assert!(matches!(chunk_types[1], ChunkType::Simple(_)));
+        assert!(matches!(chunk_types[2], ChunkType::Delta(_)));
+        assert!(matches!(chunk_types[3], ChunkType::Simple(_)));
+        assert_eq!(
+            map.parent_of(&manifest.keys()[2]).as_ref(),
+            Some(&manifest.keys()[0])
+        );
+        let restored: Vec<Vec<u8>> = restore(&manifest, &map).map(Result::unwrap).collect();
+        assert_eq!(restored, chunks);
+    }
+}
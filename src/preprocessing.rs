@@ -0,0 +1,162 @@
+use crate::clusterer::ChunkContainer;
+use crate::SBCHash;
+
+/// Reversible transform applied to chunk bytes before similarity hashing and
+/// delta encoding. Numeric data usually clusters much better after it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Preprocessing {
+    #[default]
+    None,
+    /// Replaces every little-endian integer of `width` (1..=8) bytes with its
+    /// difference from the previous integer.
+    IntegerDelta { width: usize },
+    /// Groups bytes of `width`-byte records by their position in the record.
+    ByteTranspose { width: usize },
+}
+
+impl Preprocessing {
+    pub fn apply(&self, data: &[u8]) -> Vec<u8> {
+        match *self {
+            Preprocessing::IntegerDelta { width } if (1..=8).contains(&width) => {
+                integer_delta(data, width, false)
+            }
+            Preprocessing::ByteTranspose { width } if width > 1 => transpose(data, width, false),
+            _ => data.to_vec(),
+        }
+    }
+
+    pub fn invert(&self, data: &[u8]) -> Vec<u8> {
+        match *self {
+            Preprocessing::IntegerDelta { width } if (1..=8).contains(&width) => {
+                integer_delta(data, width, true)
+            }
+            Preprocessing::ByteTranspose { width } if width > 1 => transpose(data, width, true),
+            _ => data.to_vec(),
+        }
+    }
+}
+
+fn integer_delta(data: &[u8], width: usize, invert: bool) -> Vec<u8> {
+    let mask = u64::MAX >> (64 - 8 * width);
+    let mut result = data.to_vec();
+    let mut previous = 0u64;
+    for record in result.chunks_exact_mut(width) {
+        let mut buf = [0u8; 8];
+        buf[..width].copy_from_slice(record);
+        let value = u64::from_le_bytes(buf);
+        let transformed = if invert {
+            value.wrapping_add(previous) & mask
+        } else {
+            value.wrapping_sub(previous) & mask
+        };
+        previous = if invert { transformed } else { value };
+        record.copy_from_slice(&transformed.to_le_bytes()[..width]);
+    }
+    result
+}
+
+fn transpose(data: &[u8], width: usize, invert: bool) -> Vec<u8> {
+    let record_count = data.len() / width;
+    let mut result = data.to_vec();
+    for record in 0..record_count {
+        for byte in 0..width {
+            let (row_major, column_major) = (record * width + byte, byte * record_count + record);
+            if invert {
+                result[row_major] = data[column_major];
+            } else {
+                result[column_major] = data[row_major];
+            }
+        }
+    }
+    result
+}
+
+/// Chunk container which exposes preprocessed data of the wrapped container.
+pub(crate) struct PreprocessedChunk<'a, C> {
+    container: &'a mut C,
+    data: Option<Vec<u8>>,
+    pub sbc_hash: Option<SBCHash>,
+}
+
+impl<'a, C: ChunkContainer> PreprocessedChunk<'a, C> {
+    pub fn new(container: &'a mut C, preprocessing: Preprocessing) -> Self {
+        let data = match preprocessing {
+            Preprocessing::None => None,
+            _ => container.chunk_data().map(|data| preprocessing.apply(data)),
+        };
+        PreprocessedChunk {
+            container,
+            data,
+            sbc_hash: None,
+        }
+    }
+
+    /// Wraps a container whose chunk was already encoded, given its decoded `data`.
+    pub fn with_data(container: &'a mut C, data: &[u8], preprocessing: Preprocessing) -> Self {
+        PreprocessedChunk {
+            container,
+            data: Some(preprocessing.apply(data)),
+            sbc_hash: None,
+        }
+    }
+}
+
+impl<C: ChunkContainer> ChunkContainer for PreprocessedChunk<'_, C> {
+    fn chunk_data(&self) -> Option<&[u8]> {
+        match &self.data {
+            Some(data) if self.sbc_hash.is_none() => Some(data.as_slice()),
+            Some(_) => None,
+            None => self.container.chunk_data(),
+        }
+    }
+
+    fn set_target(&mut self, sbc_hash: SBCHash) {
+        self.container.set_target(sbc_hash.clone());
+        self.sbc_hash = Some(sbc_hash);
+    }
+
+    fn target(&self) -> Option<&[SBCHash]> {
+        self.container.target()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn assert_round_trip(preprocessing: Preprocessing, data: &[u8]) {
+        assert_eq!(preprocessing.invert(&preprocessing.apply(data)), data);
+    }
+
+    #[test]
+    fn test_integer_delta() {
+        let data: Vec<u8> = [1000u32, 1003, 1001, 5]
+            .iter()
+            .flat_map(|value| value.to_le_bytes())
+            .chain([7, 7])
+            .collect();
+        let preprocessing = Preprocessing::IntegerDelta { width: 4 };
+        let deltas = preprocessing.apply(data.as_slice());
+
+        assert_eq!(deltas[4..8], 3u32.to_le_bytes());
+        assert_eq!(deltas[8..12], (-2i32).to_le_bytes());
+        assert_eq!(deltas[16..], [7, 7]);
+        assert_round_trip(preprocessing, data.as_slice());
+    }
+
+    #[test]
+    fn test_byte_transpose() {
+        let preprocessing = Preprocessing::ByteTranspose { width: 2 };
+        assert_eq!(
+            preprocessing.apply(&[1, 2, 3, 4, 5, 6, 7]),
+            [1, 3, 5, 2, 4, 6, 7]
+        );
+
+        let data: Vec<u8> = (0..8191).map(|_| rand::random::<u8>()).collect();
+        assert_round_trip(preprocessing, data.as_slice());
+        assert_round_trip(Preprocessing::ByteTranspose { width: 8 }, data.as_slice());
+        assert_round_trip(Preprocessing::IntegerDelta { width: 8 }, data.as_slice());
+        assert_round_trip(Preprocessing::IntegerDelta { width: 1 }, data.as_slice());
+    }
+}
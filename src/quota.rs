@@ -0,0 +1,135 @@
+use crate::{
+    levenshtein_functions, parent_digest, parent_ref, zstd_ref, ChunkType, Result, SBCHash, SBCMap,
+    SbcError,
+};
+
+type AccountingCallback = Box<dyn FnMut(usize, usize) -> Result<()> + Send + Sync>;
+
+/// Capacity limit and accounting of the chunks stored in an [`SBCMap`].
+#[derive(Default)]
+pub(crate) struct Quota {
+    max_stored_bytes: Option<usize>,
+    pub stored_bytes: usize,
+    accounting: Option<AccountingCallback>,
+}
+
+impl SBCMap {
+    /// Rejects insertions which would make the stored chunks exceed
+    /// `max_stored_bytes`. Scrubs leave the chunks that do not fit untouched.
+    pub fn with_quota(mut self, max_stored_bytes: usize) -> SBCMap {
+        self.quota.max_stored_bytes = Some(max_stored_bytes);
+        self
+    }
+
+    /// Calls `accounting` with the decoded and the stored size of every inserted
+    /// chunk. An error returned by the callback rejects the insertion.
+    pub fn with_accounting<F>(mut self, accounting: F) -> SBCMap
+    where
+        F: FnMut(usize, usize) -> Result<()> + Send + Sync + 'static,
+    {
+        self.quota.accounting = Some(Box::new(accounting));
+        self
+    }
+
+    /// Total size of the stored, possibly delta encoded, chunks.
+    pub fn stored_bytes(&self) -> usize {
+        self.quota.stored_bytes
+    }
+
+    /// Checks that `chunk` may be inserted under `sbc_hash`.
+    pub(crate) fn account(&mut self, sbc_hash: &SBCHash, chunk: &[u8]) -> Result<()> {
+        let replaced_bytes = self.stored_len(sbc_hash).unwrap_or_default();
+        if let Some(max_stored_bytes) = self.quota.max_stored_bytes {
+            if self.quota.stored_bytes - replaced_bytes + chunk.len() > max_stored_bytes {
+                return Err(SbcError::QuotaExceeded {
+                    quota: max_stored_bytes,
+                });
+            }
+        }
+        if self.quota.accounting.is_none() {
+            return Ok(());
+        }
+        let logical_bytes = match sbc_hash.chunk_type {
+            ChunkType::Delta(_) => self.delta_decoded_len(chunk).unwrap_or(chunk.len()),
+            ChunkType::Simple(_) | ChunkType::Content(_) => chunk.len(),
+        };
+        let accounting = self.quota.accounting.as_mut().unwrap();
+        accounting(logical_bytes, chunk.len())
+    }
+
+    /// Length of the chunk the stored delta `chunk` decodes to, computed from
+    /// the length of its parent and the delta without decoding it. Preprocessing
+    /// keeps the length, so it is ignored.
+    fn delta_decoded_len(&self, chunk: &[u8]) -> Option<usize> {
+        let (parent_hash, delta_chunk) = parent_ref::split(chunk)?;
+        let (_, delta_chunk) = parent_digest::split(&delta_chunk)?;
+        if zstd_ref::is_zstd_delta(&delta_chunk) {
+            return zstd_ref::decoded_len(&delta_chunk);
+        }
+        levenshtein_functions::decoded_len(self.stored_len(&parent_hash)?, &delta_chunk)
+    }
+
+    /// Fails when the chunk an encoder returned was rejected by the map.
+    pub(crate) fn ensure_stored(&self, sbc_hash: &SBCHash) -> Result<()> {
+        match self.stored_value(sbc_hash) {
+            Some(_) => Ok(()),
+            None => Err(SbcError::Encode {
+                key: sbc_hash.key,
+                reason: "the map rejected the chunk".to_string(),
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chunkfs::Database;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_quota() {
+        let simple_hash = |key| SBCHash {
+            key,
+            chunk_type: ChunkType::Simple(0),
+        };
+        let mut map = SBCMap::new().with_quota(100);
+        map.insert(simple_hash(1), vec![0; 60]).unwrap();
+        assert!(map.insert(simple_hash(2), vec![0; 60]).is_err());
+        assert!(!map.contains(&simple_hash(2)));
+        map.insert(simple_hash(1), vec![0; 90]).unwrap();
+        assert_eq!(map.stored_bytes(), 90);
+    }
+
+    #[test]
+    fn test_accounting() {
+        let data: Vec<u8> = (0..4096).map(|_| rand::random::<u8>()).collect();
+        let mut similar_data = data.clone();
+        similar_data[10] ^= 1;
+        let accounted = Arc::new(Mutex::new(Vec::new()));
+        let accounted_inserts = Arc::clone(&accounted);
+        let mut map = SBCMap::new().with_accounting(move |logical_bytes, stored_bytes| {
+            accounted_inserts
+                .lock()
+                .unwrap()
+                .push((logical_bytes, stored_bytes));
+            Ok(())
+        });
+        map.insert(SBCHash::default(), data.clone()).unwrap();
+        let delta_hash = SBCHash {
+            key: 7,
+            chunk_type: ChunkType::Delta(0),
+        };
+        let delta = crate::encode_delta(&similar_data, &data, 0).unwrap();
+        map.insert(delta_hash.clone(), delta).unwrap();
+        assert_eq!(*accounted.lock().unwrap(), [(4096, 4096), (4096, 8)]);
+
+        #[cfg(feature = "zstd")]
+        {
+            let zstd_delta = zstd_ref::encode(&similar_data[..3000], &data, 3, 4096).unwrap();
+            let zstd_delta = [&0u32.to_be_bytes()[..], &zstd_delta].concat();
+            map.insert(delta_hash, zstd_delta).unwrap();
+            assert_eq!(accounted.lock().unwrap()[2].0, 3000);
+        }
+    }
+}
@@ -0,0 +1,122 @@
+use crate::chunkfs_sbc::decode_chunk;
+use crate::preprocessing::Preprocessing;
+#[cfg(feature = "zstd")]
+use crate::SolidCluster;
+use crate::{Result, SBCHash, SBCMap};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Read-only snapshot of an [`SBCMap`], which can be shared between threads.
+/// Later changes of the map are not visible through the view.
+#[derive(Clone)]
+pub struct SBCMapView {
+    sbc_hashmap: Arc<HashMap<SBCHash, Arc<[u8]>>>,
+    preprocessing: Arc<HashMap<SBCHash, Preprocessing>>,
+    #[cfg(feature = "zstd")]
+    solid_clusters: Arc<HashMap<SBCHash, Arc<SolidCluster>>>,
+}
+
+impl SBCMapView {
+    pub fn get(&self, sbc_hash: &SBCHash) -> Result<Vec<u8>> {
+        #[cfg(feature = "zstd")]
+        if let Some(cluster) = self.solid_clusters.get(sbc_hash) {
+            return cluster.get(sbc_hash);
+        }
+        decode_chunk(
+            sbc_hash,
+            |sbc_hash| self.sbc_hashmap.get(sbc_hash).map(AsRef::as_ref),
+            &self.preprocessing,
+        )
+    }
+
+    pub fn contains(&self, sbc_hash: &SBCHash) -> bool {
+        #[cfg(feature = "zstd")]
+        if self.solid_clusters.contains_key(sbc_hash) {
+            return true;
+        }
+        self.sbc_hashmap.contains_key(sbc_hash)
+    }
+}
+
+impl SBCMap {
+    /// Returns a view of the current state of the map. Chunks are shared with
+    /// the map until it is modified; simple chunks kept in a memory-mapped
+    /// file are copied into the view.
+    pub fn read_view(&self) -> SBCMapView {
+        #[cfg(feature = "mmap")]
+        if let Some(storage) = &self.simple_storage {
+            let mut sbc_hashmap = HashMap::clone(&self.sbc_hashmap);
+            for ((key, number), data) in storage.iter() {
+                sbc_hashmap.insert(
+                    SBCHash {
+                        key,
+                        chunk_type: crate::ChunkType::Simple(number),
+                    },
+                    Arc::from(data),
+                );
+            }
+            return SBCMapView {
+                sbc_hashmap: Arc::new(sbc_hashmap),
+                preprocessing: Arc::clone(&self.preprocessing),
+                #[cfg(feature = "zstd")]
+                solid_clusters: Arc::clone(&self.solid_clusters),
+            };
+        }
+        SBCMapView {
+            sbc_hashmap: Arc::clone(&self.sbc_hashmap),
+            preprocessing: Arc::clone(&self.preprocessing),
+            #[cfg(feature = "zstd")]
+            solid_clusters: Arc::clone(&self.solid_clusters),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{compress_chunks, SBCScrubber};
+    use chunkfs::Database;
+
+    #[test]
+    fn test_concurrent_reads_from_view() {
+        let data: Vec<u8> = (0..8192).map(|_| rand::random::<u8>()).collect();
+        let mut chunks = vec![data.clone()];
+        for i in 1..4 {
+            let mut chunk = data.clone();
+            chunk[i * 1000] = chunk[i * 1000].wrapping_add(1);
+            chunks.push(chunk);
+        }
+        let (map, manifest) = compress_chunks(chunks.clone(), &mut SBCScrubber::new()).unwrap();
+        let view = map.read_view();
+
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    for (sbc_hash, chunk) in manifest.keys().iter().zip(chunks.iter()) {
+                        assert_eq!(&view.get(sbc_hash).unwrap(), chunk);
+                    }
+                });
+            }
+        });
+    }
+
+    #[test]
+    fn test_view_does_not_see_later_inserts() {
+        let mut map = SBCMap::new();
+        let first = SBCHash {
+            key: 1,
+            chunk_type: crate::ChunkType::Simple(0),
+        };
+        let second = SBCHash {
+            key: 2,
+            chunk_type: crate::ChunkType::Simple(0),
+        };
+        map.insert(first.clone(), vec![1, 2, 3]).unwrap();
+        let view = map.read_view();
+        map.insert(second.clone(), vec![4, 5, 6]).unwrap();
+
+        assert_eq!(view.get(&first).unwrap(), vec![1, 2, 3]);
+        assert!(!view.contains(&second));
+        assert!(map.contains(&second));
+    }
+}
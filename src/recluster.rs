@@ -0,0 +1,88 @@
+use crate::{compress_chunks, Result, SBCHash, SBCMap, SBCScrubber};
+use std::collections::HashMap;
+
+/// Result of [`SBCMap::recluster`].
+pub struct Reclustered {
+    /// In-memory map with the re-encoded chunks.
+    pub map: SBCMap,
+    /// New keys of the chunks of the original map.
+    pub keys: HashMap<SBCHash, SBCHash>,
+    /// Stored size of the original map.
+    pub size_before: usize,
+    /// Stored size of the new map.
+    pub size_after: usize,
+}
+
+impl Reclustered {
+    pub fn saved_bytes(&self) -> isize {
+        self.size_before as isize - self.size_after as isize
+    }
+}
+
+impl SBCMap {
+    /// Decodes all stored chunks and encodes them again with `scrubber`, which
+    /// recomputes their similarity hashes and parents. The map itself is left
+    /// untouched, so parameters can be tuned without re-ingesting the source data.
+    pub fn recluster(&self, scrubber: &mut SBCScrubber) -> Result<Reclustered> {
+        let mut old_keys = self.keys();
+        old_keys.sort_by_key(|sbc_hash| sbc_hash.key);
+        let size_before = old_keys
+            .iter()
+            .filter_map(|sbc_hash| self.stored_len(sbc_hash))
+            .sum();
+        let chunks = old_keys
+            .iter()
+            .map(|sbc_hash| self.decode(sbc_hash))
+            .collect::<Result<Vec<_>>>()?;
+
+        let (map, manifest) = compress_chunks(chunks, scrubber)?;
+        let keys: HashMap<SBCHash, SBCHash> = old_keys
+            .into_iter()
+            .zip(manifest.keys().iter().cloned())
+            .collect();
+        let size_after = map
+            .keys()
+            .iter()
+            .filter_map(|sbc_hash| map.stored_len(sbc_hash))
+            .sum();
+        Ok(Reclustered {
+            map,
+            keys,
+            size_before,
+            size_after,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ChunkType;
+    use chunkfs::Database;
+
+    #[test]
+    fn test_recluster_map_of_simple_chunks() {
+        let data: Vec<u8> = (0..8192).map(|_| rand::random::<u8>()).collect();
+        let mut map = SBCMap::new();
+        for i in 0..4u32 {
+            let mut chunk = data.clone();
+            chunk[i as usize * 1000] ^= 1;
+            let sbc_hash = SBCHash {
+                key: i,
+                chunk_type: ChunkType::Simple(0),
+            };
+            map.insert(sbc_hash, chunk).unwrap();
+        }
+
+        let reclustered = map.recluster(&mut SBCScrubber::new()).unwrap();
+        assert_eq!(reclustered.size_before, 4 * 8192);
+        assert!(reclustered.saved_bytes() >= 0);
+        assert_eq!(reclustered.keys.len(), 4);
+        for (old_key, new_key) in &reclustered.keys {
+            assert_eq!(
+                reclustered.map.decode(new_key).unwrap(),
+                map.decode(old_key).unwrap()
+            );
+        }
+    }
+}
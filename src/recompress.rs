@@ -0,0 +1,136 @@
+use crate::levenshtein_functions::{decode_delta, is_levenshtein_delta};
+use crate::{parent_digest, parent_ref, zstd_ref, Result, SBCHash, SBCMap};
+use chunkfs::Database;
+use std::collections::HashMap;
+
+/// Result of [`SBCMap::recompress_weak_clusters`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Recompressed {
+    /// Clusters whose Levenshtein deltas exceeded the ratio.
+    pub weak_clusters: usize,
+    /// Delta chunks stored again as zstd deltas.
+    pub recompressed_chunks: usize,
+    pub saved_bytes: usize,
+}
+
+impl SBCMap {
+    /// Re-encodes with zstd at `level` the Levenshtein delta chunks of the
+    /// clusters whose deltas take more than `max_ratio` of the size of their
+    /// decoded chunks, with the parent as a dictionary. A delta is replaced only
+    /// when zstd makes it smaller. Other clusters are not decoded at all.
+    pub fn recompress_weak_clusters(&mut self, max_ratio: f64, level: i32) -> Result<Recompressed> {
+        let mut clusters: HashMap<SBCHash, Vec<SBCHash>> = HashMap::new();
+        for sbc_hash in self.keys() {
+            if let Some(parent_hash) = self.parent_of(&sbc_hash) {
+                clusters.entry(parent_hash).or_default().push(sbc_hash);
+            }
+        }
+
+        let mut recompressed = Recompressed::default();
+        for (parent_hash, children) in clusters {
+            let Some(parent_data) = self.shared_value(&parent_hash) else {
+                continue;
+            };
+            let mut deltas = Vec::new();
+            let (mut stored_bytes, mut decoded_bytes) = (0, 0);
+            for child in children {
+                let Some(stored) = self.stored_value(&child) else {
+                    continue;
+                };
+                let stored_len = stored.len();
+                let Some((_, delta_chunk)) = parent_ref::split(stored) else {
+                    continue;
+                };
+                let Some((digest, delta_chunk)) = parent_digest::split(&delta_chunk) else {
+                    continue;
+                };
+                // Deltas whose digest does not match the parent are left for
+                // verification to report.
+                if !is_levenshtein_delta(&delta_chunk)
+                    || digest.is_some_and(|digest| digest != parent_digest::digest(&parent_data))
+                {
+                    continue;
+                }
+                let Ok(data) = decode_delta(&parent_data, &delta_chunk) else {
+                    continue;
+                };
+                stored_bytes += stored_len;
+                decoded_bytes += data.len();
+                deltas.push((child, stored_len, digest.is_some(), data));
+            }
+            if stored_bytes as f64 <= decoded_bytes as f64 * max_ratio {
+                continue;
+            }
+            recompressed.weak_clusters += 1;
+
+            for (child, stored_len, has_digest, data) in deltas {
+                let mut delta_chunk = parent_ref::header(&parent_hash);
+                if has_digest {
+                    delta_chunk.extend(parent_digest::header(&parent_data));
+                }
+                // The limit of `zstd_ref::encode` counts the parent key, which
+                // is already in the header.
+                let max_len = (stored_len + 4).saturating_sub(delta_chunk.len());
+                let Some(zstd_delta) = zstd_ref::encode(&data, &parent_data, level, max_len) else {
+                    continue;
+                };
+                delta_chunk.extend(zstd_delta);
+                let preprocessing = self.preprocessing.get(&child).copied();
+                recompressed.saved_bytes += stored_len - delta_chunk.len();
+                self.insert(child.clone(), delta_chunk)?;
+                if let Some(preprocessing) = preprocessing {
+                    self.set_preprocessing(child, preprocessing);
+                }
+                recompressed.recompressed_chunks += 1;
+            }
+        }
+        Ok(recompressed)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ChunkType;
+
+    #[test]
+    fn test_recompress_weak_clusters() {
+        let data: Vec<u8> = (0..4096).map(|_| rand::random::<u8>()).collect();
+        let parent_hash = SBCHash {
+            key: 1,
+            chunk_type: ChunkType::Simple(0),
+        };
+        let delta_hash = SBCHash {
+            key: 2,
+            chunk_type: ChunkType::Delta(0),
+        };
+        let mut similar_data = data.clone();
+        similar_data[1000..1500].fill(0);
+        let mut map = SBCMap::new();
+        map.insert(parent_hash.clone(), data).unwrap();
+        let delta = crate::encode_delta(&similar_data, &map.get(&parent_hash).unwrap(), 1).unwrap();
+        let delta_len = delta.len();
+        map.insert(delta_hash.clone(), delta).unwrap();
+
+        assert_eq!(
+            map.recompress_weak_clusters(0.9, 19).unwrap(),
+            Recompressed::default()
+        );
+        let recompressed = map.recompress_weak_clusters(0.1, 19).unwrap();
+        assert_eq!(recompressed.weak_clusters, 1);
+        assert_eq!(recompressed.recompressed_chunks, 1);
+        assert_eq!(
+            map.stored_len(&delta_hash),
+            Some(delta_len - recompressed.saved_bytes)
+        );
+        assert!(zstd_ref::is_zstd_delta(
+            map.stored_value(&delta_hash).unwrap()
+        ));
+        assert_eq!(map.get(&delta_hash).unwrap(), similar_data);
+
+        assert_eq!(
+            map.recompress_weak_clusters(0.1, 19).unwrap(),
+            Recompressed::default()
+        );
+    }
+}
@@ -0,0 +1,234 @@
+use crate::levenshtein_functions::{decode_delta, get_delta_action, Action};
+use crate::{parent_digest, parent_ref, zstd_ref, Result, SBCHash, SBCMap, SbcError};
+use chunkfs::Database;
+use std::collections::HashMap;
+
+/// Result of [`SBCMap::repair_parent`].
+#[derive(Debug, Default)]
+pub struct ParentRepair {
+    /// Whether the whole parent was re-derived and stored again.
+    pub parent_restored: bool,
+    /// Delta chunks of the parent which can be decoded, with their data.
+    pub saved: Vec<(SBCHash, Vec<u8>)>,
+    /// Delta chunks of the parent which still cannot be decoded.
+    pub lost: Vec<SBCHash>,
+}
+
+impl SBCMap {
+    /// Re-derives the lost chunk `parent_hash` from `known_children`, delta
+    /// chunks of it whose data is known from elsewhere, e.g. from another copy
+    /// of a file: every parent byte kept by their Levenshtein deltas is taken
+    /// from their data. A completely re-derived parent is checked against the
+    /// parent digests of the deltas and stored again. Otherwise the delta
+    /// chunks keeping only re-derived bytes are still decoded and returned.
+    /// Does nothing when the parent is stored.
+    pub fn repair_parent(
+        &mut self,
+        parent_hash: &SBCHash,
+        known_children: &[(SBCHash, &[u8])],
+    ) -> Result<ParentRepair> {
+        let mut repair = ParentRepair::default();
+        if self.contains(parent_hash) {
+            return Ok(repair);
+        }
+        let children = self.children_of(parent_hash);
+
+        let mut digests = Vec::new();
+        let mut deltas = HashMap::new();
+        for child in &children {
+            let Some((_, delta_chunk)) = self.stored_value(child).and_then(parent_ref::split)
+            else {
+                continue;
+            };
+            let Some((digest, delta_chunk)) = parent_digest::split(&delta_chunk) else {
+                continue;
+            };
+            digests.extend(digest);
+            if let Some(actions) = delta_actions(&delta_chunk) {
+                deltas.insert(child.clone(), (delta_chunk.into_owned(), actions));
+            }
+        }
+
+        let mut parent_bytes: Option<Vec<Option<u8>>> = None;
+        for (child, data) in known_children {
+            let Some((_, actions)) = deltas.get(child) else {
+                continue;
+            };
+            let data = match self.preprocessing.get(child) {
+                Some(preprocessing) => preprocessing.apply(data),
+                None => data.to_vec(),
+            };
+            let len = count_actions(actions, Action::Del) + data.len();
+            let len = len
+                .checked_sub(count_actions(actions, Action::Add))
+                .filter(|&len| parent_bytes.as_ref().is_none_or(|bytes| bytes.len() == len))
+                .ok_or_else(|| mismatch(child))?;
+            let parent_data = parent_bytes.get_or_insert_with(|| vec![None; len]);
+            let positions = kept_positions(actions, len).ok_or_else(|| mismatch(child))?;
+            for (position, byte) in positions.into_iter().zip(data) {
+                let Some(position) = position else {
+                    continue;
+                };
+                match parent_data[position] {
+                    Some(parent_byte) if parent_byte != byte => return Err(mismatch(child)),
+                    _ => parent_data[position] = Some(byte),
+                }
+            }
+        }
+        let Some(parent_bytes) = parent_bytes else {
+            repair.lost = children;
+            return Ok(repair);
+        };
+        let parent_len = parent_bytes.len();
+
+        if let Some(parent_data) = parent_bytes.iter().copied().collect::<Option<Vec<u8>>>() {
+            if digests
+                .iter()
+                .any(|digest| *digest != parent_digest::digest(&parent_data))
+            {
+                return Err(SbcError::Decode {
+                    key: parent_hash.key,
+                    reason: "re-derived parent chunk does not match the digest stored in a delta"
+                        .to_string(),
+                });
+            }
+            self.insert(parent_hash.clone(), parent_data)?;
+            repair.parent_restored = true;
+            for child in children {
+                match self.decode(&child) {
+                    Ok(data) => repair.saved.push((child, data)),
+                    Err(_) => repair.lost.push(child),
+                }
+            }
+            return Ok(repair);
+        }
+
+        // Bytes which are not re-derived are not kept by the saved delta chunks,
+        // so any value can stand in for them.
+        let partial_parent: Vec<u8> = parent_bytes.iter().map(|byte| byte.unwrap_or(0)).collect();
+        for child in children {
+            let Some((delta_chunk, _)) = deltas.get(&child).filter(|(_, actions)| {
+                kept_positions(actions, parent_len).is_some_and(|positions| {
+                    positions
+                        .into_iter()
+                        .flatten()
+                        .all(|position| parent_bytes[position].is_some())
+                })
+            }) else {
+                repair.lost.push(child);
+                continue;
+            };
+            let Ok(data) = decode_delta(&partial_parent, delta_chunk) else {
+                repair.lost.push(child);
+                continue;
+            };
+            let data = match self.preprocessing.get(&child) {
+                Some(preprocessing) => preprocessing.invert(&data),
+                None => data,
+            };
+            repair.saved.push((child, data));
+        }
+        Ok(repair)
+    }
+}
+
+/// Actions of a Levenshtein delta, `None` for zstd deltas and invalid actions.
+fn delta_actions(delta_chunk: &[u8]) -> Option<Vec<(Action, usize, u8)>> {
+    let actions = delta_chunk.get(4..)?;
+    if zstd_ref::is_zstd_delta(delta_chunk) || actions.len() % 4 != 0 {
+        return None;
+    }
+    actions
+        .chunks_exact(4)
+        .map(|word| {
+            let code = u32::from_be_bytes(word.try_into().unwrap());
+            (code >> 30 != 3).then(|| get_delta_action(code))
+        })
+        .collect()
+}
+
+fn count_actions(actions: &[(Action, usize, u8)], kind: Action) -> usize {
+    actions
+        .iter()
+        .filter(|&&(action, _, _)| action == kind)
+        .count()
+}
+
+/// For every byte of the chunk decoded with `actions`, the position of the
+/// parent byte it keeps, if any.
+fn kept_positions(
+    actions: &[(Action, usize, u8)],
+    parent_len: usize,
+) -> Option<Vec<Option<usize>>> {
+    let mut positions: Vec<Option<usize>> = (0..parent_len).map(Some).collect();
+    for &(action, index, _) in actions {
+        match action {
+            Action::Del if index < positions.len() => {
+                positions.remove(index);
+            }
+            Action::Add if index <= positions.len() => positions.insert(index, None),
+            Action::Rep if index < positions.len() => positions[index] = None,
+            _ => return None,
+        }
+    }
+    Some(positions)
+}
+
+fn mismatch(child: &SBCHash) -> SbcError {
+    SbcError::Decode {
+        key: child.key,
+        reason: "known data does not match the delta chunk".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ChunkType;
+
+    #[test]
+    fn test_repair_parent() {
+        let data: Vec<u8> = (0..4096).map(|_| rand::random::<u8>()).collect();
+        let parent_hash = SBCHash {
+            key: 1,
+            chunk_type: ChunkType::Simple(0),
+        };
+        let mut map = SBCMap::new();
+        let mut children = Vec::new();
+        for (key, position) in [(2, 100), (3, 200)] {
+            let mut similar_data = data.clone();
+            similar_data[position] ^= 1;
+            let delta_hash = SBCHash {
+                key,
+                chunk_type: ChunkType::Delta(0),
+            };
+            let delta = crate::encode_delta(&similar_data, &data, 1).unwrap();
+            map.insert(delta_hash.clone(), delta).unwrap();
+            children.push((delta_hash, similar_data));
+        }
+
+        let repair = map
+            .repair_parent(&parent_hash, &[(children[0].0.clone(), &children[0].1)])
+            .unwrap();
+        assert!(!repair.parent_restored);
+        assert_eq!(repair.saved, vec![children[0].clone()]);
+        assert_eq!(repair.lost, vec![children[1].0.clone()]);
+
+        let mut wrong_data = children[1].1.clone();
+        wrong_data[0] ^= 1;
+        let known_children = [
+            (children[0].0.clone(), children[0].1.as_slice()),
+            (children[1].0.clone(), wrong_data.as_slice()),
+        ];
+        assert!(map.repair_parent(&parent_hash, &known_children).is_err());
+
+        let known_children = [
+            (children[0].0.clone(), children[0].1.as_slice()),
+            (children[1].0.clone(), children[1].1.as_slice()),
+        ];
+        let repair = map.repair_parent(&parent_hash, &known_children).unwrap();
+        assert!(repair.parent_restored);
+        assert_eq!(repair.saved, children);
+        assert_eq!(map.get(&parent_hash).unwrap(), data);
+    }
+}
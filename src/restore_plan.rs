@@ -0,0 +1,114 @@
+use crate::{SBCHash, SBCMap};
+use std::collections::HashMap;
+
+/// Chunks of a [`RestorePlan`] sharing a parent.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RestoreGroup {
+    /// Parent of the delta chunks of the group, or the simple chunk itself.
+    pub parent: SBCHash,
+    /// Positions of the chunks of the group among the planned keys.
+    pub positions: Vec<usize>,
+    /// Stored bytes read to restore the group, the parent counted once.
+    pub read_bytes: usize,
+}
+
+/// Order in which to restore chunks, see [`SBCMap::plan_restore`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RestorePlan {
+    pub groups: Vec<RestoreGroup>,
+}
+
+impl RestorePlan {
+    /// Stored bytes read by the whole restore, for progress reporting.
+    pub fn read_bytes(&self) -> usize {
+        self.groups.iter().map(|group| group.read_bytes).sum()
+    }
+}
+
+impl SBCMap {
+    /// Groups `keys`, e.g. those of a file, by the parent needed to decode them,
+    /// so every parent is read once, and orders the groups by parent key.
+    /// Keys which are not stored are left out.
+    pub fn plan_restore(&self, keys: &[SBCHash]) -> RestorePlan {
+        let mut groups: HashMap<SBCHash, RestoreGroup> = HashMap::new();
+        for (position, sbc_hash) in keys.iter().enumerate() {
+            let Some(stored_len) = self.stored_len(sbc_hash) else {
+                continue;
+            };
+            let parent = self.parent_of(sbc_hash);
+            let group_parent = parent.clone().unwrap_or_else(|| sbc_hash.clone());
+            let group = groups
+                .entry(group_parent.clone())
+                .or_insert_with(|| RestoreGroup {
+                    read_bytes: self.stored_len(&group_parent).unwrap_or_default(),
+                    parent: group_parent,
+                    positions: Vec::new(),
+                });
+            if parent.is_some() {
+                group.read_bytes += stored_len;
+            }
+            group.positions.push(position);
+        }
+        let mut groups: Vec<RestoreGroup> = groups.into_values().collect();
+        groups.sort_by_key(|group| (group.parent.key, group.positions[0]));
+        RestorePlan { groups }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ChunkType;
+    use chunkfs::Database;
+
+    #[test]
+    fn test_plan_restore() {
+        let data: Vec<u8> = (0..4096).map(|_| rand::random::<u8>()).collect();
+        let mut similar_data = data.clone();
+        similar_data[10] ^= 1;
+        let parent_hash = SBCHash {
+            key: 5,
+            chunk_type: ChunkType::Simple(0),
+        };
+        let other_hash = SBCHash {
+            key: 1,
+            chunk_type: ChunkType::Simple(0),
+        };
+        let delta_hash = SBCHash {
+            key: 9,
+            chunk_type: ChunkType::Delta(0),
+        };
+        let mut map = SBCMap::new();
+        map.insert(parent_hash.clone(), data.clone()).unwrap();
+        map.insert(other_hash.clone(), vec![1; 100]).unwrap();
+        let delta = crate::encode_delta(&similar_data, &data, 5).unwrap();
+        map.insert(delta_hash.clone(), delta).unwrap();
+
+        let missing_hash = SBCHash {
+            key: 3,
+            chunk_type: ChunkType::Simple(0),
+        };
+        let plan = map.plan_restore(&[
+            delta_hash,
+            other_hash.clone(),
+            parent_hash.clone(),
+            missing_hash,
+        ]);
+        assert_eq!(
+            plan.groups,
+            vec![
+                RestoreGroup {
+                    parent: other_hash,
+                    positions: vec![1],
+                    read_bytes: 100,
+                },
+                RestoreGroup {
+                    parent: parent_hash,
+                    positions: vec![0, 2],
+                    read_bytes: 4096 + 8,
+                },
+            ]
+        );
+        assert_eq!(plan.read_bytes(), 4204);
+    }
+}
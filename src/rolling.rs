@@ -0,0 +1,73 @@
+const RS_CHAR_OFFSET: u32 = 31;
+
+/// Weak rolling checksum of librsync (`rollsum`) of a window of bytes, which
+/// can slide over data one byte at a time.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Rollsum {
+    count: u32,
+    s1: u32,
+    s2: u32,
+}
+
+impl Rollsum {
+    pub fn new() -> Rollsum {
+        Rollsum::default()
+    }
+
+    /// Appends `data` to the end of the window.
+    pub fn update(&mut self, data: &[u8]) {
+        for &byte in data {
+            self.s1 = self.s1.wrapping_add(byte as u32 + RS_CHAR_OFFSET);
+            self.s2 = self.s2.wrapping_add(self.s1);
+        }
+        self.count = self.count.wrapping_add(data.len() as u32);
+    }
+
+    /// Slides the window by one byte: `out` leaves it at the start and `in_`
+    /// enters it at the end.
+    pub fn rotate(&mut self, out: u8, in_: u8) {
+        let out = out as u32 + RS_CHAR_OFFSET;
+        self.s1 = self
+            .s1
+            .wrapping_add(in_ as u32 + RS_CHAR_OFFSET)
+            .wrapping_sub(out);
+        self.s2 = self
+            .s2
+            .wrapping_add(self.s1)
+            .wrapping_sub(self.count.wrapping_mul(out));
+    }
+
+    pub fn digest(&self) -> u32 {
+        ((self.s2 & 0xffff) << 16) | (self.s1 & 0xffff)
+    }
+}
+
+/// Weak rolling checksum of librsync (`rollsum`) of `data`.
+pub fn rollsum(data: &[u8]) -> u32 {
+    let mut sum = Rollsum::new();
+    sum.update(data);
+    sum.digest()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_rollsum() {
+        assert_eq!(rollsum(b"abc"), (772 << 16) | 387);
+        assert_eq!(rollsum(&[]), 0);
+    }
+
+    #[test]
+    fn test_rotate_matches_rollsum_of_window() {
+        let data: Vec<u8> = (0..4096).map(|_| rand::random::<u8>()).collect();
+        let window_len = 700;
+        let mut sum = Rollsum::new();
+        sum.update(&data[..window_len]);
+        for start in 1..=data.len() - window_len {
+            sum.rotate(data[start - 1], data[start + window_len - 1]);
+            assert_eq!(sum.digest(), rollsum(&data[start..start + window_len]));
+        }
+    }
+}
@@ -0,0 +1,145 @@
+use crate::{entropy, Codec};
+
+/// Byte entropy above which chunks are taken for compressed or encrypted data.
+const COMPRESSED_MIN_ENTROPY: f64 = 7.5;
+/// Share of printable ASCII and whitespace bytes of text chunks.
+const TEXT_MIN_PRINTABLE: f64 = 0.95;
+const SAMPLE_LEN: usize = 4096;
+/// Level of zstd deltas of routed chunks when no zstd fallback level is set.
+pub(crate) const DEFAULT_ZSTD_LEVEL: i32 = 3;
+
+/// Family of a chunk, see [`ChunkClass::of`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ChunkClass {
+    Text,
+    Binary,
+    /// Compressed or encrypted data.
+    Compressed,
+}
+
+impl ChunkClass {
+    /// Classifies `data` by its byte entropy and, below the entropy of
+    /// compressed data, by its share of printable bytes. Long chunks are
+    /// estimated from evenly spaced bytes.
+    pub fn of(data: &[u8]) -> ChunkClass {
+        if entropy::byte_entropy(data) > COMPRESSED_MIN_ENTROPY {
+            return ChunkClass::Compressed;
+        }
+        let step = data.len().div_ceil(SAMPLE_LEN).max(1);
+        let sample_len = data.len().div_ceil(step);
+        let printable = data
+            .iter()
+            .step_by(step)
+            .filter(|byte| byte.is_ascii_graphic() || byte.is_ascii_whitespace())
+            .count();
+        if printable as f64 >= sample_len as f64 * TEXT_MIN_PRINTABLE {
+            ChunkClass::Text
+        } else {
+            ChunkClass::Binary
+        }
+    }
+}
+
+/// Encoding of the chunks of a class.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Route {
+    /// Delta encoded with the codec. Without the `zstd` feature, chunks routed
+    /// to zstd are stored as simple chunks.
+    Delta(Codec),
+    /// Left out of clustering and stored as simple chunks.
+    Skip,
+}
+
+/// Routes of every [`ChunkClass`], see [`crate::SBCScrubber::with_routing`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct RoutingTable {
+    pub text: Route,
+    pub binary: Route,
+    pub compressed: Route,
+}
+
+impl Default for RoutingTable {
+    fn default() -> Self {
+        RoutingTable {
+            text: Route::Delta(Codec::Levenshtein),
+            binary: Route::Delta(Codec::Levenshtein),
+            compressed: Route::Skip,
+        }
+    }
+}
+
+impl RoutingTable {
+    pub fn route(&self, data: &[u8]) -> Route {
+        match ChunkClass::of(data) {
+            ChunkClass::Text => self.text,
+            ChunkClass::Binary => self.binary,
+            ChunkClass::Compressed => self.compressed,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{compress_chunks, restore, SBCScrubber};
+
+    fn text_chunk() -> Vec<u8> {
+        (0..8192)
+            .map(|_| b"abcdefghijklmnopqrstuvwxyz \n"[rand::random::<u8>() as usize % 28])
+            .collect()
+    }
+
+    fn binary_chunk() -> Vec<u8> {
+        (0..2048)
+            .flat_map(|_| (rand::random::<u16>() as u32).to_le_bytes())
+            .collect()
+    }
+
+    #[test]
+    fn test_chunk_classes() {
+        assert_eq!(ChunkClass::of(&text_chunk()), ChunkClass::Text);
+        assert_eq!(ChunkClass::of(&binary_chunk()), ChunkClass::Binary);
+        let random: Vec<u8> = (0..8192).map(|_| rand::random::<u8>()).collect();
+        assert_eq!(ChunkClass::of(&random), ChunkClass::Compressed);
+        assert_eq!(ChunkClass::of(&[]), ChunkClass::Text);
+    }
+
+    #[test]
+    fn test_routing() {
+        let routing = RoutingTable {
+            binary: Route::Skip,
+            ..RoutingTable::default()
+        };
+        let (text, binary) = (text_chunk(), binary_chunk());
+        let chunks = vec![text.clone(), text, binary.clone(), binary];
+        let mut scrubber = SBCScrubber::new().with_routing(routing);
+        let (map, manifest) = compress_chunks(chunks.clone(), &mut scrubber).unwrap();
+
+        assert_eq!(scrubber.skipped_chunk_count(), 2);
+        assert!(map.parent_of(&manifest.keys()[1]).is_some());
+        assert!(map.parent_of(&manifest.keys()[3]).is_none());
+        let restored: Vec<Vec<u8>> = restore(&manifest, &map).map(Result::unwrap).collect();
+        assert_eq!(restored, chunks);
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn test_routing_to_zstd() {
+        let routing = RoutingTable {
+            binary: Route::Delta(Codec::Zstd),
+            ..RoutingTable::default()
+        };
+        let binary = binary_chunk();
+        let chunks = vec![binary.clone(), binary];
+        let mut scrubber = SBCScrubber::new().with_routing(routing);
+        let (map, manifest) = compress_chunks(chunks.clone(), &mut scrubber).unwrap();
+
+        let delta = map.stored_value(&manifest.keys()[1]).unwrap();
+        assert!(crate::zstd_ref::is_zstd_delta(delta));
+        let restored: Vec<Vec<u8>> = restore(&manifest, &map).map(Result::unwrap).collect();
+        assert_eq!(restored, chunks);
+    }
+}
@@ -0,0 +1,109 @@
+use crate::{rollsum, Result, SbcError};
+use blake2::digest::consts::U32;
+use blake2::{Blake2b, Digest};
+
+/// Magic number of librsync signatures with rollsum weak and BLAKE2 strong checksums.
+pub const RS_BLAKE2_SIG_MAGIC: u32 = 0x72730137;
+const MAX_STRONG_SUM_LEN: usize = 32;
+
+/// Checksums of one block of a chunk.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BlockChecksum {
+    pub weak: u32,
+    pub strong: Vec<u8>,
+}
+
+/// Per-block checksums of a chunk, which a remote peer can use to compute
+/// a delta against the chunk without having its data.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChunkSignature {
+    block_len: u32,
+    strong_sum_len: u32,
+    blocks: Vec<BlockChecksum>,
+}
+
+impl ChunkSignature {
+    pub fn new(data: &[u8], block_len: usize, strong_sum_len: usize) -> Result<ChunkSignature> {
+        if block_len == 0 || block_len > u32::MAX as usize {
+            return Err(SbcError::Config(
+                "block length must be in 1..=u32::MAX".to_string(),
+            ));
+        }
+        if strong_sum_len == 0 || strong_sum_len > MAX_STRONG_SUM_LEN {
+            return Err(SbcError::Config(
+                "strong checksum length must be in 1..=32".to_string(),
+            ));
+        }
+
+        let blocks = data
+            .chunks(block_len)
+            .map(|block| BlockChecksum {
+                weak: rollsum(block),
+                strong: Blake2b::<U32>::digest(block)[..strong_sum_len].to_vec(),
+            })
+            .collect();
+        Ok(ChunkSignature {
+            block_len: block_len as u32,
+            strong_sum_len: strong_sum_len as u32,
+            blocks,
+        })
+    }
+
+    pub fn block_len(&self) -> usize {
+        self.block_len as usize
+    }
+
+    pub fn blocks(&self) -> &[BlockChecksum] {
+        self.blocks.as_slice()
+    }
+
+    /// Serializes the signature in the librsync signature file format.
+    pub fn to_librsync_bytes(&self) -> Vec<u8> {
+        let mut bytes =
+            Vec::with_capacity(12 + self.blocks.len() * (4 + self.strong_sum_len as usize));
+        bytes.extend_from_slice(&RS_BLAKE2_SIG_MAGIC.to_be_bytes());
+        bytes.extend_from_slice(&self.block_len.to_be_bytes());
+        bytes.extend_from_slice(&self.strong_sum_len.to_be_bytes());
+        for block in &self.blocks {
+            bytes.extend_from_slice(&block.weak.to_be_bytes());
+            bytes.extend_from_slice(block.strong.as_slice());
+        }
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_signature_layout() {
+        let data: Vec<u8> = (0..2500).map(|_| rand::random::<u8>()).collect();
+        let signature = ChunkSignature::new(data.as_slice(), 1024, 8).unwrap();
+        let bytes = signature.to_librsync_bytes();
+
+        assert_eq!(signature.blocks().len(), 3);
+        assert_eq!(bytes.len(), 12 + 3 * (4 + 8));
+        assert_eq!(bytes[..4], RS_BLAKE2_SIG_MAGIC.to_be_bytes());
+        assert_eq!(bytes[4..8], 1024u32.to_be_bytes());
+        assert_eq!(bytes[8..12], 8u32.to_be_bytes());
+        assert_eq!(bytes[12..16], rollsum(&data[..1024]).to_be_bytes());
+        assert_eq!(bytes[36..40], rollsum(&data[2048..]).to_be_bytes());
+    }
+
+    #[test]
+    fn test_equal_blocks_have_equal_checksums() {
+        let block: Vec<u8> = (0..512).map(|_| rand::random::<u8>()).collect();
+        let data = [block.as_slice(), block.as_slice()].concat();
+        let signature = ChunkSignature::new(data.as_slice(), 512, 32).unwrap();
+
+        assert_eq!(signature.blocks()[0], signature.blocks()[1]);
+        assert_eq!(signature.blocks()[0].strong.len(), 32);
+    }
+
+    #[test]
+    fn test_invalid_parameters() {
+        assert!(ChunkSignature::new(&[1, 2, 3], 0, 8).is_err());
+        assert!(ChunkSignature::new(&[1, 2, 3], 2048, 33).is_err());
+    }
+}
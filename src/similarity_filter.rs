@@ -0,0 +1,166 @@
+use std::collections::HashSet;
+
+const DEFAULT_MAX_SIZE_DIFFERENCE: usize = 4000;
+const WINDOW_LEN: usize = 16;
+
+/// Decides whether a chunk is worth delta encoding against a parent chunk,
+/// before any delta encoding work is done.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct SimilarityFilter {
+    /// Maximum absolute difference of chunk sizes in bytes.
+    pub max_size_difference: usize,
+    /// Maximum size difference relative to the larger chunk, `None` disables the check.
+    pub max_relative_size_difference: Option<f64>,
+    /// Number of byte positions compared by the sampling check, `0` disables it.
+    pub sample_count: usize,
+    /// Minimum share of equal sampled bytes for the chunks to be considered similar.
+    pub min_sampled_similarity: f64,
+    /// Number of windows of the chunk looked up in the parent at any offset, `0` disables it.
+    pub window_sample_count: usize,
+    /// Minimum share of sampled windows found in the parent.
+    pub min_window_resemblance: f64,
+}
+
+impl Default for SimilarityFilter {
+    fn default() -> Self {
+        SimilarityFilter {
+            max_size_difference: DEFAULT_MAX_SIZE_DIFFERENCE,
+            max_relative_size_difference: None,
+            sample_count: 0,
+            min_sampled_similarity: 0.0,
+            window_sample_count: 0,
+            min_window_resemblance: 0.0,
+        }
+    }
+}
+
+impl SimilarityFilter {
+    pub(crate) fn should_delta_encode(&self, data: &[u8], parent_data: &[u8]) -> bool {
+        self.sizes_are_close(data.len(), parent_data.len())
+            && (self.sample_count == 0
+                || sampled_similarity(data, parent_data, self.sample_count)
+                    >= self.min_sampled_similarity)
+            && (self.window_sample_count == 0
+                || window_resemblance(data, parent_data, self.window_sample_count)
+                    >= self.min_window_resemblance)
+    }
+
+    pub(crate) fn sizes_are_close(&self, len: usize, parent_len: usize) -> bool {
+        let size_difference = len.abs_diff(parent_len);
+        if size_difference > self.max_size_difference {
+            return false;
+        }
+        match self.max_relative_size_difference {
+            None => true,
+            Some(max_relative_difference) => {
+                let max_len = std::cmp::max(len, parent_len);
+                max_len == 0 || size_difference as f64 / max_len as f64 <= max_relative_difference
+            }
+        }
+    }
+}
+
+fn sampled_similarity(data: &[u8], parent_data: &[u8], sample_count: usize) -> f64 {
+    let min_len = std::cmp::min(data.len(), parent_data.len());
+    if min_len == 0 {
+        return 0.0;
+    }
+    let mut eq_from_start = 0;
+    let mut eq_from_end = 0;
+    for sample in 0..sample_count {
+        let offset = sample * min_len / sample_count;
+        if data[offset] == parent_data[offset] {
+            eq_from_start += 1;
+        }
+        if data[data.len() - offset - 1] == parent_data[parent_data.len() - offset - 1] {
+            eq_from_end += 1;
+        }
+    }
+    std::cmp::max(eq_from_start, eq_from_end) as f64 / sample_count as f64
+}
+
+fn window_resemblance(data: &[u8], parent_data: &[u8], sample_count: usize) -> f64 {
+    let window_count = data.len() / WINDOW_LEN;
+    if window_count == 0 || parent_data.len() < WINDOW_LEN {
+        return 0.0;
+    }
+    let sample_count = std::cmp::min(sample_count, window_count);
+    let mut samples: HashSet<u128> = (0..sample_count)
+        .map(|sample| {
+            let offset = sample * window_count / sample_count * WINDOW_LEN;
+            window(&data[offset..])
+        })
+        .collect();
+    let distinct_count = samples.len();
+    for offset in 0..=parent_data.len() - WINDOW_LEN {
+        samples.remove(&window(&parent_data[offset..]));
+        if samples.is_empty() {
+            break;
+        }
+    }
+    (distinct_count - samples.len()) as f64 / distinct_count as f64
+}
+
+fn window(data: &[u8]) -> u128 {
+    let mut buf = [0u8; WINDOW_LEN];
+    buf.copy_from_slice(&data[..WINDOW_LEN]);
+    u128::from_le_bytes(buf)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_default_filter_rejects_only_large_size_difference() {
+        let filter = SimilarityFilter::default();
+        assert!(filter.sizes_are_close(8192, 4200));
+        assert!(!filter.sizes_are_close(8192, 4000));
+    }
+
+    #[test]
+    fn test_relative_size_difference() {
+        let filter = SimilarityFilter {
+            max_relative_size_difference: Some(0.25),
+            ..SimilarityFilter::default()
+        };
+        assert!(filter.sizes_are_close(1000, 800));
+        assert!(!filter.sizes_are_close(1000, 700));
+        assert!(filter.sizes_are_close(0, 0));
+    }
+
+    #[test]
+    fn test_sampling_detects_shifted_similar_chunks() {
+        let parent_data: Vec<u8> = (0..8192).map(|_| rand::random::<u8>()).collect();
+        let data = parent_data[15..].to_vec();
+        let noise: Vec<u8> = (0..8192).map(|_| rand::random::<u8>()).collect();
+        let filter = SimilarityFilter {
+            sample_count: 64,
+            min_sampled_similarity: 0.5,
+            ..SimilarityFilter::default()
+        };
+
+        assert!(filter.should_delta_encode(data.as_slice(), parent_data.as_slice()));
+        assert!(!filter.should_delta_encode(noise.as_slice(), parent_data.as_slice()));
+    }
+
+    #[test]
+    fn test_window_sampling() {
+        let parent_data: Vec<u8> = (0..8192).map(|_| rand::random::<u8>()).collect();
+        let mut data = parent_data[7..].to_vec();
+        data.splice(3000..3000, (0..100).map(|_| rand::random::<u8>()));
+        let noise: Vec<u8> = (0..8192).map(|_| rand::random::<u8>()).collect();
+        let filter = SimilarityFilter {
+            window_sample_count: 32,
+            min_window_resemblance: 0.5,
+            ..SimilarityFilter::default()
+        };
+
+        assert!(window_resemblance(data.as_slice(), parent_data.as_slice(), 32) > 0.8);
+        assert!(filter.should_delta_encode(data.as_slice(), parent_data.as_slice()));
+        assert!(!filter.should_delta_encode(noise.as_slice(), parent_data.as_slice()));
+        assert!(!filter.should_delta_encode(&[1, 2, 3], parent_data.as_slice()));
+    }
+}
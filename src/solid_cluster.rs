@@ -0,0 +1,286 @@
+use crate::{ChunkType, Preprocessing, Result, SBCHash, SBCMap, SbcError};
+use std::sync::Arc;
+use zstd_safe::{CCtx, DCtx};
+
+/// Entry of the seek table of a [`SolidCluster`]: where the frame of the chunk
+/// is and how long the chunk is.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct SolidEntry {
+    sbc_hash: SBCHash,
+    frame_offset: usize,
+    frame_len: usize,
+    len: usize,
+}
+
+/// Parent chunk and its delta chunks decoded and compressed with zstd, see
+/// [`SBCMap::solid_cluster`]. Every chunk is an independent frame, the delta
+/// chunks compressed with the parent as dictionary, so reading a chunk
+/// decompresses its own frame and the frame of the parent only. For many small
+/// similar chunks this is often smaller than the per-chunk deltas.
+/// [`SBCMap::store_solid`] keeps the cluster in the map instead of the deltas.
+pub struct SolidCluster {
+    frames: Vec<u8>,
+    /// Seek table of the frames, the parent first.
+    entries: Vec<SolidEntry>,
+}
+
+impl SolidCluster {
+    /// Keys of the chunks in the order they are compressed, the parent first.
+    pub fn keys(&self) -> Vec<SBCHash> {
+        self.entries
+            .iter()
+            .map(|entry| entry.sbc_hash.clone())
+            .collect()
+    }
+
+    /// Size of the zstd frames.
+    pub fn compressed_len(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Decompresses the frame of chunk `sbc_hash`, and the frame of the parent
+    /// used as its dictionary.
+    pub fn get(&self, sbc_hash: &SBCHash) -> Result<Vec<u8>> {
+        let position = self
+            .position(sbc_hash)
+            .ok_or_else(|| corrupted(sbc_hash, "chunk is not in the cluster"))?;
+        let parent_data = self.decompress(&self.entries[0], &[])?;
+        match position {
+            0 => Ok(parent_data),
+            _ => self.decompress(&self.entries[position], &parent_data),
+        }
+    }
+
+    /// Size of the frame of chunk `sbc_hash`.
+    pub(crate) fn frame_len(&self, sbc_hash: &SBCHash) -> Option<usize> {
+        Some(self.entries[self.position(sbc_hash)?].frame_len)
+    }
+
+    pub(crate) fn parent(&self) -> &SBCHash {
+        &self.entries[0].sbc_hash
+    }
+
+    /// Keys, lengths and frames of the chunks, the parent first.
+    pub(crate) fn frames(&self) -> impl Iterator<Item = (&SBCHash, usize, &[u8])> {
+        self.entries.iter().map(|entry| {
+            let frame = &self.frames[entry.frame_offset..entry.frame_offset + entry.frame_len];
+            (&entry.sbc_hash, entry.len, frame)
+        })
+    }
+
+    /// Cluster of the frames listed by [`SolidCluster::frames`].
+    pub(crate) fn from_frames(chunks: Vec<(SBCHash, usize, Vec<u8>)>) -> SolidCluster {
+        let mut frames = Vec::new();
+        let mut entries = Vec::with_capacity(chunks.len());
+        for (sbc_hash, len, frame) in chunks {
+            entries.push(SolidEntry {
+                sbc_hash,
+                frame_offset: frames.len(),
+                frame_len: frame.len(),
+                len,
+            });
+            frames.extend(frame);
+        }
+        SolidCluster { frames, entries }
+    }
+
+    fn position(&self, sbc_hash: &SBCHash) -> Option<usize> {
+        self.entries
+            .iter()
+            .position(|entry| &entry.sbc_hash == sbc_hash)
+    }
+
+    fn decompress(&self, entry: &SolidEntry, dict: &[u8]) -> Result<Vec<u8>> {
+        let frame = self
+            .frames
+            .get(entry.frame_offset..entry.frame_offset + entry.frame_len)
+            .ok_or_else(|| corrupted(&entry.sbc_hash, "frame is truncated"))?;
+        let mut data = Vec::with_capacity(entry.len);
+        DCtx::create()
+            .decompress_using_dict(&mut data, frame, dict)
+            .map_err(|_| corrupted(&entry.sbc_hash, "frame is corrupted"))?;
+        if data.len() != entry.len {
+            return Err(corrupted(&entry.sbc_hash, "frame is truncated"));
+        }
+        Ok(data)
+    }
+}
+
+impl SBCMap {
+    /// Decodes the chunk `parent_hash` and all delta chunks encoded against it
+    /// and compresses them as a [`SolidCluster`] with zstd at `level`, as an
+    /// alternative to storing them as deltas. The map itself is left untouched.
+    pub fn solid_cluster(&self, parent_hash: &SBCHash, level: i32) -> Result<SolidCluster> {
+        let mut keys = vec![parent_hash.clone()];
+        keys.extend(self.children_of(parent_hash));
+
+        let parent_data = self.decode(parent_hash)?;
+        let mut cctx = CCtx::create();
+        let mut frames = Vec::new();
+        let mut entries = Vec::with_capacity(keys.len());
+        for sbc_hash in keys {
+            let (data, dict) = if entries.is_empty() {
+                (parent_data.clone(), &[][..])
+            } else {
+                (self.decode(&sbc_hash)?, parent_data.as_slice())
+            };
+            let mut frame = Vec::with_capacity(zstd_safe::compress_bound(data.len()));
+            cctx.compress_using_dict(&mut frame, &data, dict, level)
+                .map_err(|code| SbcError::Encode {
+                    key: sbc_hash.key,
+                    reason: zstd_safe::get_error_name(code).to_string(),
+                })?;
+            entries.push(SolidEntry {
+                sbc_hash,
+                frame_offset: frames.len(),
+                frame_len: frame.len(),
+                len: data.len(),
+            });
+            frames.extend(frame);
+        }
+        Ok(SolidCluster { frames, entries })
+    }
+
+    /// Replaces the chunk `parent_hash` and all delta chunks encoded against it
+    /// with their [`SolidCluster`], compressed at `level`. The chunks keep their
+    /// keys, and reading one of them decompresses only its frame and the frame
+    /// of the parent. Writing to one of the keys takes the chunk out of the
+    /// cluster. Fails while a snapshot is outstanding, as rolling back would
+    /// need the replaced deltas.
+    pub fn store_solid(&mut self, parent_hash: &SBCHash, level: i32) -> Result<()> {
+        if self.journal.is_some() {
+            return Err(SbcError::Config(
+                "solid clusters cannot be stored while a snapshot is outstanding".to_string(),
+            ));
+        }
+        if matches!(parent_hash.chunk_type, ChunkType::Delta(_)) {
+            return Err(SbcError::Config(
+                "delta chunks are not parents of solid clusters".to_string(),
+            ));
+        }
+        let cluster = Arc::new(self.solid_cluster(parent_hash, level)?);
+        for sbc_hash in cluster.keys() {
+            self.set_preprocessing(sbc_hash.clone(), Preprocessing::None);
+            self.insert_solid(sbc_hash, Arc::clone(&cluster))?;
+        }
+        Ok(())
+    }
+
+    /// Stores the chunk `sbc_hash` of `cluster` as a member of it, replacing
+    /// the value stored under its key.
+    pub(crate) fn insert_solid(
+        &mut self,
+        sbc_hash: SBCHash,
+        cluster: Arc<SolidCluster>,
+    ) -> Result<()> {
+        if self.journal.is_some() {
+            return Err(SbcError::Config(
+                "solid clusters cannot be stored while a snapshot is outstanding".to_string(),
+            ));
+        }
+        let frame_len = cluster
+            .frame_len(&sbc_hash)
+            .ok_or_else(|| corrupted(&sbc_hash, "chunk is not in the cluster"))?;
+        self.remove_value(&sbc_hash);
+        self.quota.stored_bytes += frame_len;
+        self.index_number(&sbc_hash, true);
+        Arc::make_mut(&mut self.solid_clusters).insert(sbc_hash, cluster);
+        Ok(())
+    }
+
+    /// Takes the chunk `sbc_hash` out of its solid cluster, if it is in one.
+    pub(crate) fn forget_solid(&mut self, sbc_hash: &SBCHash) {
+        if self.solid_clusters.contains_key(sbc_hash) {
+            Arc::make_mut(&mut self.solid_clusters).remove(sbc_hash);
+        }
+    }
+}
+
+fn corrupted(sbc_hash: &SBCHash, reason: &str) -> SbcError {
+    SbcError::Decode {
+        key: sbc_hash.key,
+        reason: reason.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ChunkType;
+    use chunkfs::Database;
+
+    fn map_with_cluster() -> (SBCMap, SBCHash, Vec<Vec<u8>>) {
+        let data: Vec<u8> = (0..4096).map(|_| rand::random::<u8>()).collect();
+        let parent_hash = SBCHash {
+            key: 3,
+            chunk_type: ChunkType::Simple(0),
+        };
+        let mut map = SBCMap::new();
+        map.insert(parent_hash.clone(), data.clone()).unwrap();
+        let mut chunks = vec![data.clone()];
+        for key in 4..12 {
+            let mut similar_data = data.clone();
+            similar_data[key as usize * 100] ^= 1;
+            let delta = crate::encode_delta(&similar_data, &data, 3).unwrap();
+            let delta_hash = SBCHash {
+                key,
+                chunk_type: ChunkType::Delta(0),
+            };
+            map.insert(delta_hash, delta).unwrap();
+            chunks.push(similar_data);
+        }
+        (map, parent_hash, chunks)
+    }
+
+    #[test]
+    fn test_solid_cluster() {
+        let (map, parent_hash, chunks) = map_with_cluster();
+        let cluster = map.solid_cluster(&parent_hash, 3).unwrap();
+        let keys = cluster.keys();
+        assert_eq!(keys.len(), 9);
+        assert_eq!(keys[0], parent_hash);
+        assert!(cluster.compressed_len() < 2 * chunks[0].len());
+        for (sbc_hash, chunk) in keys.iter().zip(&chunks) {
+            assert_eq!(&cluster.get(sbc_hash).unwrap(), chunk);
+        }
+        assert!(cluster.get(&SBCHash::default()).is_err());
+    }
+
+    #[test]
+    fn test_store_solid() {
+        let (mut map, parent_hash, chunks) = map_with_cluster();
+        let keys = map.solid_cluster(&parent_hash, 3).unwrap().keys();
+        let compressed_len = map.solid_cluster(&parent_hash, 3).unwrap().compressed_len();
+        let snapshot = map.snapshot();
+        assert!(map.store_solid(&parent_hash, 3).is_err());
+        map.release(snapshot).unwrap();
+        assert!(map.store_solid(&keys[1], 3).is_err());
+
+        map.store_solid(&parent_hash, 3).unwrap();
+        assert_eq!(map.stored_bytes(), compressed_len);
+        assert_eq!(map.parent_of(&keys[1]), Some(parent_hash.clone()));
+        assert_eq!(map.cluster_of(&keys[1]).unwrap().children, keys[1..]);
+        let view = map.read_view();
+        for (sbc_hash, chunk) in keys.iter().zip(&chunks) {
+            assert!(map.stored_value(sbc_hash).is_none());
+            assert!(map.contains(sbc_hash));
+            assert_eq!(&map.get(sbc_hash).unwrap(), chunk);
+            assert_eq!(&view.get(sbc_hash).unwrap(), chunk);
+        }
+        assert!(map.verify_all().is_ok());
+
+        let replaced_data = vec![7; 100];
+        map.insert(keys[0].clone(), replaced_data.clone()).unwrap();
+        assert_eq!(map.get(&keys[0]).unwrap(), replaced_data);
+        assert_eq!(&map.get(&keys[1]).unwrap(), &chunks[1]);
+
+        let mut bytes = Vec::new();
+        map.write_to(&mut bytes).unwrap();
+        let read_map = SBCMap::read_from(&mut bytes.as_slice()).unwrap();
+        assert_eq!(read_map.stored_bytes(), map.stored_bytes());
+        assert_eq!(read_map.get(&keys[0]).unwrap(), replaced_data);
+        for (sbc_hash, chunk) in keys.iter().zip(&chunks).skip(1) {
+            assert_eq!(&read_map.get(sbc_hash).unwrap(), chunk);
+        }
+    }
+}
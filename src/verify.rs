@@ -0,0 +1,130 @@
+use crate::{parent_ref, ChunkType, SBCHash, SBCMap, SbcError};
+use chunkfs::Database;
+use std::collections::HashSet;
+
+/// Problems found by [`SBCMap::verify_all`].
+#[derive(Debug, Default)]
+pub struct VerifyReport {
+    /// Number of stored chunks that were checked.
+    pub checked_chunks: usize,
+    /// Delta chunks whose parent chunk is not stored.
+    pub missing_parents: Vec<SBCHash>,
+    /// Chunks which could not be decoded, with the reason.
+    pub undecodable: Vec<(SBCHash, SbcError)>,
+    /// Chunks which are neither referenced nor parents of referenced delta chunks.
+    /// Filled only by [`SBCMap::verify_reachable`].
+    pub orphans: Vec<SBCHash>,
+}
+
+impl VerifyReport {
+    pub fn is_ok(&self) -> bool {
+        self.missing_parents.is_empty() && self.undecodable.is_empty() && self.orphans.is_empty()
+    }
+}
+
+impl SBCMap {
+    /// Decodes every stored chunk and checks that the parents of delta chunks exist.
+    pub fn verify_all(&self) -> VerifyReport {
+        let mut report = VerifyReport::default();
+        for sbc_hash in self.keys() {
+            report.checked_chunks += 1;
+            if let Some(parent_hash) = self.parent_of(&sbc_hash) {
+                if !self.contains(&parent_hash) {
+                    report.missing_parents.push(sbc_hash.clone());
+                    continue;
+                }
+            }
+            if let Err(error) = self.decode(&sbc_hash) {
+                report.undecodable.push((sbc_hash, error));
+            }
+        }
+        report
+    }
+
+    /// Same as [`SBCMap::verify_all`], also reporting chunks unreachable from
+    /// `referenced`, e.g. the keys of all files of a file system.
+    pub fn verify_reachable<'a>(
+        &self,
+        referenced: impl IntoIterator<Item = &'a SBCHash>,
+    ) -> VerifyReport {
+        let mut reachable = HashSet::new();
+        for sbc_hash in referenced {
+            reachable.extend(self.parent_of(sbc_hash));
+            reachable.insert(sbc_hash.clone());
+        }
+        let mut report = self.verify_all();
+        report.orphans = self
+            .keys()
+            .into_iter()
+            .filter(|sbc_hash| !reachable.contains(sbc_hash))
+            .collect();
+        report
+    }
+
+    /// Parent of the delta chunk `sbc_hash`, read from its stored delta.
+    pub fn parent_of(&self, sbc_hash: &SBCHash) -> Option<SBCHash> {
+        match sbc_hash.chunk_type {
+            ChunkType::Simple(_) | ChunkType::Content(_) => None,
+            ChunkType::Delta(_) => {
+                #[cfg(feature = "zstd")]
+                if let Some(cluster) = self.solid_clusters.get(sbc_hash) {
+                    return Some(cluster.parent().clone());
+                }
+                let (parent_hash, _) = parent_ref::split(self.stored_value(sbc_hash)?)?;
+                Some(parent_hash)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chunkfs::Database;
+
+    #[test]
+    fn test_verify_all() {
+        let data: Vec<u8> = (0..4096).map(|_| rand::random::<u8>()).collect();
+        let mut similar_data = data.clone();
+        similar_data[10] ^= 1;
+        let other_data: Vec<u8> = (0..2048).map(|_| rand::random::<u8>()).collect();
+        let simple_hash = |key| SBCHash {
+            key,
+            chunk_type: ChunkType::Simple(0),
+        };
+        let delta_hash = SBCHash {
+            key: 3,
+            chunk_type: ChunkType::Delta(0),
+        };
+        let mut map = SBCMap::new();
+        map.insert(simple_hash(1), data.clone()).unwrap();
+        map.insert(simple_hash(2), other_data).unwrap();
+        map.insert(
+            delta_hash.clone(),
+            crate::encode_delta(&similar_data, &data, 1).unwrap(),
+        )
+        .unwrap();
+        assert!(map.verify_all().is_ok());
+        assert_eq!(map.verify_all().checked_chunks, 3);
+        assert_eq!(map.decode(&delta_hash).unwrap(), similar_data);
+
+        let report = map.verify_reachable([&delta_hash]);
+        assert_eq!(report.orphans, vec![simple_hash(2)]);
+
+        map.insert(delta_hash.clone(), vec![0xee, 0xee, 0xee, 0xee])
+            .unwrap();
+        let broken_hash = SBCHash {
+            key: 1,
+            chunk_type: ChunkType::Delta(0),
+        };
+        map.insert(
+            broken_hash.clone(),
+            [&1u32.to_be_bytes()[..], &[0xff; 4], &[1, 2]].concat(),
+        )
+        .unwrap();
+        let report = map.verify_all();
+        assert_eq!(report.missing_parents, vec![delta_hash]);
+        assert_eq!(report.undecodable.len(), 1);
+        assert_eq!(report.undecodable[0].0, broken_hash);
+    }
+}
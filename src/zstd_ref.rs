@@ -0,0 +1,107 @@
+#[cfg(feature = "zstd")]
+use std::cmp::min;
+
+/// Word following the parent key in zstd deltas. It is not a valid Levenshtein
+/// action, so the two delta formats cannot be confused.
+const ZSTD_DELTA_MARKER: [u8; 4] = [0xff; 4];
+#[cfg(feature = "zstd")]
+const MAX_DECODED_LEN: u64 = 1 << 30;
+
+pub(crate) fn is_zstd_delta(delta_chunk: &[u8]) -> bool {
+    delta_chunk.get(4..8) == Some(&ZSTD_DELTA_MARKER[..])
+}
+
+/// Returns the marker and the zstd frame of `data` compressed against `parent_data`,
+/// if they are, with the parent key, smaller than `max_len` bytes. Compression
+/// stops once the frame outgrows the limit.
+#[cfg(feature = "zstd")]
+pub(crate) fn encode(
+    data: &[u8],
+    parent_data: &[u8],
+    level: i32,
+    max_len: usize,
+) -> Option<Vec<u8>> {
+    let mut frame = Vec::with_capacity(min(
+        zstd_safe::compress_bound(data.len()),
+        max_len.checked_sub(8)?,
+    ));
+    zstd_safe::CCtx::create()
+        .compress_using_dict(&mut frame, data, parent_data, level)
+        .ok()?;
+    if 8 + frame.len() >= max_len {
+        return None;
+    }
+    Some([&ZSTD_DELTA_MARKER[..], frame.as_slice()].concat())
+}
+
+#[cfg(not(feature = "zstd"))]
+pub(crate) fn encode(
+    _data: &[u8],
+    _parent_data: &[u8],
+    _level: i32,
+    _max_len: usize,
+) -> Option<Vec<u8>> {
+    None
+}
+
+#[cfg(feature = "zstd")]
+pub(crate) fn decode(parent_data: &[u8], delta_chunk: &[u8]) -> Option<Vec<u8>> {
+    let frame = &delta_chunk[8..];
+    let len = zstd_safe::get_frame_content_size(frame).ok()??;
+    if len > MAX_DECODED_LEN {
+        return None;
+    }
+    let mut data = Vec::with_capacity(len as usize);
+    zstd_safe::DCtx::create()
+        .decompress_using_dict(&mut data, frame, parent_data)
+        .ok()?;
+    Some(data)
+}
+
+#[cfg(not(feature = "zstd"))]
+pub(crate) fn decode(_parent_data: &[u8], _delta_chunk: &[u8]) -> Option<Vec<u8>> {
+    None
+}
+
+/// Length of the chunk `delta_chunk` decodes to, read from the frame header.
+#[cfg(feature = "zstd")]
+pub(crate) fn decoded_len(delta_chunk: &[u8]) -> Option<usize> {
+    let len = zstd_safe::get_frame_content_size(delta_chunk.get(8..)?).ok()??;
+    usize::try_from(len).ok()
+}
+
+#[cfg(not(feature = "zstd"))]
+pub(crate) fn decoded_len(_delta_chunk: &[u8]) -> Option<usize> {
+    None
+}
+
+#[cfg(all(test, feature = "zstd"))]
+mod test {
+    use crate::{compress_chunks, restore, SBCScrubber};
+
+    #[test]
+    fn test_zstd_fallback_for_shuffled_chunk() {
+        let blocks: Vec<Vec<u8>> = (0..8)
+            .map(|_| (0..1024).map(|_| rand::random::<u8>()).collect())
+            .collect();
+        let data = blocks.concat();
+        let shuffled_data: Vec<u8> = blocks.iter().rev().flatten().copied().collect();
+
+        let delta_code =
+            super::encode(shuffled_data.as_slice(), data.as_slice(), 3, data.len()).unwrap();
+        assert!(delta_code.len() < 1024);
+        assert!(super::encode(shuffled_data.as_slice(), data.as_slice(), 3, 16).is_none());
+        let delta_chunk = [&[0, 0, 0, 1][..], delta_code.as_slice()].concat();
+        assert!(super::is_zstd_delta(delta_chunk.as_slice()));
+        assert_eq!(
+            super::decode(data.as_slice(), delta_chunk.as_slice()),
+            Some(shuffled_data.clone())
+        );
+
+        let chunks = vec![data, shuffled_data];
+        let mut scrubber = SBCScrubber::new().with_zstd_fallback(3);
+        let (map, manifest) = compress_chunks(chunks.clone(), &mut scrubber).unwrap();
+        let restored: Vec<Vec<u8>> = restore(&manifest, &map).map(Result::unwrap).collect();
+        assert_eq!(restored, chunks);
+    }
+}
@@ -0,0 +1,124 @@
+#![cfg(feature = "datasets")]
+
+extern crate sbc_algorithm;
+use sbc_algorithm::{run_experiment, GearChunker, ReportFormat, SBCScrubber};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Consecutive releases of the same tree, so most of the second one is similar
+/// to the first.
+const DATASETS: [(&str, &[&str]); 1] = [(
+    "linux",
+    &[
+        "https://cdn.kernel.org/pub/linux/kernel/v6.x/linux-6.1.1.tar.xz",
+        "https://cdn.kernel.org/pub/linux/kernel/v6.x/linux-6.1.2.tar.xz",
+    ],
+)];
+/// Bytes replayed from every file, `SBC_DATASET_MAX_BYTES` overrides it.
+const DEFAULT_MAX_BYTES: usize = 64 << 20;
+
+/// Directory of downloaded files, `SBC_DATASETS_DIR` or `target/datasets`.
+fn datasets_dir() -> PathBuf {
+    std::env::var_os("SBC_DATASETS_DIR").map_or_else(
+        || Path::new(env!("CARGO_MANIFEST_DIR")).join("target/datasets"),
+        PathBuf::from,
+    )
+}
+
+fn max_bytes() -> usize {
+    std::env::var("SBC_DATASET_MAX_BYTES")
+        .ok()
+        .and_then(|bytes| bytes.parse().ok())
+        .unwrap_or(DEFAULT_MAX_BYTES)
+}
+
+/// Downloads `url` with curl unless it was downloaded before, and returns its
+/// first `max_bytes` bytes, unpacked with xz for `.xz` files. Returns `None`
+/// when curl or xz is not installed or the download fails.
+fn load(url: &str, max_bytes: usize) -> Option<Vec<u8>> {
+    let dir = datasets_dir();
+    fs::create_dir_all(&dir).ok()?;
+    let path = dir.join(url.rsplit('/').next()?);
+    if !path.exists() {
+        let partial = path.with_extension("partial");
+        let status = Command::new("curl")
+            .args(["-fsSL", "-o"])
+            .arg(&partial)
+            .arg(url)
+            .status()
+            .ok()?;
+        if !status.success() {
+            eprintln!("failed to download {url}");
+            return None;
+        }
+        fs::rename(&partial, &path).ok()?;
+    }
+    let mut data = match path.extension() {
+        Some(extension) if extension == "xz" => {
+            let mut child = Command::new("xz")
+                .arg("-dc")
+                .arg(&path)
+                .stdout(std::process::Stdio::piped())
+                .spawn()
+                .ok()?;
+            let mut data = Vec::new();
+            io::Read::read_to_end(
+                &mut io::Read::take(child.stdout.take()?, max_bytes as u64),
+                &mut data,
+            )
+            .ok()?;
+            let _ = child.kill();
+            let _ = child.wait();
+            data
+        }
+        _ => fs::read(&path).ok()?,
+    };
+    data.truncate(max_bytes);
+    Some(data)
+}
+
+/// Replays every dataset through chunking and a full scrub and prints one CSV
+/// report per dataset. Local files, e.g. VM image snippets, can be added as
+/// one more dataset with `SBC_DATASET_FILES`, a list of paths separated by `:`.
+#[test]
+fn test_replay_datasets() {
+    let max_bytes = max_bytes();
+    let mut datasets: Vec<(String, Vec<Option<Vec<u8>>>)> = DATASETS
+        .iter()
+        .map(|(name, urls)| {
+            let files = urls.iter().map(|url| load(url, max_bytes)).collect();
+            (name.to_string(), files)
+        })
+        .collect();
+    if let Some(paths) = std::env::var_os("SBC_DATASET_FILES") {
+        let files = std::env::split_paths(&paths)
+            .map(|path| {
+                let mut data = fs::read(path).ok()?;
+                data.truncate(max_bytes);
+                Some(data)
+            })
+            .collect();
+        datasets.push(("local".to_string(), files));
+    }
+
+    let chunker = GearChunker::default();
+    for (name, files) in datasets {
+        let Some(files) = files.into_iter().collect::<Option<Vec<Vec<u8>>>>() else {
+            eprintln!("{name} is not available, skipped");
+            continue;
+        };
+        let chunks: Vec<Vec<u8>> = files
+            .iter()
+            .flat_map(|file| chunker.chunks(file))
+            .map(|chunk| chunk.data.to_vec())
+            .collect();
+        let report = run_experiment(chunks, &mut SBCScrubber::balanced()).unwrap();
+        println!("{name}:");
+        report
+            .write_to(&mut io::stdout().lock(), ReportFormat::Csv)
+            .unwrap();
+        assert!(report.stored_bytes > 0, "{name}: nothing was stored");
+    }
+}
@@ -0,0 +1,102 @@
+#![cfg(feature = "differential-tests")]
+
+extern crate sbc_algorithm;
+use sbc_algorithm::{decode_delta, encode_delta};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("sbc_differential_{}_{}", std::process::id(), name))
+}
+
+/// Runs `program` with `args` and returns the size of the produced `output`,
+/// or `None` when the program is not installed.
+fn external_delta_len(program: &str, args: &[&Path], output: &Path) -> Option<usize> {
+    let status = Command::new(program).args(args).status().ok()?;
+    assert!(status.success(), "{program} failed");
+    Some(fs::metadata(output).unwrap().len() as usize)
+}
+
+fn chunk_pairs() -> Vec<(&'static str, Vec<u8>, Vec<u8>)> {
+    let parent: Vec<u8> = (0..8192).map(|_| rand::random::<u8>()).collect();
+    let mut replaced = parent.clone();
+    replaced[4000] = replaced[4000].wrapping_add(1);
+    let mut inserted = parent.clone();
+    inserted.splice(2000..2000, [1, 2, 3, 4, 5, 6, 7, 8]);
+    let mut deleted = parent.clone();
+    deleted.drain(6000..6016);
+    vec![
+        ("replaced", parent.clone(), replaced),
+        ("inserted", parent.clone(), inserted),
+        ("deleted", parent, deleted),
+    ]
+}
+
+#[test]
+fn test_delta_sizes_against_external_encoders() {
+    for (name, parent, data) in chunk_pairs() {
+        let delta_chunk = encode_delta(data.as_slice(), parent.as_slice(), 0).unwrap();
+        assert_eq!(
+            decode_delta(parent.as_slice(), delta_chunk.as_slice()).unwrap(),
+            data,
+            "{name}: wrong restored data"
+        );
+        let delta_len = delta_chunk.len();
+
+        let (parent_path, data_path) = (temp_path("parent"), temp_path("data"));
+        fs::write(&parent_path, &parent).unwrap();
+        fs::write(&data_path, &data).unwrap();
+        let xdelta_path = temp_path("xdelta");
+        let zstd_path = temp_path("zstd");
+        let external = [
+            (
+                "xdelta3",
+                external_delta_len(
+                    "xdelta3",
+                    &[
+                        Path::new("-e"),
+                        Path::new("-f"),
+                        Path::new("-s"),
+                        &parent_path,
+                        &data_path,
+                        &xdelta_path,
+                    ],
+                    &xdelta_path,
+                ),
+            ),
+            (
+                "zstd",
+                external_delta_len(
+                    "zstd",
+                    &[
+                        Path::new("-q"),
+                        Path::new("-f"),
+                        Path::new("-19"),
+                        Path::new("--patch-from"),
+                        &parent_path,
+                        &data_path,
+                        Path::new("-o"),
+                        &zstd_path,
+                    ],
+                    &zstd_path,
+                ),
+            ),
+        ];
+        for (program, external_len) in external {
+            match external_len {
+                None => eprintln!("{program} is not installed, skipped"),
+                Some(external_len) => {
+                    println!("{name}: sbc {delta_len} bytes, {program} {external_len} bytes");
+                    assert!(
+                        delta_len <= 4 * external_len + 64,
+                        "{name}: delta of {delta_len} bytes, {program} needs {external_len}"
+                    );
+                }
+            }
+        }
+        for path in [parent_path, data_path, xdelta_path, zstd_path] {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
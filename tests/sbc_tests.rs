@@ -5,7 +5,7 @@ mod test {
     use chunkfs::chunkers::SuperChunker;
     use chunkfs::hashers::Sha256Hasher;
     use chunkfs::FileSystem;
-    use sbc_algorithm::{SBCMap, SBCScrubber};
+    use sbc_algorithm::{GearChunker, GearFeatures, SBCMap, SBCScrubber};
     use std::collections::HashMap;
     #[test]
     fn test_data_recovery() {
@@ -29,6 +29,29 @@ mod test {
         assert_eq!(read, data);
     }
 
+    #[test]
+    fn test_gear_chunker_features() {
+        let features = GearFeatures::default();
+        let mut fs = FileSystem::new_with_scrubber(
+            HashMap::default(),
+            SBCMap::new(),
+            Box::new(SBCScrubber::new().with_gear_features(features.clone())),
+            Sha256Hasher::default(),
+        );
+        let chunker = GearChunker::default().with_features(features.clone());
+        let mut handle = fs.create_file("file".to_string(), chunker).unwrap();
+        let data = generate_data(8);
+        fs.write_to_file(&mut handle, &data).unwrap();
+        fs.close_file(handle).unwrap();
+        assert!(!features.is_empty());
+
+        let _res = fs.scrub().unwrap();
+
+        let mut handle = fs.open_file("file", GearChunker::default()).unwrap();
+        let read = fs.read_file_complete(&mut handle).unwrap();
+        assert_eq!(read, data);
+    }
+
     const MB: usize = 1024 * 1024;
 
     fn generate_data(mb_size: usize) -> Vec<u8> {